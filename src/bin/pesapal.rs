@@ -0,0 +1,621 @@
+//! `pesapal` command line tool
+//!
+//! One-off operations against the `PesaPal` API: checking credentials, an
+//! order status, issuing refunds and managing IPN URLs.
+//!
+//! The configuration is read from the `PESAPAL_*` variables of
+//! [`PesaPalConfig::from_env`], or from the TOML or JSON config file passed
+//! with `--config` / `PESAPAL_CONFIG`, in the format of the
+//! [`config`](pesapal::config) module:
+//!
+//! ```toml
+//! consumer_key = "..."
+//! consumer_secret = "..."
+//! environment = "sandbox"
+//! timeout_secs = 30
+//! currency = "KES"
+//! ```
+//!
+//! The `--consumer-key`, `--consumer-secret` and `--environment` flags take
+//! precedence over both. The order defaults of the configuration are used by
+//! `submit-order` when the matching flags aren't passed.
+//!
+//! Refunds and every call to the production environment ask for a
+//! confirmation, unless `--yes` is passed.
+
+use std::error::Error;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use pesapal::config::{CONSUMER_KEY_VAR, CONSUMER_SECRET_VAR, ENVIRONMENT_VAR};
+use pesapal::{
+    BillingAddress, Environment, NotificationType, PesaPal, PesaPalConfig, PesaPalResponse,
+    PhoneNumber, RedirectMode, Secret,
+};
+use serde_json::Value;
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "pesapal",
+    version,
+    about = "Command line client for the PesaPal API"
+)]
+struct Cli {
+    /// Consumer key provided by PesaPal
+    #[arg(
+        long,
+        env = "PESAPAL_CONSUMER_KEY",
+        global = true,
+        hide_env_values = true
+    )]
+    consumer_key: Option<String>,
+    /// Consumer secret provided by PesaPal
+    #[arg(
+        long,
+        env = "PESAPAL_CONSUMER_SECRET",
+        global = true,
+        hide_env_values = true
+    )]
+    consumer_secret: Option<String>,
+    /// Environment to call, `sandbox` or `production`
+    #[arg(long, env = "PESAPAL_ENVIRONMENT", global = true)]
+    environment: Option<String>,
+    /// TOML or JSON config file, see the `pesapal::config` module
+    #[arg(long, env = "PESAPAL_CONFIG", global = true)]
+    config: Option<PathBuf>,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Output::Table, global = true)]
+    output: Output,
+    /// Don't ask for confirmation before refunds and production calls
+    #[arg(short, long, global = true)]
+    yes: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    Json,
+    Table,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Requests an access token to check the credentials
+    Authenticate {
+        /// Prints the access token, which then ends up in the terminal or
+        /// CI logs
+        #[arg(long)]
+        show_token: bool,
+    },
+    /// Creates a payment request and prints the redirect URL
+    SubmitOrder {
+        /// Amount to charge
+        #[arg(long)]
+        amount: u64,
+        /// Currency to charge in, required unless a default currency is
+        /// configured
+        #[arg(long)]
+        currency: Option<String>,
+        /// Description of the order
+        #[arg(long)]
+        description: String,
+        /// URL the customer is redirected to after the payment, defaults to
+        /// the configured one
+        #[arg(long)]
+        callback_url: Option<String>,
+        /// URL the customer is redirected to if they cancel the payment
+        #[arg(long)]
+        cancellation_url: Option<String>,
+        /// Id of the registered IPN URL, defaults to the configured one
+        #[arg(long)]
+        notification_id: Option<String>,
+        /// Store / branch the payment is accredited to
+        #[arg(long)]
+        branch: Option<String>,
+        /// Customer's email address
+        #[arg(long, required_unless_present = "phone")]
+        email: Option<String>,
+        /// Customer's phone number, in its international form, e.g. +254712345678
+        #[arg(long)]
+        phone: Option<PhoneNumber>,
+        /// Customer's first name
+        #[arg(long)]
+        first_name: Option<String>,
+        /// Customer's last name
+        #[arg(long)]
+        last_name: Option<String>,
+        /// Where the callback URL is loaded
+        #[arg(long, value_enum, default_value_t = Redirect::TopWindow)]
+        redirect_mode: Redirect,
+    },
+    /// Prints the status of a transaction
+    TransactionStatus {
+        /// Order tracking id returned when the order was submitted
+        order_tracking_id: String,
+    },
+    /// Refunds a payment
+    Refund {
+        /// Confirmation code of the payment
+        #[arg(long)]
+        confirmation_code: String,
+        /// Amount to refund
+        #[arg(long)]
+        amount: f64,
+        /// User initiating the refund
+        #[arg(long)]
+        username: String,
+        /// Reason for the refund
+        #[arg(long)]
+        remarks: String,
+    },
+    /// Registers an IPN URL
+    RegisterIpnUrl {
+        /// URL PesaPal sends the notifications to
+        url: String,
+        /// HTTP method of the notifications, `GET` or `POST`
+        #[arg(long, default_value = "GET")]
+        notification_type: String,
+    },
+    /// Lists the registered IPN URLs
+    ListIpnUrls,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Redirect {
+    TopWindow,
+    ParentWindow,
+}
+
+impl From<Redirect> for RedirectMode {
+    fn from(value: Redirect) -> Self {
+        match value {
+            Redirect::TopWindow => Self::TopWindow,
+            Redirect::ParentWindow => Self::ParentWindow,
+        }
+    }
+}
+
+impl Cli {
+    /// Reads the configuration, the flags take precedence over the config
+    /// file and the variables
+    fn config(&self) -> CliResult<PesaPalConfig> {
+        let Some(path) = &self.config else {
+            return Ok(PesaPalConfig::from_vars(|name| {
+                self.flag(name).or_else(|| std::env::var(name).ok())
+            })?);
+        };
+
+        let mut config = PesaPalConfig::from_file(path)?;
+        if let Some(consumer_key) = &self.consumer_key {
+            config.consumer_key.clone_from(consumer_key);
+        }
+        if let Some(consumer_secret) = &self.consumer_secret {
+            config.consumer_secret = Secret::from(consumer_secret.as_str());
+        }
+        if let Some(environment) = &self.environment {
+            config.environment = Environment::try_from(environment.as_str())?;
+        }
+
+        Ok(config)
+    }
+
+    /// Value of the flag standing for the `PESAPAL_*` variable `name`
+    fn flag(&self, name: &str) -> Option<String> {
+        match name {
+            CONSUMER_KEY_VAR => self.consumer_key.clone(),
+            CONSUMER_SECRET_VAR => self.consumer_secret.clone(),
+            ENVIRONMENT_VAR => self.environment.clone(),
+            _ => None,
+        }
+    }
+
+    /// Asks the user to confirm the call, refunds and production calls
+    /// always need a confirmation
+    fn confirm(&self, environment: &Environment) -> CliResult<()> {
+        let mut reasons = Vec::new();
+        if *environment == Environment::Production {
+            reasons.push("This call goes to the PesaPal PRODUCTION environment.".to_string());
+        }
+        if let Command::Refund {
+            confirmation_code,
+            amount,
+            ..
+        } = &self.command
+        {
+            reasons.push(format!(
+                "This refunds {amount} of the payment {confirmation_code}."
+            ));
+        }
+        if reasons.is_empty() || self.yes {
+            return Ok(());
+        }
+
+        let stdin = std::io::stdin();
+        if !stdin.is_terminal() {
+            return Err(format!("{} Pass --yes to confirm.", reasons.join(" ")).into());
+        }
+
+        let mut stderr = std::io::stderr();
+        writeln!(stderr, "{}", reasons.join("\n"))?;
+        write!(stderr, "Continue? [y/N] ")?;
+        stderr.flush()?;
+
+        let mut answer = String::new();
+        stdin.lock().read_line(&mut answer)?;
+        if matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            return Ok(());
+        }
+        Err("aborted".into())
+    }
+}
+
+async fn run(cli: Cli) -> CliResult<Value> {
+    let config = cli.config()?;
+    let environment = config.environment.clone();
+    let has_default_currency = config.defaults.currency.is_some();
+    let pesapal = PesaPal::from_config(config)?;
+    cli.confirm(&environment)?;
+
+    let response = match cli.command {
+        Command::Authenticate { show_token } => {
+            let token = pesapal.authenticate().await?;
+            let mut response = serde_json::json!({
+                "environment": environment.as_str(),
+                "authenticated": true,
+            });
+            if show_token {
+                response["token"] = token.expose_secret().into();
+            }
+            return Ok(response);
+        }
+        Command::SubmitOrder {
+            amount,
+            currency,
+            description,
+            callback_url,
+            cancellation_url,
+            notification_id,
+            branch,
+            email,
+            phone,
+            first_name,
+            last_name,
+            redirect_mode,
+        } => {
+            let mut order = pesapal.submit_order();
+            order
+                .amount(amount)
+                .description(description)
+                .redirect_mode(redirect_mode.into())
+                .billing_address(BillingAddress {
+                    email_address: email,
+                    phone_number: phone,
+                    first_name,
+                    last_name,
+                    ..Default::default()
+                });
+            match currency {
+                Some(currency) => {
+                    order.currency(currency);
+                }
+                None if !has_default_currency => {
+                    return Err("--currency is required, no default currency is configured".into())
+                }
+                None => {}
+            }
+            if let Some(callback_url) = callback_url {
+                order.callback_url(callback_url);
+            }
+            if let Some(notification_id) = notification_id {
+                order.notification_id(notification_id);
+            }
+            if let Some(cancellation_url) = cancellation_url {
+                order.cancellation_url(cancellation_url);
+            }
+            if let Some(branch) = branch {
+                order.branch(branch);
+            }
+            raw(order.build()?.send_with_meta().await?)
+        }
+        Command::TransactionStatus { order_tracking_id } => raw(pesapal
+            .transaction_status()
+            .order_tracking_id(order_tracking_id)
+            .build()?
+            .send_with_meta()
+            .await?),
+        Command::Refund {
+            confirmation_code,
+            amount,
+            username,
+            remarks,
+        } => raw(pesapal
+            .refund()
+            .confirmation_code(confirmation_code)
+            .amount(amount)
+            .username(username)
+            .remarks(remarks)
+            .build()?
+            .send_with_meta()
+            .await?),
+        Command::RegisterIpnUrl {
+            url,
+            notification_type,
+        } => raw(pesapal
+            .register_ipn_url()
+            .url(url)
+            .ipn_notification_type(NotificationType::try_from(notification_type.as_str())?)
+            .build()?
+            .send_with_meta()
+            .await?),
+        Command::ListIpnUrls => raw(pesapal.list_ipn_urls().send_with_meta().await?),
+    };
+
+    Ok(response?)
+}
+
+/// Body of the response exactly as returned by `PesaPal`
+fn raw<T>(response: PesaPalResponse<T>) -> serde_json::Result<Value> {
+    serde_json::from_str(&response.raw_body)
+}
+
+/// Renders the value as a table
+///
+/// Objects are rendered as `field | value` rows, with the nested fields
+/// flattened, and arrays of objects as one row per item.
+fn table(value: &Value) -> String {
+    let (header, rows) = match value {
+        Value::Array(items) => {
+            let items: Vec<Vec<(String, String)>> = items.iter().map(flatten).collect();
+            let mut header: Vec<String> = Vec::new();
+            for (field, _) in items.iter().flatten() {
+                if !header.contains(field) {
+                    header.push(field.clone());
+                }
+            }
+            let rows: Vec<Vec<String>> = items
+                .iter()
+                .map(|item| {
+                    header
+                        .iter()
+                        .map(|field| {
+                            item.iter()
+                                .find(|(name, _)| name == field)
+                                .map(|(_, value)| value.clone())
+                                .unwrap_or_default()
+                        })
+                        .collect()
+                })
+                .collect();
+            (header, rows)
+        }
+        value => (
+            vec!["field".to_string(), "value".to_string()],
+            flatten(value)
+                .into_iter()
+                .map(|(field, value)| vec![field, value])
+                .collect(),
+        ),
+    };
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .chain(std::iter::once(header[column].chars().count()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let line = |cells: &[String]| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join(" | ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut output = vec![
+        line(&header),
+        widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect::<Vec<_>>()
+            .join("-+-"),
+    ];
+    output.extend(rows.iter().map(|row| line(row)));
+    output.join("\n")
+}
+
+/// Flattens the value into `field`, `value` pairs, nested fields are joined
+/// with a `.`
+fn flatten(value: &Value) -> Vec<(String, String)> {
+    fn walk(prefix: &str, value: &Value, fields: &mut Vec<(String, String)>) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    let field = if prefix.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{prefix}.{key}")
+                    };
+                    walk(&field, value, fields);
+                }
+            }
+            Value::Null => fields.push((prefix.to_string(), String::new())),
+            Value::String(value) => fields.push((prefix.to_string(), value.clone())),
+            value => fields.push((prefix.to_string(), value.to_string())),
+        }
+    }
+
+    let mut fields = Vec::new();
+    walk("", value, &mut fields);
+    fields
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let output = cli.output;
+
+    let result = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(Into::into)
+        .and_then(|runtime| runtime.block_on(run(cli)));
+
+    match result {
+        Ok(value) => match output {
+            Output::Json => println!("{value:#}"),
+            Output::Table => println!("{}", table(&value)),
+        },
+        Err(error) => {
+            eprintln!("error: {error}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_of_object() {
+        let value = serde_json::json!({
+            "status": "200",
+            "error": {"code": null, "message": "ok"},
+        });
+
+        assert_eq!(
+            table(&value),
+            "field         | value\n--------------+------\nerror.code    |\nerror.message | ok\nstatus        | 200"
+        );
+    }
+
+    #[test]
+    fn test_table_of_array() {
+        let value = serde_json::json!([
+            {"url": "https://example.com/ipn", "ipn_id": "1"},
+            {"url": "https://example.com/other", "ipn_id": "22", "status": 200},
+        ]);
+
+        assert_eq!(
+            table(&value),
+            "ipn_id | url                       | status\n-------+---------------------------+-------\n1      | https://example.com/ipn   |\n22     | https://example.com/other | 200"
+        );
+    }
+
+    /// Serializes the tests changing the environment
+    static ENV: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    /// Removes the `PESAPAL_*` variables of the environment running the tests,
+    /// the environment is left to the caller until the guard is dropped
+    fn clear_pesapal_env() -> std::sync::MutexGuard<'static, ()> {
+        let guard = ENV
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        for (name, _) in std::env::vars_os() {
+            if name.to_string_lossy().starts_with("PESAPAL_") {
+                std::env::remove_var(name);
+            }
+        }
+        guard
+    }
+
+    #[test]
+    fn test_config_file_is_a_pesapal_config() {
+        let _env = clear_pesapal_env();
+        let path = std::env::temp_dir().join(format!("pesapal-cli-{}.toml", ulid::Ulid::new()));
+        std::fs::write(
+            &path,
+            "consumer_key = \"key\"\nconsumer_secret = \"secret\"\nenvironment = \"production\"\ntimeout_secs = 30\ncurrency = \"ugx\"\nnotification_id = \"ipn\"\n",
+        )
+        .unwrap();
+
+        let cli = Cli::try_parse_from([
+            "pesapal",
+            "--config",
+            path.to_str().unwrap(),
+            "--environment",
+            "sandbox",
+            "list-ipn-urls",
+        ])
+        .unwrap();
+        let config = cli.config();
+        std::fs::remove_file(path).unwrap();
+        let config = config.unwrap();
+
+        assert_eq!(config.consumer_key, "key");
+        assert_eq!(config.environment, Environment::Sandbox);
+        assert_eq!(config.timeout, Some(std::time::Duration::from_secs(30)));
+        assert_eq!(config.defaults.currency.as_deref(), Some("UGX"));
+        assert!(cli.confirm(&config.environment).is_ok());
+    }
+
+    #[test]
+    fn test_flags_and_variables_without_config_file() {
+        let _env = clear_pesapal_env();
+        std::env::set_var("PESAPAL_TIMEOUT_SECS", "15");
+        std::env::set_var("PESAPAL_CALLBACK_URL", "https://example.com/callback");
+
+        let cli = Cli::try_parse_from([
+            "pesapal",
+            "--consumer-key",
+            "key",
+            "--consumer-secret",
+            "secret",
+            "list-ipn-urls",
+        ])
+        .unwrap();
+        let config = cli.config();
+        std::env::remove_var("PESAPAL_TIMEOUT_SECS");
+        std::env::remove_var("PESAPAL_CALLBACK_URL");
+        let config = config.unwrap();
+
+        assert_eq!(config.consumer_key, "key");
+        assert_eq!(config.environment, Environment::Sandbox);
+        assert_eq!(config.timeout, Some(std::time::Duration::from_secs(15)));
+        assert_eq!(
+            config.defaults.callback_url.as_deref(),
+            Some("https://example.com/callback")
+        );
+    }
+
+    #[test]
+    fn test_submit_order_requires_a_currency_without_default() {
+        let _env = clear_pesapal_env();
+        let cli = Cli::try_parse_from([
+            "pesapal",
+            "--consumer-key",
+            "key",
+            "--consumer-secret",
+            "secret",
+            "submit-order",
+            "--amount",
+            "100",
+            "--description",
+            "order",
+            "--callback-url",
+            "https://example.com/callback",
+            "--notification-id",
+            "ipn",
+            "--email",
+            "john@doe.com",
+        ])
+        .unwrap();
+
+        let error = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(run(cli))
+            .unwrap_err();
+        assert!(error.to_string().contains("--currency"), "{error}");
+    }
+}
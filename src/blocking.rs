@@ -0,0 +1,609 @@
+//! Blocking `PesaPal` client
+//!
+//! Mirrors the API of the async [`PesaPal`](crate::PesaPal) client for
+//! synchronous code such as batch jobs and CLI tools. The request and response
+//! types are shared with the async client, only `send` blocks the current
+//! thread until the response is received.
+//!
+//! The client drives the requests on its own single threaded runtime, hence it
+//! must not be used from within an async context: the requests sent from one
+//! fail with a [`PesaPalError::Internal`](crate::PesaPalError::Internal)
+//! instead of being sent.
+//!
+//! ```rust,no_run
+//! # #[cfg(not(feature = "reqwest"))]
+//! # fn main() {}
+//! # #[cfg(feature = "reqwest")]
+//! # fn main() {
+//! use pesapal::blocking::PesaPal;
+//! use pesapal::{BillingAddress, Environment};
+//!
+//! let pesapal = PesaPal::new("consumer_key", "consumer_secret", Environment::Sandbox);
+//!
+//! let response = pesapal
+//!     .submit_order()
+//!     .currency("KES")
+//!     .amount(2500)
+//!     .description("Shopping")
+//!     .callback_url("https://example.com")
+//!     .notification_id("example")
+//!     .billing_address(BillingAddress {
+//!         email_address: Some("john@doe.com".to_string()),
+//!         ..Default::default()
+//!     })
+//!     .build()
+//!     .unwrap()
+//!     .send()
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::future::Future;
+use std::sync::Arc;
+
+use tokio::runtime::{Handle, Runtime};
+
+use crate::pesapal::refund::RefundBuilderError;
+use crate::pesapal::register_ipn::RegisterIPNBuilderError;
+use crate::pesapal::transaction_status::TransactionStatusBuilderError;
+use crate::transport::HttpTransport;
+use crate::{
+    AccessToken, BillingAddress, EnsuredIpn, Environment, IPNListResponse, NotificationType,
+    PesaPalError, PesaPalResponse, PesaPalResult, RedirectMode, RefundResponse, RefundStatus,
+    RegisterIPNResponse, SubmitOrderResponse, TransactionStatusResponse,
+};
+
+/// Forwards builder setters to the wrapped async builder
+macro_rules! forward_setters {
+    ($($(#[$doc:meta])* $name:ident: $ty:ty),* $(,)?) => {
+        $(
+            $(#[$doc])*
+            pub fn $name(&mut self, value: $ty) -> &mut Self {
+                self.inner.$name(value);
+                self
+            }
+        )*
+    };
+}
+
+/// Runs the request on the runtime of the client, unless the calling thread
+/// already runs an async runtime, in which `block_on` would panic
+fn block_on<T>(
+    runtime: &Runtime,
+    request: impl Future<Output = PesaPalResult<T>>,
+) -> PesaPalResult<T> {
+    if Handle::try_current().is_ok() {
+        return Err(PesaPalError::Internal(
+            "the blocking client can't be used from within an async runtime, use the async client"
+                .to_string(),
+        ));
+    }
+    runtime.block_on(request)
+}
+
+/// [`PesaPal`] Blocking client which allows communication with the `PesaPal`
+/// services
+#[derive(Debug, Clone)]
+pub struct PesaPal {
+    inner: crate::PesaPal,
+    runtime: Arc<Runtime>,
+}
+
+impl PesaPal {
+    /// This function construct a new blocking `PesaPal` Instance
+    ///
+    /// # Panics
+    /// Panics if the underlying runtime cannot be created
+    #[cfg(feature = "reqwest")]
+    pub fn new<S: Into<String>>(consumer_key: S, consumer_secret: S, env: Environment) -> Self {
+        Self::from_async(crate::PesaPal::new(consumer_key, consumer_secret, env))
+    }
+
+    /// Constructs a new blocking `PesaPal` Instance which sends the requests
+    /// through the given [`HttpTransport`]
+    ///
+    /// # Panics
+    /// Panics if the underlying runtime cannot be created
+    pub fn new_with_transport<S: Into<String>>(
+        consumer_key: S,
+        consumer_secret: S,
+        env: Environment,
+        transport: impl HttpTransport + 'static,
+    ) -> Self {
+        Self::from_async(crate::PesaPal::new_with_transport(
+            consumer_key,
+            consumer_secret,
+            env,
+            transport,
+        ))
+    }
+
+    /// Wraps an existing async [`PesaPal`](crate::PesaPal) client
+    ///
+    /// # Panics
+    /// Panics if the underlying runtime cannot be created
+    pub fn from_async(inner: crate::PesaPal) -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Error building blocking runtime");
+
+        Self {
+            inner,
+            runtime: Arc::new(runtime),
+        }
+    }
+
+    /// Blocking version of [`PesaPal::authenticate`](crate::PesaPal::authenticate)
+    ///
+    /// # Errors
+    /// [`PesaPalError::AuthenticationError`](crate::PesaPalError::AuthenticationError) - Incase the authentication fails
+    pub fn authenticate(&self) -> PesaPalResult<AccessToken> {
+        block_on(&self.runtime, self.inner.authenticate())
+    }
+
+    /// Creates a [`SubmitOrderBuilder`] for creating a new payment request
+    #[must_use]
+    pub fn submit_order(&self) -> SubmitOrderBuilder {
+        SubmitOrderBuilder {
+            inner: self.inner.submit_order(),
+            runtime: Arc::clone(&self.runtime),
+        }
+    }
+
+    /// Creates a [`RefundBuilder`] for creating a new refund request
+    #[must_use]
+    pub fn refund(&self) -> RefundBuilder {
+        RefundBuilder {
+            inner: self.inner.refund(),
+            runtime: Arc::clone(&self.runtime),
+        }
+    }
+
+    /// Creates a [`RefundTracker`] following the refund of a payment
+    #[must_use]
+    pub fn track_refund(&self, order_tracking_id: impl Into<String>) -> RefundTracker {
+        RefundTracker {
+            inner: self.inner.track_refund(order_tracking_id),
+            runtime: Arc::clone(&self.runtime),
+        }
+    }
+
+    /// Creates a [`RegisterIPNBuilder`] for registering an IPN URL
+    #[must_use]
+    pub fn register_ipn_url(&self) -> RegisterIPNBuilder {
+        RegisterIPNBuilder {
+            inner: self.inner.register_ipn_url(),
+            runtime: Arc::clone(&self.runtime),
+        }
+    }
+
+    /// Creates a [`ListIPN`] for listing the IPN URLs registered for the
+    /// merchant
+    #[must_use]
+    pub fn list_ipn_urls(&self) -> ListIPN {
+        ListIPN {
+            inner: self.inner.list_ipn_urls(),
+            runtime: Arc::clone(&self.runtime),
+        }
+    }
+
+    /// Blocking version of [`PesaPal::ensure_ipn`](crate::PesaPal::ensure_ipn)
+    ///
+    /// # Errors
+    /// [`PesaPalError::RegisterIPNError`](crate::PesaPalError::RegisterIPNError) - Incase the registration fails
+    pub fn ensure_ipn(
+        &self,
+        url: impl AsRef<str>,
+        notification_type: NotificationType,
+    ) -> PesaPalResult<EnsuredIpn> {
+        block_on(&self.runtime, self.inner.ensure_ipn(url, notification_type))
+    }
+
+    /// Creates a [`TransactionStatusBuilder`] for checking the status of a
+    /// transaction
+    #[must_use]
+    pub fn transaction_status(&self) -> TransactionStatusBuilder {
+        TransactionStatusBuilder {
+            inner: self.inner.transaction_status(),
+            runtime: Arc::clone(&self.runtime),
+        }
+    }
+}
+
+/// Blocking builder for [`SubmitOrder`]
+pub struct SubmitOrderBuilder {
+    inner: crate::pesapal::submit_order::SubmitOrderBuilder,
+    runtime: Arc<Runtime>,
+}
+
+impl SubmitOrderBuilder {
+    forward_setters! {
+        /// Unique merchant reference of the order, generated if not set
+        merchant_reference: impl Into<String>,
+        /// Currency which is used to charge the customers
+        currency: impl Into<String>,
+        /// Amount to be processed
+        amount: u64,
+        /// Description of the order
+        description: impl Into<String>,
+        /// Where the callback URL will be loaded
+        redirect_mode: RedirectMode,
+        /// URL which PesaPal will re-direct for the payment processing
+        callback_url: impl Into<String>,
+        /// A valid URL which PesaPal will redirect client incase they cancel the payment
+        cancellation_url: impl Into<String>,
+        /// The IPN id which Pesapal will send notifications to
+        notification_id: impl Into<String>,
+        /// Store / branch to which this payment will be accredited to
+        branch: impl Into<String>,
+        /// The billing address of the customer
+        billing_address: BillingAddress,
+    }
+
+    /// Builds a new [`SubmitOrder`]
+    ///
+    /// # Errors
+    /// Every missing or invalid field, in a [`ValidationErrors`](crate::ValidationErrors)
+    pub fn build(&self) -> Result<SubmitOrder, crate::ValidationErrors> {
+        Ok(SubmitOrder {
+            inner: self.inner.build()?,
+            runtime: Arc::clone(&self.runtime),
+        })
+    }
+}
+
+/// Blocking Submit Order request
+pub struct SubmitOrder {
+    inner: crate::SubmitOrder,
+    runtime: Arc<Runtime>,
+}
+
+impl SubmitOrder {
+    /// Blocking version of [`SubmitOrder::send`](crate::SubmitOrder::send)
+    ///
+    /// # Errors
+    /// [`PesaPalError::SubmitOrderError`](crate::PesaPalError::SubmitOrderError) - Incase the payment fails
+    pub fn send(self) -> PesaPalResult<SubmitOrderResponse> {
+        block_on(&self.runtime, self.inner.send())
+    }
+
+    /// Blocking version of [`SubmitOrder::send_with_meta`](crate::SubmitOrder::send_with_meta)
+    ///
+    /// # Errors
+    /// [`PesaPalError::SubmitOrderError`](crate::PesaPalError::SubmitOrderError) - Incase the payment fails
+    pub fn send_with_meta(self) -> PesaPalResult<PesaPalResponse<SubmitOrderResponse>> {
+        block_on(&self.runtime, self.inner.send_with_meta())
+    }
+}
+
+/// Blocking builder for [`Refund`]
+pub struct RefundBuilder {
+    inner: crate::pesapal::refund::RefundBuilder,
+    runtime: Arc<Runtime>,
+}
+
+impl RefundBuilder {
+    forward_setters! {
+        /// Payment confirmation code that was returned by the payment processor
+        confirmation_code: impl Into<String>,
+        /// Amount to be refunded.
+        amount: impl Into<f64>,
+        /// Identity of the user who has initiated the refund.
+        username: impl Into<String>,
+        /// A brief description on the reason for the refund.
+        remarks: impl Into<String>,
+        /// Checks the refund against the original transaction before sending it.
+        preflight: impl Into<crate::RefundPreflight>,
+    }
+
+    /// Builds a new [`Refund`]
+    ///
+    /// # Errors
+    /// If a required field has not been initialized
+    pub fn build(&self) -> Result<Refund, RefundBuilderError> {
+        Ok(Refund {
+            inner: self.inner.build()?,
+            runtime: Arc::clone(&self.runtime),
+        })
+    }
+}
+
+/// Blocking Refund request
+pub struct Refund {
+    inner: crate::Refund,
+    runtime: Arc<Runtime>,
+}
+
+impl Refund {
+    /// Blocking version of [`Refund::send`](crate::Refund::send)
+    ///
+    /// # Errors
+    /// [`PesaPalError::RefundError`](crate::PesaPalError::RefundError) - Incase the refund is rejected
+    pub fn send(self) -> PesaPalResult<RefundResponse> {
+        block_on(&self.runtime, self.inner.send())
+    }
+
+    /// Blocking version of [`Refund::send_with_meta`](crate::Refund::send_with_meta)
+    ///
+    /// # Errors
+    /// [`PesaPalError::RefundError`](crate::PesaPalError::RefundError) - Incase the refund is rejected
+    pub fn send_with_meta(self) -> PesaPalResult<PesaPalResponse<RefundResponse>> {
+        block_on(&self.runtime, self.inner.send_with_meta())
+    }
+}
+
+/// Blocking version of [`RefundTracker`](crate::RefundTracker)
+pub struct RefundTracker {
+    inner: crate::RefundTracker,
+    runtime: Arc<Runtime>,
+}
+
+impl RefundTracker {
+    /// Time between two status checks of [`RefundTracker::wait`]
+    ///
+    /// # Panics
+    /// Panics if `interval` is zero
+    #[must_use]
+    pub fn poll_interval(mut self, interval: std::time::Duration) -> Self {
+        self.inner = self.inner.poll_interval(interval);
+        self
+    }
+
+    /// Time after which [`RefundTracker::wait`] gives up
+    #[must_use]
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.inner = self.inner.timeout(timeout);
+        self
+    }
+
+    /// Blocking version of [`RefundTracker::status`](crate::RefundTracker::status)
+    ///
+    /// # Errors
+    /// [`PesaPalError::TransactionStatusError`](crate::PesaPalError::TransactionStatusError) - Incase the status lookup fails
+    pub fn status(&self) -> PesaPalResult<RefundStatus> {
+        block_on(&self.runtime, self.inner.status())
+    }
+
+    /// Blocking version of [`RefundTracker::wait`](crate::RefundTracker::wait)
+    ///
+    /// # Errors
+    /// [`PesaPalError::TransactionStatusError`](crate::PesaPalError::TransactionStatusError) - Incase a status lookup fails
+    pub fn wait(&self) -> PesaPalResult<RefundStatus> {
+        block_on(&self.runtime, self.inner.wait())
+    }
+}
+
+/// Blocking builder for [`RegisterIPN`]
+pub struct RegisterIPNBuilder {
+    inner: crate::pesapal::register_ipn::RegisterIPNBuilder,
+    runtime: Arc<Runtime>,
+}
+
+impl RegisterIPNBuilder {
+    forward_setters! {
+        /// The notification URL pesapal will send status alert to
+        url: impl Into<String>,
+        /// The http request method Pesapal will use when triggering the IPN alert
+        ipn_notification_type: NotificationType,
+    }
+
+    /// Tries to set the http request method Pesapal will use when triggering
+    /// the IPN alert
+    ///
+    /// # Errors
+    /// If the value can't be converted to a [`NotificationType`]
+    pub fn try_ipn_notification_type<V: TryInto<NotificationType>>(
+        &mut self,
+        value: V,
+    ) -> Result<&mut Self, V::Error> {
+        self.inner.try_ipn_notification_type(value)?;
+        Ok(self)
+    }
+
+    /// Builds a new [`RegisterIPN`]
+    ///
+    /// # Errors
+    /// If a required field has not been initialized
+    pub fn build(&self) -> Result<RegisterIPN, RegisterIPNBuilderError> {
+        Ok(RegisterIPN {
+            inner: self.inner.build()?,
+            runtime: Arc::clone(&self.runtime),
+        })
+    }
+}
+
+/// Blocking Register IPN URL request
+pub struct RegisterIPN {
+    inner: crate::RegisterIPN,
+    runtime: Arc<Runtime>,
+}
+
+impl RegisterIPN {
+    /// Blocking version of [`RegisterIPN::send`](crate::RegisterIPN::send)
+    ///
+    /// # Errors
+    /// [`PesaPalError::RegisterIPNError`](crate::PesaPalError::RegisterIPNError) - Incase the registration fails
+    pub fn send(self) -> PesaPalResult<RegisterIPNResponse> {
+        block_on(&self.runtime, self.inner.send())
+    }
+
+    /// Blocking version of [`RegisterIPN::send_with_meta`](crate::RegisterIPN::send_with_meta)
+    ///
+    /// # Errors
+    /// [`PesaPalError::RegisterIPNError`](crate::PesaPalError::RegisterIPNError) - Incase the registration fails
+    pub fn send_with_meta(self) -> PesaPalResult<PesaPalResponse<RegisterIPNResponse>> {
+        block_on(&self.runtime, self.inner.send_with_meta())
+    }
+}
+
+/// Blocking List IPN URLs request
+pub struct ListIPN {
+    inner: crate::ListIPN,
+    runtime: Arc<Runtime>,
+}
+
+impl ListIPN {
+    /// Blocking version of [`ListIPN::send`](crate::ListIPN::send)
+    ///
+    /// # Errors
+    /// Incase the request fails
+    pub fn send(&self) -> PesaPalResult<IPNListResponse> {
+        block_on(&self.runtime, self.inner.send())
+    }
+
+    /// Blocking version of [`ListIPN::send_with_meta`](crate::ListIPN::send_with_meta)
+    ///
+    /// # Errors
+    /// Incase the request fails
+    pub fn send_with_meta(&self) -> PesaPalResult<PesaPalResponse<IPNListResponse>> {
+        block_on(&self.runtime, self.inner.send_with_meta())
+    }
+}
+
+/// Blocking builder for [`TransactionStatus`]
+pub struct TransactionStatusBuilder {
+    inner: crate::TransactionStatusBuilder,
+    runtime: Arc<Runtime>,
+}
+
+impl TransactionStatusBuilder {
+    forward_setters! {
+        /// Unique order id generated by Pesapal
+        order_tracking_id: impl Into<String>,
+    }
+
+    /// Builds a new [`TransactionStatus`]
+    ///
+    /// # Errors
+    /// If a required field has not been initialized
+    pub fn build(&self) -> Result<TransactionStatus, TransactionStatusBuilderError> {
+        Ok(TransactionStatus {
+            inner: self.inner.build()?,
+            runtime: Arc::clone(&self.runtime),
+        })
+    }
+}
+
+/// Blocking Transaction Status request
+pub struct TransactionStatus {
+    inner: crate::TransactionStatus,
+    runtime: Arc<Runtime>,
+}
+
+impl TransactionStatus {
+    /// Blocking version of [`TransactionStatus::send`](crate::TransactionStatus::send)
+    ///
+    /// # Errors
+    /// [`PesaPalError::TransactionStatusError`](crate::PesaPalError::TransactionStatusError) - Incase the request fails
+    pub fn send(&self) -> PesaPalResult<TransactionStatusResponse> {
+        block_on(&self.runtime, self.inner.send())
+    }
+
+    /// Blocking version of [`TransactionStatus::send_with_meta`](crate::TransactionStatus::send_with_meta)
+    ///
+    /// # Errors
+    /// [`PesaPalError::TransactionStatusError`](crate::PesaPalError::TransactionStatusError) - Incase the request fails
+    pub fn send_with_meta(&self) -> PesaPalResult<PesaPalResponse<TransactionStatusResponse>> {
+        block_on(&self.runtime, self.inner.send_with_meta())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn test_blocking_builders_mirror_async_validation() {
+        let pesapal = PesaPal::new_with_transport(
+            "key",
+            "secret",
+            Environment::Sandbox,
+            MockTransport::new(),
+        );
+
+        let order = pesapal
+            .submit_order()
+            .currency("KES")
+            .amount(2500)
+            .description("Shopping")
+            .callback_url("https://example.com")
+            .notification_id("example")
+            .billing_address(BillingAddress::default())
+            .build();
+        assert!(order.is_err());
+
+        let status = pesapal
+            .transaction_status()
+            .order_tracking_id("tracking-id")
+            .build();
+        assert!(status.is_ok());
+
+        let mut ipn = pesapal.register_ipn_url();
+        assert!(ipn.try_ipn_notification_type("PATCH").is_err());
+    }
+
+    const SUBMIT_ORDER_URL: &str = "api/Transactions/SubmitOrderRequest";
+
+    fn order(pesapal: &PesaPal) -> SubmitOrder {
+        pesapal
+            .submit_order()
+            .currency("KES")
+            .amount(2500)
+            .description("Shopping")
+            .callback_url("https://example.com")
+            .notification_id("example")
+            .billing_address(BillingAddress {
+                email_address: Some("john@doe.com".to_string()),
+                ..Default::default()
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_blocking_send() {
+        let transport = Arc::new(MockTransport::new().on(
+            SUBMIT_ORDER_URL,
+            200,
+            r#"{"order_tracking_id":"tracking","merchant_reference":"reference","redirect_url":"https://example.com","error":null,"status":"200"}"#,
+        ));
+        let pesapal = PesaPal::new_with_transport(
+            "blocking-send-key",
+            "secret",
+            Environment::Sandbox,
+            Arc::clone(&transport),
+        );
+
+        let response = order(&pesapal).send().unwrap();
+        assert_eq!(response.order_tracking_id, "tracking");
+
+        let response = order(&pesapal).send_with_meta().unwrap();
+        assert_eq!(response.body.merchant_reference, "reference");
+        assert_eq!(response.status, http::StatusCode::OK);
+        assert_eq!(transport.requests(SUBMIT_ORDER_URL).len(), 2);
+    }
+
+    #[test]
+    fn test_blocking_send_from_an_async_runtime_fails() {
+        let transport = Arc::new(MockTransport::new());
+        let pesapal = PesaPal::new_with_transport(
+            "blocking-async-key",
+            "secret",
+            Environment::Sandbox,
+            Arc::clone(&transport),
+        );
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let result = runtime.block_on(async { order(&pesapal).send() });
+
+        assert!(matches!(
+            result,
+            Err(PesaPalError::Internal(message)) if message.contains("async runtime")
+        ));
+        assert!(transport.requests(SUBMIT_ORDER_URL).is_empty());
+    }
+}
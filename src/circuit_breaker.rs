@@ -0,0 +1,579 @@
+//! Circuit breaker
+//!
+//! When `PesaPal` has an outage every call hangs until it times out. With a
+//! [`CircuitBreaker`] set through
+//! [`PesaPal::with_circuit_breaker`](crate::PesaPal::with_circuit_breaker) the
+//! client stops calling `PesaPal` once too many calls fail, and returns
+//! [`PesaPalError::CircuitOpen`] straight away. Once the circuit has been open
+//! for a while a few probe calls are let through (half-open), the circuit
+//! closes again if they succeed.
+//!
+//! Transport errors and `5xx` responses count as failures. Error responses
+//! such as a rejected refund don't, as they show `PesaPal` is up.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::transport::HttpResponse;
+use crate::{telemetry, PesaPalError, PesaPalResult};
+
+/// State of the circuit
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum CircuitState {
+    /// Calls go through
+    Closed,
+    /// Calls fail fast with [`PesaPalError::CircuitOpen`]
+    Open,
+    /// A limited number of probe calls go through
+    HalfOpen,
+}
+
+impl CircuitState {
+    /// Name of the state as used in logs and metrics
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half_open",
+        }
+    }
+}
+
+impl std::fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Callback invoked with the previous and the new state of the circuit
+type StateListener = Arc<dyn Fn(CircuitState, CircuitState) + Send + Sync>;
+
+/// Circuit breaker configuration
+///
+/// By default the circuit opens after 5 consecutive failures, stays open for
+/// 30 seconds and then lets a single probe call through.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use pesapal::CircuitBreaker;
+///
+/// let breaker = CircuitBreaker::new()
+///     .consecutive_failures(3)
+///     .failure_rate(0.5, 20)
+///     .open_for(Duration::from_secs(10))
+///     .on_state_change(|from, to| println!("pesapal circuit {from} -> {to}"));
+/// ```
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    consecutive_failures: Option<u32>,
+    failure_rate: Option<(f64, usize)>,
+    open_for: Duration,
+    half_open_probes: u32,
+    on_state_change: Option<StateListener>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: Some(5),
+            failure_rate: None,
+            open_for: Duration::from_secs(30),
+            half_open_probes: 1,
+            on_state_change: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("consecutive_failures", &self.consecutive_failures)
+            .field("failure_rate", &self.failure_rate)
+            .field("open_for", &self.open_for)
+            .field("half_open_probes", &self.half_open_probes)
+            .field("on_state_change", &self.on_state_change.is_some())
+            .finish()
+    }
+}
+
+impl CircuitBreaker {
+    /// Creates a circuit breaker with the default configuration
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the circuit after `failures` consecutive failures
+    ///
+    /// # Panics
+    /// Panics if `failures` is zero
+    #[must_use]
+    pub fn consecutive_failures(mut self, failures: u32) -> Self {
+        assert!(failures > 0, "consecutive failures must be greater than 0");
+        self.consecutive_failures = Some(failures);
+        self
+    }
+
+    /// Opens the circuit when at least `rate` of the last `window` calls
+    /// failed
+    ///
+    /// # Panics
+    /// Panics if `rate` is not within `(0, 1]` or `window` is zero
+    #[must_use]
+    pub fn failure_rate(mut self, rate: f64, window: usize) -> Self {
+        assert!(
+            rate > 0.0 && rate <= 1.0,
+            "failure rate must be within (0, 1]"
+        );
+        assert!(window > 0, "failure rate window must be greater than 0");
+        self.failure_rate = Some((rate, window));
+        self
+    }
+
+    /// Time the circuit stays open before letting probe calls through
+    #[must_use]
+    pub const fn open_for(mut self, open_for: Duration) -> Self {
+        self.open_for = open_for;
+        self
+    }
+
+    /// Number of probe calls let through while half-open, the circuit closes
+    /// once all of them succeed
+    ///
+    /// # Panics
+    /// Panics if `probes` is zero
+    #[must_use]
+    pub fn half_open_probes(mut self, probes: u32) -> Self {
+        assert!(probes > 0, "half-open probes must be greater than 0");
+        self.half_open_probes = probes;
+        self
+    }
+
+    /// Registers a callback invoked with the previous and the new state
+    /// whenever the circuit changes state
+    #[must_use]
+    pub fn on_state_change<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(CircuitState, CircuitState) + Send + Sync + 'static,
+    {
+        self.on_state_change = Some(Arc::new(listener));
+        self
+    }
+}
+
+/// Circuit breaker shared by all the clones of a client
+#[derive(Debug)]
+pub(crate) struct Breaker {
+    config: CircuitBreaker,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug)]
+struct BreakerState {
+    circuit: Circuit,
+    consecutive_failures: u32,
+    /// Outcomes of the last calls, `true` for a failure
+    outcomes: VecDeque<bool>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Circuit {
+    Closed,
+    Open { until: Instant },
+    HalfOpen { in_flight: u32, successes: u32 },
+}
+
+impl Circuit {
+    const fn state(self) -> CircuitState {
+        match self {
+            Self::Closed => CircuitState::Closed,
+            Self::Open { .. } => CircuitState::Open,
+            Self::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+}
+
+impl Breaker {
+    pub(crate) fn new(config: CircuitBreaker) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BreakerState {
+                circuit: Circuit::Closed,
+                consecutive_failures: 0,
+                outcomes: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Current state of the circuit
+    pub(crate) fn state(&self) -> CircuitState {
+        self.lock().circuit.state()
+    }
+
+    /// Lets a call through, or fails fast if the circuit is open
+    ///
+    /// # Errors
+    /// [`PesaPalError::CircuitOpen`] - Incase the circuit is open or all the
+    /// half-open probes are in flight
+    pub(crate) fn admit(&self, now: Instant) -> PesaPalResult<CircuitPermit<'_>> {
+        let mut state = self.lock();
+        let previous = state.circuit.state();
+
+        let probe = match state.circuit {
+            Circuit::Closed => false,
+            Circuit::Open { until } if now < until => {
+                return Err(PesaPalError::CircuitOpen {
+                    retry_after: until - now,
+                });
+            }
+            Circuit::Open { .. } => {
+                state.circuit = Circuit::HalfOpen {
+                    in_flight: 1,
+                    successes: 0,
+                };
+                true
+            }
+            Circuit::HalfOpen {
+                in_flight,
+                successes,
+            } => {
+                if in_flight + successes >= self.config.half_open_probes {
+                    return Err(PesaPalError::CircuitOpen {
+                        retry_after: Duration::ZERO,
+                    });
+                }
+                state.circuit = Circuit::HalfOpen {
+                    in_flight: in_flight + 1,
+                    successes,
+                };
+                true
+            }
+        };
+
+        let current = state.circuit.state();
+        drop(state);
+        self.notify(previous, current);
+
+        Ok(CircuitPermit {
+            breaker: self,
+            probe,
+            done: false,
+        })
+    }
+
+    /// Records the outcome of a call let through by [`Breaker::admit`]
+    fn record(&self, probe: bool, failed: bool, now: Instant) {
+        let mut state = self.lock();
+        let previous = state.circuit.state();
+
+        match state.circuit {
+            Circuit::Closed => {
+                if failed {
+                    state.consecutive_failures += 1;
+                } else {
+                    state.consecutive_failures = 0;
+                }
+                if let Some((_, window)) = self.config.failure_rate {
+                    state.outcomes.push_back(failed);
+                    if state.outcomes.len() > window {
+                        state.outcomes.pop_front();
+                    }
+                }
+
+                if self.should_trip(&state) {
+                    self.open(&mut state, now);
+                }
+            }
+            Circuit::HalfOpen {
+                in_flight,
+                successes,
+            } if probe => {
+                if failed {
+                    self.open(&mut state, now);
+                } else if successes + 1 >= self.config.half_open_probes {
+                    state.circuit = Circuit::Closed;
+                } else {
+                    state.circuit = Circuit::HalfOpen {
+                        in_flight: in_flight.saturating_sub(1),
+                        successes: successes + 1,
+                    };
+                }
+            }
+            // Calls let through before the circuit opened don't count
+            Circuit::HalfOpen { .. } | Circuit::Open { .. } => {}
+        }
+
+        let current = state.circuit.state();
+        drop(state);
+        self.notify(previous, current);
+    }
+
+    /// Frees the slot of a probe which was cancelled before completing
+    fn release(&self) {
+        let mut state = self.lock();
+        if let Circuit::HalfOpen {
+            in_flight,
+            successes,
+        } = state.circuit
+        {
+            state.circuit = Circuit::HalfOpen {
+                in_flight: in_flight.saturating_sub(1),
+                successes,
+            };
+        }
+    }
+
+    fn should_trip(&self, state: &BreakerState) -> bool {
+        let consecutive = self
+            .config
+            .consecutive_failures
+            .is_some_and(|limit| state.consecutive_failures >= limit);
+
+        let rate = self.config.failure_rate.is_some_and(|(rate, window)| {
+            let failures = state.outcomes.iter().filter(|failed| **failed).count();
+            #[allow(clippy::cast_precision_loss)]
+            let observed = failures as f64 / window as f64;
+            state.outcomes.len() == window && observed >= rate
+        });
+
+        consecutive || rate
+    }
+
+    fn open(&self, state: &mut BreakerState, now: Instant) {
+        state.circuit = Circuit::Open {
+            until: now + self.config.open_for,
+        };
+        state.consecutive_failures = 0;
+        state.outcomes.clear();
+    }
+
+    fn notify(&self, previous: CircuitState, current: CircuitState) {
+        if previous == current {
+            return;
+        }
+
+        telemetry::observe_circuit_state(previous, current);
+        if let Some(listener) = &self.config.on_state_change {
+            listener(previous, current);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.state.lock().expect("circuit breaker lock poisoned")
+    }
+}
+
+/// Permission to send a call, the outcome must be reported with
+/// [`CircuitPermit::record`]
+#[derive(Debug)]
+pub(crate) struct CircuitPermit<'a> {
+    breaker: &'a Breaker,
+    probe: bool,
+    done: bool,
+}
+
+impl CircuitPermit<'_> {
+    /// Records the outcome of the call
+    ///
+    /// Transport errors and `5xx` responses count as failures
+    pub(crate) fn record(self, response: &PesaPalResult<HttpResponse>) {
+        let failed = response
+            .as_ref()
+            .map_or(true, |response| response.status.is_server_error());
+
+        self.finish(failed, Instant::now());
+    }
+
+    fn finish(mut self, failed: bool, now: Instant) {
+        self.done = true;
+        self.breaker.record(self.probe, failed, now);
+    }
+}
+
+impl Drop for CircuitPermit<'_> {
+    fn drop(&mut self) {
+        if self.probe && !self.done {
+            self.breaker.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Transitions = Arc<Mutex<Vec<(CircuitState, CircuitState)>>>;
+
+    fn breaker(config: CircuitBreaker) -> (Breaker, Transitions) {
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&transitions);
+        let config = config.on_state_change(move |from, to| {
+            recorded.lock().unwrap().push((from, to));
+        });
+
+        (Breaker::new(config), transitions)
+    }
+
+    fn call(breaker: &Breaker, failed: bool, now: Instant) {
+        breaker.admit(now).unwrap().finish(failed, now);
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let (breaker, transitions) = breaker(CircuitBreaker::new().consecutive_failures(3));
+        let now = Instant::now();
+
+        call(&breaker, true, now);
+        call(&breaker, true, now);
+        call(&breaker, false, now);
+        call(&breaker, true, now);
+        call(&breaker, true, now);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        call(&breaker, true, now);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(matches!(
+            breaker.admit(now + Duration::from_secs(10)),
+            Err(PesaPalError::CircuitOpen { retry_after }) if retry_after == Duration::from_secs(20)
+        ));
+        assert_eq!(
+            *transitions.lock().unwrap(),
+            vec![(CircuitState::Closed, CircuitState::Open)]
+        );
+    }
+
+    #[test]
+    fn test_opens_on_failure_rate() {
+        let (breaker, _) = breaker(CircuitBreaker::new().failure_rate(0.5, 4));
+        let now = Instant::now();
+
+        call(&breaker, true, now);
+        call(&breaker, false, now);
+        call(&breaker, true, now);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        call(&breaker, false, now);
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn test_half_open_probes() {
+        let (breaker, transitions) = breaker(
+            CircuitBreaker::new()
+                .consecutive_failures(1)
+                .half_open_probes(2)
+                .open_for(Duration::from_secs(5)),
+        );
+        let now = Instant::now();
+        call(&breaker, true, now);
+
+        // A failed probe opens the circuit again
+        let later = now + Duration::from_secs(5);
+        call(&breaker, true, later);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // Only the configured number of probes is let through
+        let later = later + Duration::from_secs(5);
+        let first = breaker.admit(later).unwrap();
+        let second = breaker.admit(later).unwrap();
+        assert!(breaker.admit(later).is_err());
+
+        // A cancelled probe frees its slot
+        drop(second);
+        let second = breaker.admit(later).unwrap();
+
+        first.finish(false, later);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        second.finish(false, later);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        assert_eq!(
+            *transitions.lock().unwrap(),
+            vec![
+                (CircuitState::Closed, CircuitState::Open),
+                (CircuitState::Open, CircuitState::HalfOpen),
+                (CircuitState::HalfOpen, CircuitState::Open),
+                (CircuitState::Open, CircuitState::HalfOpen),
+                (CircuitState::HalfOpen, CircuitState::Closed),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_fails_fast_while_open() {
+        use crate::transport::mock::MockTransport;
+        use crate::{Environment, PesaPal};
+
+        let transport = Arc::new(MockTransport::new().on("api/URLSetup/GetIpnList", 503, "{}"));
+        let client = PesaPal::new_with_transport(
+            "circuit-breaker-key",
+            "secret",
+            Environment::Sandbox,
+            Arc::clone(&transport),
+        )
+        .with_circuit_breaker(CircuitBreaker::new().consecutive_failures(2));
+
+        assert!(client.list_ipn_urls().send().await.is_err());
+        assert!(client.list_ipn_urls().send().await.is_err());
+        assert_eq!(client.circuit_state(), Some(CircuitState::Open));
+
+        let response = client.list_ipn_urls().send().await;
+        assert!(matches!(response, Err(PesaPalError::CircuitOpen { .. })));
+        assert_eq!(transport.requests("api/URLSetup/GetIpnList").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_call_doesnt_hold_the_probe() {
+        use crate::transport::mock::MockTransport;
+        use crate::{Endpoint, Environment, PesaPal, RateLimit};
+
+        let transport = Arc::new(
+            MockTransport::new()
+                .on("api/URLSetup/GetIpnList", 503, "{}")
+                .on("api/URLSetup/GetIpnList", 200, "[]")
+                .on(
+                    "api/Transactions/GetTransactionStatus",
+                    200,
+                    r#"{"paymentMethod":"Visa","amount":100,"createdDate":"2022-04-30T07:41:09.763","confirmationCode":"6513008693186320103009","paymentStatusDescription":1,"description":"","message":"ok","paymentAccount":"476173**0010","callBackUrl":"https://example.com","statusCode":1,"merchantReference":"TEST1515111119","currency":"KES","error":{"error_type":"","code":"","message":"","call_back_url":""},"status":"200"}"#,
+                ),
+        );
+        let client = PesaPal::new_with_transport(
+            "circuit-breaker-probe-key",
+            "secret",
+            Environment::Sandbox,
+            Arc::clone(&transport),
+        )
+        .with_rate_limit(
+            Endpoint::ListIpn,
+            RateLimit::new(1, Duration::from_millis(300)),
+        )
+        .with_circuit_breaker(
+            CircuitBreaker::new()
+                .consecutive_failures(1)
+                .open_for(Duration::from_millis(50)),
+        );
+
+        assert!(client.list_ipn_urls().send().await.is_err());
+        futures_timer::Delay::new(Duration::from_millis(60)).await;
+
+        // The list call waits for the rate limiter while the status lookup
+        // takes the only probe slot
+        let list_ipn = client.list_ipn_urls();
+        let (listed, status) = tokio::join!(list_ipn.send(), async {
+            futures_timer::Delay::new(Duration::from_millis(20)).await;
+            client
+                .transaction_status()
+                .order_tracking_id("tracking")
+                .build()
+                .unwrap()
+                .send()
+                .await
+        });
+
+        assert!(status.is_ok(), "{status:?}");
+        assert!(listed.is_ok(), "{listed:?}");
+        assert_eq!(client.circuit_state(), Some(CircuitState::Closed));
+    }
+}
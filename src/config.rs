@@ -0,0 +1,549 @@
+//! Client configuration loaded from the environment or a configuration file
+//!
+//! [`PesaPalConfig`] holds everything needed to build a [`PesaPal`] client:
+//! the credentials, the environment, the HTTP timeouts and the defaults
+//! applied to every [`SubmitOrder`](crate::SubmitOrder).
+//!
+//! It can be read from the `PESAPAL_*` environment variables with
+//! [`PesaPalConfig::from_env`], or deserialized with `serde` from any format,
+//! e.g. a TOML file:
+//!
+//! ```toml
+//! consumer_key = "..."
+//! consumer_secret = "..."
+//! environment = "production"
+//! timeout_secs = 30
+//! connect_timeout_secs = 10
+//! currency = "KES"
+//! callback_url = "https://example.com/pesapal/callback"
+//! cancellation_url = "https://example.com/pesapal/cancel"
+//! notification_id = "..."
+//! ```
+//!
+//! Every problem found is reported at once in a [`ConfigError`], instead of
+//! failing on the first one.
+
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::environment::Environment;
+use crate::error::{PesaPalError, PesaPalResult};
+use crate::pesapal::PesaPal;
+use crate::secret::Secret;
+use crate::transport::HttpTransport;
+
+/// `PesaPal` consumer key, required
+pub const CONSUMER_KEY_VAR: &str = "PESAPAL_CONSUMER_KEY";
+/// `PesaPal` consumer secret, required
+pub const CONSUMER_SECRET_VAR: &str = "PESAPAL_CONSUMER_SECRET";
+/// `sandbox` or `production`, defaults to `sandbox`
+pub const ENVIRONMENT_VAR: &str = "PESAPAL_ENVIRONMENT";
+/// Timeout of a whole request in seconds
+pub const TIMEOUT_VAR: &str = "PESAPAL_TIMEOUT_SECS";
+/// Timeout of the connection in seconds, defaults to 10
+pub const CONNECT_TIMEOUT_VAR: &str = "PESAPAL_CONNECT_TIMEOUT_SECS";
+/// Default ISO 4217 currency code of the orders
+pub const CURRENCY_VAR: &str = "PESAPAL_CURRENCY";
+/// Default HTTPS callback URL of the orders
+pub const CALLBACK_URL_VAR: &str = "PESAPAL_CALLBACK_URL";
+/// Default HTTPS cancellation URL of the orders
+pub const CANCELLATION_URL_VAR: &str = "PESAPAL_CANCELLATION_URL";
+/// Default IPN id of the orders
+pub const NOTIFICATION_ID_VAR: &str = "PESAPAL_NOTIFICATION_ID";
+
+/// Default connect timeout, the same as the default [`ReqwestTransport`](crate::transport::ReqwestTransport)
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Configuration of a [`PesaPal`] client
+///
+/// # Example
+/// ```ignore
+/// let config: PesaPalConfig = toml::from_str(&std::fs::read_to_string("pesapal.toml")?)?;
+/// let pesapal = PesaPal::from_config(config)?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RawConfig")]
+#[non_exhaustive]
+pub struct PesaPalConfig {
+    /// `PesaPal` consumer key
+    pub consumer_key: String,
+    /// `PesaPal` consumer secret
+    pub consumer_secret: Secret,
+    /// Environment the client talks to
+    pub environment: Environment,
+    /// Timeout of a whole request, none by default
+    pub timeout: Option<Duration>,
+    /// Timeout of the connection
+    pub connect_timeout: Duration,
+    /// Defaults applied to the orders
+    pub defaults: OrderDefaults,
+}
+
+/// Values used by [`PesaPal::submit_order`] when the order doesn't set them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct OrderDefaults {
+    /// ISO 4217 currency code, in upper case
+    pub currency: Option<String>,
+    /// URL `PesaPal` redirects the customer to after the payment
+    pub callback_url: Option<String>,
+    /// URL `PesaPal` redirects the customer to if they cancel the payment
+    pub cancellation_url: Option<String>,
+    /// Id of the registered IPN URL
+    pub notification_id: Option<String>,
+}
+
+impl OrderDefaults {
+    /// Creates empty defaults
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the currency, an ISO 4217 code
+    #[must_use]
+    pub fn currency(mut self, currency: impl Into<String>) -> Self {
+        self.currency = Some(currency.into().to_uppercase());
+        self
+    }
+
+    /// Sets the callback URL
+    #[must_use]
+    pub fn callback_url(mut self, callback_url: impl Into<String>) -> Self {
+        self.callback_url = Some(callback_url.into());
+        self
+    }
+
+    /// Sets the cancellation URL
+    #[must_use]
+    pub fn cancellation_url(mut self, cancellation_url: impl Into<String>) -> Self {
+        self.cancellation_url = Some(cancellation_url.into());
+        self
+    }
+
+    /// Sets the IPN id
+    #[must_use]
+    pub fn notification_id(mut self, notification_id: impl Into<String>) -> Self {
+        self.notification_id = Some(notification_id.into());
+        self
+    }
+}
+
+impl PesaPalConfig {
+    /// Reads the configuration from the `PESAPAL_*` environment variables
+    ///
+    /// | Variable | |
+    /// |---|---|
+    /// | `PESAPAL_CONSUMER_KEY` | required |
+    /// | `PESAPAL_CONSUMER_SECRET` | required |
+    /// | `PESAPAL_ENVIRONMENT` | `sandbox` (default) or `production` |
+    /// | `PESAPAL_TIMEOUT_SECS` | request timeout, none by default |
+    /// | `PESAPAL_CONNECT_TIMEOUT_SECS` | connect timeout, 10 by default |
+    /// | `PESAPAL_CURRENCY` | default currency of the orders |
+    /// | `PESAPAL_CALLBACK_URL` | default callback URL of the orders |
+    /// | `PESAPAL_CANCELLATION_URL` | default cancellation URL of the orders |
+    /// | `PESAPAL_NOTIFICATION_ID` | default IPN id of the orders |
+    ///
+    /// Empty variables are treated as unset.
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ConfigError`] listing every missing or invalid variable
+    pub fn from_env() -> PesaPalResult<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Parses a JSON configuration, with the keys of the TOML example in the
+    /// [module documentation](crate::config)
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ConfigError`] if the JSON is malformed or any value is
+    /// missing or invalid
+    pub fn from_json(json: &str) -> PesaPalResult<Self> {
+        serde_json::from_str(json).map_err(|error| ConfigError::new(error.to_string()).into())
+    }
+
+    /// Parses a TOML configuration, see the [module documentation](crate::config)
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ConfigError`] if the TOML is malformed or any value is
+    /// missing or invalid
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> PesaPalResult<Self> {
+        toml::from_str(toml).map_err(|error| ConfigError::new(error.message().to_string()).into())
+    }
+
+    /// Reads a configuration file, parsed as TOML if its extension is
+    /// `.toml` and as JSON otherwise
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ConfigError`] if the file can't be read or parsed
+    pub fn from_file(path: impl AsRef<Path>) -> PesaPalResult<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| ConfigError::new(format!("can't read {}: {error}", path.display())))?;
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            #[cfg(feature = "toml")]
+            Some("toml") => Self::from_toml(&contents),
+            #[cfg(not(feature = "toml"))]
+            Some("toml") => Err(ConfigError::new(format!(
+                "can't read {}: TOML configuration requires the `toml` feature",
+                path.display()
+            ))
+            .into()),
+            _ => Self::from_json(&contents),
+        }
+    }
+
+    /// Reads the configuration from the `PESAPAL_*` variables looked up with
+    /// `var`, e.g. to layer command line flags over the environment, see
+    /// [`PesaPalConfig::from_env`]
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ConfigError`] listing every missing or invalid variable
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> PesaPalResult<Self> {
+        let var = |name| var(name).filter(|value: &String| !value.trim().is_empty());
+        let mut problems = Vec::new();
+        let mut seconds = |name| {
+            var(name).and_then(|value: String| match value.trim().parse::<u64>() {
+                Ok(seconds) => Some(seconds),
+                Err(_) => {
+                    problems.push(format!(
+                        "{name} must be a whole number of seconds, got {value:?}"
+                    ));
+                    None
+                }
+            })
+        };
+        let timeout_secs = seconds(TIMEOUT_VAR);
+        let connect_timeout_secs = seconds(CONNECT_TIMEOUT_VAR);
+
+        let raw = RawConfig {
+            consumer_key: var(CONSUMER_KEY_VAR),
+            consumer_secret: var(CONSUMER_SECRET_VAR),
+            environment: var(ENVIRONMENT_VAR),
+            timeout_secs,
+            connect_timeout_secs,
+            currency: var(CURRENCY_VAR),
+            callback_url: var(CALLBACK_URL_VAR),
+            cancellation_url: var(CANCELLATION_URL_VAR),
+            notification_id: var(NOTIFICATION_ID_VAR),
+        };
+
+        raw.validate(&Names::ENV, problems)
+            .map_err(PesaPalError::ConfigError)
+    }
+}
+
+impl PesaPal {
+    /// Constructs a new `PesaPal` Instance from the `PESAPAL_*` environment
+    /// variables, see [`PesaPalConfig::from_env`]
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ConfigError`] listing every missing or invalid variable
+    #[cfg(feature = "reqwest")]
+    pub fn from_env() -> PesaPalResult<Self> {
+        Self::from_config(PesaPalConfig::from_env()?)
+    }
+
+    /// Constructs a new `PesaPal` Instance from the configuration, with a
+    /// [`ReqwestTransport`](crate::transport::ReqwestTransport) using its
+    /// timeouts
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ReqwestError`] if the HTTP client can't be built
+    #[cfg(feature = "reqwest")]
+    pub fn from_config(config: PesaPalConfig) -> PesaPalResult<Self> {
+        let mut client = reqwest::Client::builder()
+            .connect_timeout(config.connect_timeout)
+            .user_agent(format!("pesapal-rs @{}", env!("CARGO_PKG_VERSION")));
+        if let Some(timeout) = config.timeout {
+            client = client.timeout(timeout);
+        }
+        let transport = crate::transport::ReqwestTransport::from_client(client.build()?);
+
+        Ok(Self::from_config_with_transport(config, transport))
+    }
+
+    /// Constructs a new `PesaPal` Instance from the configuration which sends
+    /// the requests through the given [`HttpTransport`]
+    ///
+    /// The timeouts of the configuration are left to the transport.
+    pub fn from_config_with_transport(
+        config: PesaPalConfig,
+        transport: impl HttpTransport + 'static,
+    ) -> Self {
+        Self::new_with_transport(
+            config.consumer_key.as_str(),
+            config.consumer_secret.expose_secret(),
+            config.environment,
+            transport,
+        )
+        .with_order_defaults(config.defaults)
+    }
+}
+
+/// Every problem found in a configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConfigError {
+    /// Descriptions of the missing or invalid values
+    pub problems: Vec<String>,
+}
+
+impl ConfigError {
+    fn new(problem: String) -> Self {
+        Self {
+            problems: vec![problem],
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.problems.join("; "))
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for PesaPalError {
+    fn from(value: ConfigError) -> Self {
+        Self::ConfigError(value)
+    }
+}
+
+/// Names of the values in the error messages
+struct Names {
+    consumer_key: &'static str,
+    consumer_secret: &'static str,
+    environment: &'static str,
+    timeout: &'static str,
+    connect_timeout: &'static str,
+    currency: &'static str,
+    callback_url: &'static str,
+    cancellation_url: &'static str,
+}
+
+impl Names {
+    const ENV: Self = Self {
+        consumer_key: CONSUMER_KEY_VAR,
+        consumer_secret: CONSUMER_SECRET_VAR,
+        environment: ENVIRONMENT_VAR,
+        timeout: TIMEOUT_VAR,
+        connect_timeout: CONNECT_TIMEOUT_VAR,
+        currency: CURRENCY_VAR,
+        callback_url: CALLBACK_URL_VAR,
+        cancellation_url: CANCELLATION_URL_VAR,
+    };
+
+    const FILE: Self = Self {
+        consumer_key: "consumer_key",
+        consumer_secret: "consumer_secret",
+        environment: "environment",
+        timeout: "timeout_secs",
+        connect_timeout: "connect_timeout_secs",
+        currency: "currency",
+        callback_url: "callback_url",
+        cancellation_url: "cancellation_url",
+    };
+}
+
+/// Configuration as written, checked all at once by [`RawConfig::validate`]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    consumer_key: Option<String>,
+    consumer_secret: Option<String>,
+    environment: Option<String>,
+    timeout_secs: Option<u64>,
+    connect_timeout_secs: Option<u64>,
+    currency: Option<String>,
+    callback_url: Option<String>,
+    cancellation_url: Option<String>,
+    notification_id: Option<String>,
+}
+
+impl RawConfig {
+    fn validate(
+        self,
+        names: &Names,
+        mut problems: Vec<String>,
+    ) -> Result<PesaPalConfig, ConfigError> {
+        let mut required = |name: &str, value: Option<String>| match value {
+            Some(value) if !value.trim().is_empty() => value,
+            _ => {
+                problems.push(format!("{name} is required"));
+                String::new()
+            }
+        };
+        let consumer_key = required(names.consumer_key, self.consumer_key);
+        let consumer_secret = required(names.consumer_secret, self.consumer_secret);
+
+        let environment = match self.environment {
+            None => Environment::default(),
+            Some(value) => Environment::try_from(value.trim()).unwrap_or_else(|_| {
+                problems.push(format!(
+                    "{} must be `sandbox` or `production`, got {value:?}",
+                    names.environment
+                ));
+                Environment::default()
+            }),
+        };
+
+        let mut duration = |name: &str, seconds: Option<u64>| match seconds {
+            Some(0) => {
+                problems.push(format!("{name} must be greater than 0"));
+                None
+            }
+            seconds => seconds.map(Duration::from_secs),
+        };
+        let timeout = duration(names.timeout, self.timeout_secs);
+        let connect_timeout = duration(names.connect_timeout, self.connect_timeout_secs)
+            .unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+
+        let currency = self.currency.map(|currency| currency.trim().to_uppercase());
+        if let Some(currency) = &currency {
+            if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+                problems.push(format!(
+                    "{} must be a 3 letter ISO 4217 code, got {currency:?}",
+                    names.currency
+                ));
+            }
+        }
+
+        for (name, url) in [
+            (names.callback_url, &self.callback_url),
+            (names.cancellation_url, &self.cancellation_url),
+        ] {
+            if let Some(url) = url {
+                match url::Url::parse(url) {
+                    // `PesaPal` only redirects to HTTPS URLs, as checked on the orders
+                    Ok(parsed) if parsed.scheme() == "https" => {}
+                    Ok(_) => problems.push(format!("{name} must be an HTTPS URL, got {url:?}")),
+                    Err(error) => {
+                        problems.push(format!("{name} is not a valid URL ({error}): {url:?}"))
+                    }
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(ConfigError { problems });
+        }
+
+        Ok(PesaPalConfig {
+            consumer_key,
+            consumer_secret: Secret::from(consumer_secret),
+            environment,
+            timeout,
+            connect_timeout,
+            defaults: OrderDefaults {
+                currency,
+                callback_url: self.callback_url,
+                cancellation_url: self.cancellation_url,
+                notification_id: self.notification_id,
+            },
+        })
+    }
+}
+
+impl TryFrom<RawConfig> for PesaPalConfig {
+    type Error = ConfigError;
+
+    fn try_from(value: RawConfig) -> Result<Self, Self::Error> {
+        value.validate(&Names::FILE, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn from_vars(vars: &[(&str, &str)]) -> PesaPalResult<PesaPalConfig> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+            .collect();
+        PesaPalConfig::from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_config_from_vars() {
+        let config = from_vars(&[
+            (CONSUMER_KEY_VAR, "key"),
+            (CONSUMER_SECRET_VAR, "hunter2"),
+            (ENVIRONMENT_VAR, "Production"),
+            (TIMEOUT_VAR, "30"),
+            (CURRENCY_VAR, "kes"),
+            (CALLBACK_URL_VAR, "https://example.com/callback"),
+            (NOTIFICATION_ID_VAR, "ipn-id"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.consumer_key, "key");
+        assert_eq!(config.environment, Environment::Production);
+        assert_eq!(config.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(config.connect_timeout, DEFAULT_CONNECT_TIMEOUT);
+        assert_eq!(config.defaults.currency.as_deref(), Some("KES"));
+        assert_eq!(config.defaults.cancellation_url, None);
+        assert!(!format!("{config:?}").contains("hunter2"));
+    }
+
+    #[test]
+    fn test_config_errors_are_aggregated() {
+        let Err(PesaPalError::ConfigError(error)) = from_vars(&[
+            (CONSUMER_SECRET_VAR, "secret"),
+            (ENVIRONMENT_VAR, "staging"),
+            (TIMEOUT_VAR, "soon"),
+            (CURRENCY_VAR, "shilling"),
+            (CALLBACK_URL_VAR, "example.com"),
+            (CANCELLATION_URL_VAR, "http://example.com/cancel"),
+        ]) else {
+            panic!("expected a configuration error");
+        };
+
+        assert_eq!(
+            error.problems,
+            [
+                "PESAPAL_TIMEOUT_SECS must be a whole number of seconds, got \"soon\"",
+                "PESAPAL_CONSUMER_KEY is required",
+                "PESAPAL_ENVIRONMENT must be `sandbox` or `production`, got \"staging\"",
+                "PESAPAL_CURRENCY must be a 3 letter ISO 4217 code, got \"SHILLING\"",
+                "PESAPAL_CALLBACK_URL is not a valid URL (relative URL without a base): \"example.com\"",
+                "PESAPAL_CANCELLATION_URL must be an HTTPS URL, got \"http://example.com/cancel\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_from_json() {
+        let config = PesaPalConfig::from_json(
+            r#"{"consumer_key": "key", "consumer_secret": "secret", "connect_timeout_secs": 5}"#,
+        )
+        .unwrap();
+        assert_eq!(config.environment, Environment::Sandbox);
+        assert_eq!(config.connect_timeout, Duration::from_secs(5));
+
+        let Err(PesaPalError::ConfigError(error)) =
+            PesaPalConfig::from_json(r#"{"timeout_secs": 0}"#)
+        else {
+            panic!("expected a configuration error");
+        };
+        assert!(error.to_string().starts_with(
+            "consumer_key is required; consumer_secret is required; timeout_secs must be greater than 0"
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+macro_rules! country_codes {
+    ($($code:ident),+ $(,)?) => {
+        /// Country in [ISO 3166-1 alpha-2]
+        ///
+        /// [ISO 3166-1 alpha-2]: https://en.wikipedia.org/wiki/ISO_3166-1_alpha-2
+        ///
+        /// # Example
+        /// ```
+        /// use pesapal::CountryCode;
+        ///
+        /// let country: CountryCode = "ke".parse().unwrap();
+        /// assert_eq!(country, CountryCode::KE);
+        /// assert_eq!(country.as_str(), "KE");
+        /// ```
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[allow(clippy::upper_case_acronyms)]
+        #[non_exhaustive]
+        pub enum CountryCode {
+            $($code),+
+        }
+
+        impl CountryCode {
+            /// Every assigned code, in alphabetical order
+            pub const ALL: &'static [Self] = &[$(Self::$code),+];
+
+            /// Two letter code of the country
+            #[must_use]
+            pub const fn as_str(&self) -> &'static str {
+                match self {
+                    $(Self::$code => stringify!($code)),+
+                }
+            }
+        }
+    };
+}
+
+country_codes!(
+    AD, AE, AF, AG, AI, AL, AM, AO, AQ, AR, AS, AT, AU, AW, AX, AZ, BA, BB, BD, BE, BF, BG, BH, BI,
+    BJ, BL, BM, BN, BO, BQ, BR, BS, BT, BV, BW, BY, BZ, CA, CC, CD, CF, CG, CH, CI, CK, CL, CM, CN,
+    CO, CR, CU, CV, CW, CX, CY, CZ, DE, DJ, DK, DM, DO, DZ, EC, EE, EG, EH, ER, ES, ET, FI, FJ, FK,
+    FM, FO, FR, GA, GB, GD, GE, GF, GG, GH, GI, GL, GM, GN, GP, GQ, GR, GS, GT, GU, GW, GY, HK, HM,
+    HN, HR, HT, HU, ID, IE, IL, IM, IN, IO, IQ, IR, IS, IT, JE, JM, JO, JP, KE, KG, KH, KI, KM, KN,
+    KP, KR, KW, KY, KZ, LA, LB, LC, LI, LK, LR, LS, LT, LU, LV, LY, MA, MC, MD, ME, MF, MG, MH, MK,
+    ML, MM, MN, MO, MP, MQ, MR, MS, MT, MU, MV, MW, MX, MY, MZ, NA, NC, NE, NF, NG, NI, NL, NO, NP,
+    NR, NU, NZ, OM, PA, PE, PF, PG, PH, PK, PL, PM, PN, PR, PS, PT, PW, PY, QA, RE, RO, RS, RU, RW,
+    SA, SB, SC, SD, SE, SG, SH, SI, SJ, SK, SL, SM, SN, SO, SR, SS, ST, SV, SX, SY, SZ, TC, TD, TF,
+    TG, TH, TJ, TK, TL, TM, TN, TO, TR, TT, TV, TW, TZ, UA, UG, UM, US, UY, UZ, VA, VC, VE, VG, VI,
+    VN, VU, WF, WS, YE, YT, ZA, ZM, ZW,
+);
+
+impl fmt::Display for CountryCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CountryCode {
+    type Err = CountryCodeError;
+
+    /// Parses the two letter code, ignoring case and surrounding whitespace
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.trim();
+        Self::ALL
+            .iter()
+            .find(|country| country.as_str().eq_ignore_ascii_case(code))
+            .copied()
+            .ok_or_else(|| CountryCodeError(s.to_string()))
+    }
+}
+
+impl TryFrom<&str> for CountryCode {
+    type Error = CountryCodeError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Serialize for CountryCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for CountryCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Value which isn't an ISO 3166-1 alpha-2 code
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{0:?} isn't an ISO 3166-1 alpha-2 country code")]
+pub struct CountryCodeError(pub String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_country_code() {
+        assert_eq!(CountryCode::ALL.len(), 249);
+        assert_eq!(" ug ".parse(), Ok(CountryCode::UG));
+        assert_eq!(
+            "KEN".parse::<CountryCode>(),
+            Err(CountryCodeError("KEN".to_string()))
+        );
+        assert_eq!(serde_json::to_string(&CountryCode::TZ).unwrap(), r#""TZ""#);
+        assert!(serde_json::from_str::<CountryCode>(r#""XX""#).is_err());
+    }
+}
@@ -0,0 +1,40 @@
+/// `PesaPal` API endpoints called by the client
+///
+/// Used to label the telemetry emitted for every call.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Endpoint {
+    /// `api/Auth/RequestToken`
+    Auth,
+    /// `api/Transactions/SubmitOrderRequest`
+    SubmitOrder,
+    /// `api/Transactions/RefundRequest`
+    Refund,
+    /// `api/Transactions/GetTransactionStatus`
+    TransactionStatus,
+    /// `api/URLSetup/RegisterIPN`
+    RegisterIpn,
+    /// `api/URLSetup/GetIpnList`
+    ListIpn,
+}
+
+impl Endpoint {
+    /// Name of the endpoint as used in spans and metric labels
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Auth => "auth",
+            Self::SubmitOrder => "submit_order",
+            Self::Refund => "refund",
+            Self::TransactionStatus => "transaction_status",
+            Self::RegisterIpn => "register_ipn",
+            Self::ListIpn => "list_ipn",
+        }
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
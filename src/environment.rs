@@ -21,6 +21,21 @@ impl Environment {
             Self::Sandbox => "https://cybqa.pesapal.com/pesapalv3",
         }
     }
+
+    /// Name of the environment, as accepted by [`Environment::from_str`]
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Production => "production",
+            Self::Sandbox => "sandbox",
+        }
+    }
+}
+
+impl std::fmt::Display for Environment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 impl TryFrom<&str> for Environment {
@@ -63,6 +78,13 @@ mod tests {
         assert_eq!(env, Environment::Production);
     }
 
+    #[test]
+    fn test_environment_display_round_trip() {
+        for env in [Environment::Production, Environment::Sandbox] {
+            assert_eq!(Environment::from_str(&env.to_string()).unwrap(), env);
+        }
+    }
+
     #[test]
     fn test_environment_with_different_case() {
         let envs = vec!["Production", "PRODUCTION", "production", "PrOdUcTiOn"];
@@ -1,5 +1,7 @@
 use serde::Deserialize;
 
+use crate::response::ResponseMeta;
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum PesaPalError {
@@ -11,18 +13,188 @@ pub enum PesaPalError {
     #[error("submit order failed :{0}")]
     SubmitOrderError(PesaPalErrorResponse),
 
-    #[error("refund request failed {0}")]
-    RefundError(String),
+    #[error("refund request failed : {0}")]
+    RefundError(crate::pesapal::refund::RefundRejection),
+    #[error("refund workflow error : {0}")]
+    RefundWorkflowError(crate::refund_workflow::RefundWorkflowError),
+    #[error("refund rejected before it was sent : {0}")]
+    RefundPreflightError(crate::pesapal::refund::RefundPreflightError),
     #[error("register IPN URL error")]
     RegisterIPNError(PesaPalErrorResponse),
     #[error("transaction status error : {0:?}")]
     TransactionStatusError(TransactionStatusError),
+    #[cfg(feature = "reqwest")]
     #[error(transparent)]
     ReqwestError(#[from] reqwest::Error),
+    #[error("transport error : {0}")]
+    TransportError(Box<dyn std::error::Error + Send + Sync>),
     #[error("unsupported environment {0}")]
     UnsupportedEnvironment(String),
     #[error("validation error")]
     ValidationError(String),
+    #[error("invalid request : {0}")]
+    ValidationErrors(#[from] crate::ValidationErrors),
+    #[error("invalid billing address : {}", crate::pesapal::billing_address::describe(.0))]
+    BillingAddressError(Vec<crate::BillingAddressError>),
+    #[error("invalid phone number : {0}")]
+    PhoneNumberError(#[from] crate::PhoneNumberError),
+    #[error("unknown merchant {0}")]
+    UnknownMerchant(String),
+    #[error("invalid configuration : {0}")]
+    ConfigError(crate::config::ConfigError),
+    #[error("invalid response from PesaPal : {reason}")]
+    InvalidResponse {
+        /// Why the body couldn't be read
+        reason: String,
+        /// Metadata of the response, including its raw body
+        meta: Box<ResponseMeta>,
+    },
+    #[error("invalid redirect_url {redirect_url:?} from PesaPal : {reason}")]
+    InvalidRedirectUrl {
+        /// `redirect_url` returned by `PesaPal`
+        redirect_url: String,
+        /// Why it can't be used
+        reason: String,
+    },
+    #[error("circuit breaker is open, retry after {retry_after:?}")]
+    CircuitOpen {
+        /// Time left before the circuit lets probe calls through
+        retry_after: std::time::Duration,
+    },
+}
+
+impl PesaPalError {
+    /// Short, stable name of the error kind
+    ///
+    /// Used as the `error_class` label of the emitted metrics
+    #[must_use]
+    pub fn class(&self) -> &'static str {
+        match self {
+            Self::Internal(_) => "internal",
+            Self::AuthenticationError(_) => "authentication",
+            Self::SubmitOrderError(_) => "submit_order",
+            Self::RefundError(_) => "refund",
+            Self::RefundPreflightError(_) => "refund_preflight",
+            Self::RefundWorkflowError(_) => "refund_workflow",
+            Self::RegisterIPNError(_) => "register_ipn",
+            Self::TransactionStatusError(_) => "transaction_status",
+            #[cfg(feature = "reqwest")]
+            Self::ReqwestError(e) if e.is_timeout() => "timeout",
+            #[cfg(feature = "reqwest")]
+            Self::ReqwestError(e) if e.is_connect() => "connect",
+            #[cfg(feature = "reqwest")]
+            Self::ReqwestError(e) if e.is_decode() => "decode",
+            #[cfg(feature = "reqwest")]
+            Self::ReqwestError(_) => "http",
+            Self::TransportError(_) => "transport",
+            Self::UnsupportedEnvironment(_) => "unsupported_environment",
+            Self::ValidationError(_) => "validation",
+            Self::ValidationErrors(_) => "validation",
+            Self::BillingAddressError(_) => "billing_address",
+            Self::PhoneNumberError(_) => "phone_number",
+            Self::UnknownMerchant(_) => "unknown_merchant",
+            Self::ConfigError(_) => "config",
+            Self::InvalidResponse { .. } => "invalid_response",
+            Self::InvalidRedirectUrl { .. } => "invalid_redirect_url",
+            Self::CircuitOpen { .. } => "circuit_open",
+        }
+    }
+
+    /// Copy of the error for the callers sharing a coalesced request
+    ///
+    /// The HTTP client and transport errors can't be cloned, they are copied
+    /// as a [`PesaPalError::TransportError`] with the same message.
+    pub(crate) fn duplicate(&self) -> Self {
+        match self {
+            Self::Internal(message) => Self::Internal(message.clone()),
+            Self::AuthenticationError(error) => Self::AuthenticationError(error.clone()),
+            Self::SubmitOrderError(error) => Self::SubmitOrderError(error.clone()),
+            Self::RefundError(rejection) => Self::RefundError(rejection.clone()),
+            Self::RefundPreflightError(error) => Self::RefundPreflightError(error.clone()),
+            Self::RefundWorkflowError(error) => Self::RefundWorkflowError(error.clone()),
+            Self::RegisterIPNError(error) => Self::RegisterIPNError(error.clone()),
+            Self::TransactionStatusError(error) => Self::TransactionStatusError(error.clone()),
+            #[cfg(feature = "reqwest")]
+            Self::ReqwestError(error) => Self::TransportError(error.to_string().into()),
+            Self::TransportError(error) => Self::TransportError(error.to_string().into()),
+            Self::UnsupportedEnvironment(environment) => {
+                Self::UnsupportedEnvironment(environment.clone())
+            }
+            Self::ValidationError(message) => Self::ValidationError(message.clone()),
+            Self::ValidationErrors(errors) => Self::ValidationErrors(errors.clone()),
+            Self::BillingAddressError(errors) => Self::BillingAddressError(errors.clone()),
+            Self::PhoneNumberError(error) => Self::PhoneNumberError(error.clone()),
+            Self::UnknownMerchant(merchant_id) => Self::UnknownMerchant(merchant_id.clone()),
+            Self::ConfigError(error) => Self::ConfigError(error.clone()),
+            Self::InvalidResponse { reason, meta } => Self::InvalidResponse {
+                reason: reason.clone(),
+                meta: meta.clone(),
+            },
+            Self::InvalidRedirectUrl {
+                redirect_url,
+                reason,
+            } => Self::InvalidRedirectUrl {
+                redirect_url: redirect_url.clone(),
+                reason: reason.clone(),
+            },
+            Self::CircuitOpen { retry_after } => Self::CircuitOpen {
+                retry_after: *retry_after,
+            },
+        }
+    }
+
+    /// HTTP metadata of the response, for the errors returned once `PesaPal`
+    /// responded
+    ///
+    /// The request id and raw body are what `PesaPal` support asks for.
+    #[must_use]
+    pub fn response_meta(&self) -> Option<&ResponseMeta> {
+        match self {
+            Self::AuthenticationError(error)
+            | Self::SubmitOrderError(error)
+            | Self::RegisterIPNError(error) => error.meta.as_deref(),
+            Self::RefundError(rejection) => rejection.meta.as_deref(),
+            Self::TransactionStatusError(error) => error.meta.as_deref(),
+            Self::InvalidResponse { meta, .. } => Some(meta),
+            _ => None,
+        }
+    }
+
+    /// Attaches the metadata of the response the error was read from
+    ///
+    /// Other errors raised while reading the response are reported as
+    /// [`PesaPalError::InvalidResponse`].
+    pub(crate) fn with_meta(self, meta: ResponseMeta) -> Self {
+        let meta = Box::new(meta);
+        match self {
+            Self::AuthenticationError(error) => Self::AuthenticationError(PesaPalErrorResponse {
+                meta: Some(meta),
+                ..error
+            }),
+            Self::SubmitOrderError(error) => Self::SubmitOrderError(PesaPalErrorResponse {
+                meta: Some(meta),
+                ..error
+            }),
+            Self::RegisterIPNError(error) => Self::RegisterIPNError(PesaPalErrorResponse {
+                meta: Some(meta),
+                ..error
+            }),
+            Self::RefundError(rejection) => {
+                Self::RefundError(crate::pesapal::refund::RefundRejection {
+                    meta: Some(meta),
+                    ..rejection
+                })
+            }
+            Self::TransactionStatusError(error) => {
+                Self::TransactionStatusError(TransactionStatusError {
+                    meta: Some(meta),
+                    ..error
+                })
+            }
+            Self::Internal(reason) => Self::InvalidResponse { reason, meta },
+            error => error,
+        }
+    }
 }
 
 /// Error response for the Pesapal API error
@@ -32,6 +204,9 @@ pub struct PesaPalErrorResponse {
     pub code: String,
     pub error_type: String,
     pub message: String,
+    /// Metadata of the response the error was read from
+    #[serde(skip)]
+    pub meta: Option<Box<ResponseMeta>>,
 }
 
 impl std::fmt::Display for PesaPalErrorResponse {
@@ -46,6 +221,31 @@ impl std::fmt::Display for PesaPalErrorResponse {
 /// Type alias for the result
 pub type PesaPalResult<T> = Result<T, PesaPalError>;
 
+/// Builder errors are reported as [`PesaPalError::ValidationError`]
+macro_rules! builder_errors {
+    ($($error:path),+ $(,)?) => {
+        $(
+            impl From<$error> for PesaPalError {
+                fn from(value: $error) -> Self {
+                    Self::ValidationError(value.to_string())
+                }
+            }
+        )+
+    };
+}
+
+builder_errors!(
+    crate::pesapal::refund::RefundBuilderError,
+    crate::pesapal::register_ipn::RegisterIPNBuilderError,
+    crate::pesapal::transaction_status::TransactionStatusBuilderError,
+);
+
+impl From<Vec<crate::BillingAddressError>> for PesaPalError {
+    fn from(value: Vec<crate::BillingAddressError>) -> Self {
+        Self::BillingAddressError(value)
+    }
+}
+
 impl From<serde_json::Error> for PesaPalError {
     fn from(value: serde_json::Error) -> Self {
         Self::Internal(value.to_string())
@@ -53,11 +253,14 @@ impl From<serde_json::Error> for PesaPalError {
 }
 
 /// Error response for the `TransactionStatus` Endpoint
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[non_exhaustive]
 pub struct TransactionStatusError {
     pub error_type: String,
     pub code: String,
     pub message: String,
     pub call_back_url: String,
+    /// Metadata of the response the error was read from
+    #[serde(skip)]
+    pub meta: Option<Box<ResponseMeta>>,
 }
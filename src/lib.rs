@@ -30,6 +30,9 @@
 //! use std::env;
 //! use dotenvy::dotenv;
 //!
+//! # #[cfg(not(feature = "reqwest"))]
+//! # fn main() {}
+//! # #[cfg(feature = "reqwest")]
 //! #[tokio::main]
 //! async fn main() {
 //!     dotenv().ok();
@@ -51,6 +54,9 @@
 //! use dotenvy::dotenv;
 //! use std::str::FromStr;
 //!
+//! # #[cfg(not(feature = "reqwest"))]
+//! # fn main() {}
+//! # #[cfg(feature = "reqwest")]
 //! #[tokio::main]
 //! async fn main() {
 //!     dotenv().ok();
@@ -62,6 +68,25 @@
 //!     );
 //! }
 //! ```
+//! The client can also be configured from the `PESAPAL_*` environment
+//! variables, or from a TOML / JSON file, see the [`config`] module. Every
+//! missing or invalid value is reported at once in a [`ConfigError`]. The
+//! currency, callback and cancellation URLs and IPN id of the configuration
+//! are used by the orders which don't set them.
+//!
+//! ```rust,no_run
+//! use pesapal::PesaPal;
+//!
+//! # #[cfg(not(feature = "reqwest"))]
+//! # fn main() {}
+//! # #[cfg(feature = "reqwest")]
+//! # fn main() -> pesapal::PesaPalResult<()> {
+//! // PESAPAL_CONSUMER_KEY, PESAPAL_CONSUMER_SECRET, PESAPAL_ENVIRONMENT, ...
+//! let client = PesaPal::from_env()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
 //! If you intend to use in production, you will need to provide the
 //! `CONSUMER_KEY` and `CONSUMER_SECRET` from the Pesapal
 //!
@@ -70,6 +95,9 @@
 //! use std::env;
 //! use dotenvy::dotenv;
 //!
+//! # #[cfg(not(feature = "reqwest"))]
+//! # fn main() {}
+//! # #[cfg(feature = "reqwest")]
 //! #[tokio::main]
 //! async fn main() {
 //!     dotenv().ok();
@@ -130,12 +158,20 @@
 //! }
 //! ```
 //!
+//! The customer is then sent to the payment page with
+//! `SubmitOrderResponse::redirect_response`, a `302 Found` response, or kept
+//! on your site with `SubmitOrderResponse::iframe`, see the helpers of
+//! [`SubmitOrderResponse`].
+//!
 //! * Refund - Sends refund request for a payment that was processed
 //! ```rust,no_run
 //! use pesapal::{PesaPal, Environment};
 //! use std::env;
 //! use dotenvy::dotenv;
 //!
+//! # #[cfg(not(feature = "reqwest"))]
+//! # fn main() {}
+//! # #[cfg(feature = "reqwest")]
 //! #[tokio::main]
 //! async fn main() {
 //!     dotenv().ok();
@@ -162,6 +198,12 @@
 //! }
 //! ```
 //!
+//!   Rejected refunds fail with [`PesaPalError::RefundError`], accepted ones
+//!   can be followed until the payment is reversed with
+//!   [`PesaPal::track_refund`]. See the `refund` module for the pre-flight
+//!   checks of the refund rules, and the [`refund_workflow`] module for the
+//!   maker-checker approval of refunds.
+//!
 //! * Register IPN URL - Register IPN URL
 //! ```rust,no_run
 //! use pesapal::{PesaPal, Environment};
@@ -169,6 +211,9 @@
 //! use dotenvy::dotenv;
 //! use pesapal::NotificationType;
 //!
+//! # #[cfg(not(feature = "reqwest"))]
+//! # fn main() {}
+//! # #[cfg(feature = "reqwest")]
 //! #[tokio::main]
 //! async fn main() {
 //!     dotenv().ok();
@@ -195,7 +240,7 @@
 //!
 //! * List IPN URL - List IPN URL
 //! ```rust,no_run,ignore
-//! use pesapal::{PesaPal, Environment};
+//! use pesapal::{PesaPal, Environment, NotificationType};
 //! use std::env;
 //! use dotenvy::dotenv;
 //! use pesapal::pesapal::list_ipn::IPNListResponse;
@@ -211,9 +256,18 @@
 //!  );
 //!
 //! let response: IPNListResponse = pesapal.list_ipn_urls().send().await.unwrap();
+//! let active_post_urls = response
+//!     .by_type(NotificationType::Post)
+//!     .filter(|ipn| ipn.is_active());
 //! }
 //! ```
 //!
+//! Entries `PesaPal` returns with an error are collected in
+//! `IPNListResponse::errors` instead of failing the whole list.
+//!
+//! * Ensure IPN URL - [`PesaPal::ensure_ipn`] registers the IPN URL only if
+//!   no active registration of it exists, to call on every deploy
+//!
 //! * Transaction Status - Transaction Status
 //! ```rust,no_run,ignore
 //! use pesapal::{PesaPal, Environment};
@@ -243,6 +297,122 @@
 //! }
 //! ```
 //!
+//! * Bulk Transaction Status - Checks many order tracking ids with bounded
+//!   concurrency, the results are available as a map or a stream
+//! ```rust,no_run,ignore
+//! let statuses = pesapal
+//!     .transaction_statuses(order_tracking_ids)
+//!     .concurrency(16)
+//!     .collect()
+//!     .await;
+//! ```
+//!
+//! Concurrent lookups of the same order tracking id, e.g. from the IPN handler
+//! and the callback page, are coalesced into a single request. A
+//! [`StatusCache`] can also be set with
+//! [`PesaPal::with_transaction_status_cache`] to serve the lookups made
+//! shortly after, Completed, Failed and Reversed statuses are cached longer
+//! as they never change.
+//!
+//! * Reconciliation - Compares the local order ledger with `PesaPal`, see the
+//!   [`reconcile`] module
+//! * Several merchants - [`PesaPalRegistry`] holds a client per merchant, each
+//!   with its own credentials, order defaults and access token, see the
+//!   [`registry`] module
+//! * Compile-time checked builders - the builders of the [`typestate`] module
+//!   only have a `send` method once every mandatory field is set
+//!
+//!### Response metadata
+//! Every request type also exposes a `send_with_meta` method which returns a
+//! [`PesaPalResponse`]. Next to the typed body it contains the HTTP status,
+//! headers, elapsed time, the `X-Request-Id` sent with the request and the raw
+//! JSON body, which is handy when raising support tickets with `PesaPal`.
+//!
+//! ```rust,no_run,ignore
+//! let response = pesapal
+//!     .transaction_status()
+//!     .order_tracking_id("example")
+//!     .build()
+//!     .unwrap()
+//!     .send_with_meta()
+//!     .await
+//!     .unwrap();
+//!
+//! println!("{} {} in {:?}", response.request_id, response.status, response.elapsed);
+//! println!("{}", response.raw_body);
+//! let status: TransactionStatusResponse = response.into_inner();
+//! ```
+//!
+//! Errors returned once `PesaPal` responded keep the same metadata as a
+//! [`ResponseMeta`], see [`PesaPalError::response_meta`]. A body which isn't
+//! the expected JSON is reported as [`PesaPalError::InvalidResponse`] together
+//! with its raw body.
+//!
+//!### Rate limiting
+//! `PesaPal` throttles bursts of requests. A client side token bucket can be
+//! set per endpoint, requests over the limit wait for their turn in order
+//! instead of failing. The time waited is reported in
+//! [`PesaPalResponse::rate_limit_wait`].
+//!
+//! ```rust,no_run,ignore
+//! use pesapal::{Endpoint, RateLimit};
+//!
+//! let pesapal = pesapal
+//!     .with_rate_limit(Endpoint::SubmitOrder, RateLimit::per_second(20))
+//!     .with_rate_limit(Endpoint::TransactionStatus, RateLimit::per_second(50));
+//! ```
+//!
+//!### Circuit breaker
+//! A [`CircuitBreaker`] stops calling `PesaPal` during an outage. Once too
+//! many calls fail in a row, or too large a share of the recent calls fail,
+//! every call fails fast with [`PesaPalError::CircuitOpen`]. After a cool down
+//! a few probe calls are let through and the circuit closes if they succeed.
+//!
+//! ```rust,no_run,ignore
+//! use pesapal::CircuitBreaker;
+//!
+//! let pesapal = pesapal.with_circuit_breaker(
+//!     CircuitBreaker::new()
+//!         .consecutive_failures(5)
+//!         .open_for(Duration::from_secs(30))
+//!         .on_state_change(|from, to| alert(format!("pesapal circuit {from} -> {to}"))),
+//! );
+//! ```
+//!
+//!### Cargo features
+//! * `reqwest` (default) - [`transport::ReqwestTransport`], the default
+//!   [`transport::HttpTransport`] used by [`PesaPal::new`]. Disable it and use
+//!   [`PesaPal::new_with_transport`] to plug in your own HTTP client.
+//! * `tracing` - Emits a [`tracing`](https://docs.rs/tracing) span for every
+//!   endpoint call and token refresh, with the endpoint name, environment,
+//!   merchant reference / tracking id, HTTP status and latency. Consumer
+//!   secrets, bearer tokens and billing contact details are never recorded,
+//!   only the last four characters of consumer keys are.
+//! * `metrics` - Emits metrics through the [`metrics`](https://docs.rs/metrics)
+//!   facade, install any recorder to collect them:
+//!   * `pesapal_requests_total` counter labelled with `endpoint`,
+//!     `environment`, `outcome` and `error_class`
+//!   * `pesapal_request_duration_seconds` histogram labelled with `endpoint`,
+//!     `environment` and `outcome`
+//!   * `pesapal_token_refreshes_total` counter labelled with `environment`,
+//!     `outcome` and `error_class`
+//!   * `pesapal_token_cache_hits_total` and `pesapal_token_cache_misses_total`
+//!     counters labelled with `environment`
+//!   * `pesapal_token_age_seconds` gauge labelled with `environment`
+//!   * `pesapal_rate_limit_wait_seconds` histogram labelled with `endpoint`
+//!     and `environment`
+//!   * `pesapal_circuit_transitions_total` counter labelled with `from` and
+//!     `to`
+//! * `blocking` - Synchronous client in the [`blocking`] module which mirrors
+//!   the async API and shares the same request and response types.
+//! * `toml` - [`PesaPalConfig::from_toml`] and TOML support in
+//!   [`PesaPalConfig::from_file`].
+//! * `cli` - `pesapal` binary with the `authenticate`, `submit-order`,
+//!   `transaction-status`, `refund`, `register-ipn-url` and `list-ipn-urls`
+//!   subcommands. Credentials are read from the `PESAPAL_CONSUMER_KEY`,
+//!   `PESAPAL_CONSUMER_SECRET` and `PESAPAL_ENVIRONMENT` variables or a TOML
+//!   file passed with `--config`. Run `pesapal --help` for the details.
+//!
 //! More will be added progressively, pull requests welcome
 //!
 //!## Author
@@ -255,22 +425,59 @@
 //!## License
 //! This project is MIT licensed
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod circuit_breaker;
+pub mod config;
+mod country_code;
+mod endpoint;
 #[deny(warnings)]
 mod environment;
 mod error;
 mod macros;
 mod pesapal;
+mod phone_number;
+mod rate_limit;
+pub mod reconcile;
+pub mod refund_workflow;
+pub mod registry;
+mod response;
+mod secret;
+mod telemetry;
+pub mod transport;
+pub mod typestate;
+mod validation;
 
+pub use circuit_breaker::{CircuitBreaker, CircuitState};
+pub use config::{ConfigError, OrderDefaults, PesaPalConfig};
+pub use country_code::{CountryCode, CountryCodeError};
+pub use endpoint::Endpoint;
 pub use environment::Environment;
 pub use error::{PesaPalError, PesaPalErrorResponse, PesaPalResult, TransactionStatusError};
+pub use phone_number::{PhoneNumber, PhoneNumberError};
+pub use rate_limit::RateLimit;
+pub use registry::PesaPalRegistry;
+pub use response::{PesaPalResponse, ResponseMeta};
+pub use secret::Secret;
+pub use validation::{FieldError, ValidationErrors};
 
-pub use crate::pesapal::list_ipn::{IPNList, IPNListResponse, ListIPN};
-pub use crate::pesapal::refund::{Refund, RefundRequest, RefundResponse};
-pub use crate::pesapal::register_ipn::{NotificationType, RegisterIPN, RegisterIPNResponse};
+pub use crate::pesapal::auth::{AccessToken, AuthenticationResponse};
+pub use crate::pesapal::billing_address::{
+    BillingAddress, BillingAddressBuilder, BillingAddressError, BillingAddressField,
+};
+pub use crate::pesapal::list_ipn::{IPNList, IPNListResponse, IpnEntryError, IpnStatus, ListIPN};
+pub use crate::pesapal::refund::{
+    Refund, RefundOutcome, RefundPreflight, RefundPreflightError, RefundRejection, RefundRequest,
+    RefundResponse, RefundStatus, RefundTracker,
+};
+pub use crate::pesapal::register_ipn::{
+    EnsuredIpn, NotificationType, RegisterIPN, RegisterIPNResponse,
+};
 pub use crate::pesapal::submit_order::{
-    BillingAddress, RedirectMode, SubmitOrder, SubmitOrderRequest, SubmitOrderResponse,
+    RedirectMode, SubmitOrder, SubmitOrderRequest, SubmitOrderResponse,
 };
 pub use crate::pesapal::transaction_status::{
-    StatusCode, TransactionStatus, TransactionStatusBuilder, TransactionStatusResponse,
+    StatusCache, StatusCode, TransactionStatus, TransactionStatusBuilder,
+    TransactionStatusResponse, TransactionStatuses,
 };
 pub use crate::pesapal::PesaPal;
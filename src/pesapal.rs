@@ -1,40 +1,90 @@
-mod auth;
+pub mod auth;
+pub mod billing_address;
+pub mod checkout;
 pub mod list_ipn;
 pub mod refund;
 pub mod register_ipn;
 pub mod submit_order;
 pub mod transaction_status;
 
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 use cached::Cached;
-use reqwest::Client as HttpClient;
-pub use submit_order::BillingAddress;
+use http::HeaderValue;
+use serde::de::DeserializeOwned;
 
 use self::auth::{AccessToken, AUTH_CACHE};
 use self::list_ipn::ListIPN;
-use self::refund::{Refund, RefundBuilder};
-use self::register_ipn::{RegisterIPN, RegisterIPNBuilder};
+use self::refund::{Refund, RefundBuilder, RefundTracker};
+use self::register_ipn::{EnsuredIpn, NotificationType, RegisterIPN, RegisterIPNBuilder};
 use self::submit_order::{SubmitOrder, SubmitOrderBuilder};
-use self::transaction_status::{TransactionStatus, TransactionStatusBuilder};
+use self::transaction_status::{
+    StatusCache, StatusLookups, TransactionStatus, TransactionStatusBuilder, TransactionStatuses,
+};
+use crate::circuit_breaker::{Breaker, CircuitBreaker, CircuitPermit, CircuitState};
+use crate::config::OrderDefaults;
+use crate::endpoint::Endpoint;
 use crate::environment::Environment;
 use crate::error::PesaPalResult;
-
-/// `PesaPal` package version
-static PESAPAL_PACKAGE_VERSION: &str = env!("CARGO_PKG_VERSION");
+use crate::rate_limit::{RateLimit, TokenBucket};
+use crate::reconcile::{LocalRecord, Reconciliation};
+use crate::response::{PesaPalResponse, ResponseMeta, REQUEST_ID_HEADER};
+use crate::secret::Secret;
+use crate::telemetry;
+use crate::transport::{HttpRequest, HttpTransport};
+use crate::PesaPalError;
 
 /// [`PesaPal`] This is the client struct which allows communication with
 /// the `PesaPal` services
+///
+/// The client is cheap to clone, all the clones share the same configuration
+/// and transport. The request builders own a handle of the client, hence the
+/// requests can be moved to spawned tasks or kept in a queue.
 #[derive(Debug, Clone)]
 pub struct PesaPal {
+    pub(crate) inner: Arc<PesaPalInner>,
+}
+
+/// Shared state of the [`PesaPal`] client
+#[derive(Clone)]
+pub(crate) struct PesaPalInner {
     /// Consumer Key - This is provided by the PesaPal
-    consumer_key: String,
+    pub(crate) consumer_key: String,
     /// Consumer Secret - This is provided by the PesaPal
-    consumer_secret: String,
+    pub(crate) consumer_secret: Secret,
     /// Environment which we are executing the PesaPal Services
     ///
     /// It can be either [Environment::Production] or [Environment::Sandbox]
     pub(crate) env: Environment,
-    /// Reqwest HTTP Client
-    pub(crate) http_client: HttpClient,
+    /// Transport used to send the HTTP requests
+    pub(crate) transport: Arc<dyn HttpTransport>,
+    /// Token buckets of the rate limited endpoints
+    pub(crate) rate_limits: HashMap<Endpoint, Arc<TokenBucket>>,
+    /// Circuit breaker guarding all the calls, if any
+    pub(crate) circuit_breaker: Option<Arc<Breaker>>,
+    /// In flight and cached transaction status lookups
+    pub(crate) status_lookups: Arc<StatusLookups>,
+    /// Values used by the orders which don't set them
+    pub(crate) defaults: OrderDefaults,
+}
+
+impl fmt::Debug for PesaPalInner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The consumer key is masked as in the tracing spans
+        f.debug_struct("PesaPalInner")
+            .field("consumer_key", &telemetry::mask(&self.consumer_key))
+            .field("consumer_secret", &self.consumer_secret)
+            .field("env", &self.env)
+            .field("transport", &self.transport)
+            .field("rate_limits", &self.rate_limits)
+            .field("circuit_breaker", &self.circuit_breaker)
+            .field("status_lookups", &self.status_lookups)
+            .field("defaults", &self.defaults)
+            .finish()
+    }
 }
 
 impl PesaPal {
@@ -48,21 +98,151 @@ impl PesaPal {
     ///       Environment::Production
     /// );
     /// ```
+    #[cfg(feature = "reqwest")]
     pub fn new<S: Into<String>>(consumer_key: S, consumer_secret: S, env: Environment) -> Self {
-        let http_client = HttpClient::builder()
-            .connect_timeout(std::time::Duration::from_secs(10))
-            .user_agent(format!("pesapal-rs @{PESAPAL_PACKAGE_VERSION}"))
-            .build()
-            .expect("Error building http client");
+        Self::new_with_transport(
+            consumer_key,
+            consumer_secret,
+            env,
+            crate::transport::ReqwestTransport::default(),
+        )
+    }
 
+    /// Constructs a new `PesaPal` Instance which sends the requests through
+    /// the given [`HttpTransport`]
+    ///
+    /// # Example
+    /// ```ignore
+    /// let pesapal: PesaPal = Pesapal::new_with_transport(
+    ///       std::env("CONSUMER_KEY").unwrap(),
+    ///       std::env("CONSUMER_SECRET").unwrap(),
+    ///       Environment::Production,
+    ///       ReqwestTransport::from_client(reqwest_client),
+    /// );
+    /// ```
+    pub fn new_with_transport<S: Into<String>>(
+        consumer_key: S,
+        consumer_secret: S,
+        env: Environment,
+        transport: impl HttpTransport + 'static,
+    ) -> Self {
         Self {
-            consumer_key: consumer_key.into(),
-            consumer_secret: consumer_secret.into(),
-            env,
-            http_client,
+            inner: Arc::new(PesaPalInner {
+                consumer_key: consumer_key.into(),
+                consumer_secret: Secret::new(consumer_secret),
+                env,
+                transport: Arc::new(transport),
+                rate_limits: HashMap::new(),
+                circuit_breaker: None,
+                status_lookups: Arc::new(StatusLookups::new(None)),
+                defaults: OrderDefaults::default(),
+            }),
         }
     }
 
+    /// Limits the rate of the requests sent to the `endpoint`
+    ///
+    /// Requests over the limit wait for their turn, in the order they were
+    /// sent, instead of failing. The time waited is reported in
+    /// [`PesaPalResponse::rate_limit_wait`]. The limit is shared by the clones
+    /// made after this call.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let pesapal = PesaPal::new(consumer_key, consumer_secret, Environment::Production)
+    ///     .with_rate_limit(Endpoint::SubmitOrder, RateLimit::per_second(20))
+    ///     .with_rate_limit(Endpoint::TransactionStatus, RateLimit::per_second(50).burst(10));
+    /// ```
+    #[must_use]
+    pub fn with_rate_limit(mut self, endpoint: Endpoint, limit: RateLimit) -> Self {
+        Arc::make_mut(&mut self.inner)
+            .rate_limits
+            .insert(endpoint, Arc::new(TokenBucket::new(limit)));
+        self
+    }
+
+    /// Sets the values used by the orders which don't set them
+    ///
+    /// # Example
+    /// ```ignore
+    /// let pesapal = PesaPal::new(consumer_key, consumer_secret, Environment::Production)
+    ///     .with_order_defaults(
+    ///         OrderDefaults::new()
+    ///             .currency("KES")
+    ///             .callback_url("https://example.com/callback")
+    ///             .notification_id(ipn_id),
+    ///     );
+    /// ```
+    #[must_use]
+    pub fn with_order_defaults(mut self, defaults: OrderDefaults) -> Self {
+        Arc::make_mut(&mut self.inner).defaults = defaults;
+        self
+    }
+
+    /// Guards all the calls, including the authentication, with a circuit
+    /// breaker
+    ///
+    /// While the circuit is open the calls fail fast with
+    /// [`PesaPalError::CircuitOpen`]. The breaker is shared by the clones made
+    /// after this call.
+    #[must_use]
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        Arc::make_mut(&mut self.inner).circuit_breaker =
+            Some(Arc::new(Breaker::new(circuit_breaker)));
+        self
+    }
+
+    /// Caches the transaction status responses
+    ///
+    /// Concurrent lookups of the same order are always coalesced into a
+    /// single request, the cache additionally serves the lookups made shortly
+    /// after. The cache is shared by the clones made after this call.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let pesapal = PesaPal::new(consumer_key, consumer_secret, Environment::Production)
+    ///     .with_transaction_status_cache(
+    ///         StatusCache::new()
+    ///             .pending_ttl(Duration::from_secs(1))
+    ///             .final_ttl(Duration::from_secs(3600)),
+    ///     );
+    /// ```
+    #[must_use]
+    pub fn with_transaction_status_cache(mut self, cache: StatusCache) -> Self {
+        Arc::make_mut(&mut self.inner).status_lookups = Arc::new(StatusLookups::new(Some(cache)));
+        self
+    }
+
+    /// State of the circuit breaker, if one is set
+    #[must_use]
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.inner
+            .circuit_breaker
+            .as_ref()
+            .map(|breaker| breaker.state())
+    }
+
+    /// Asks the circuit breaker, if any, to let a call through
+    pub(crate) fn circuit_permit(&self) -> PesaPalResult<Option<CircuitPermit<'_>>> {
+        self.inner
+            .circuit_breaker
+            .as_ref()
+            .map(|breaker| breaker.admit(Instant::now()))
+            .transpose()
+    }
+
+    /// Waits for the rate limiter of the `endpoint`, if any, and returns the
+    /// time waited
+    pub(crate) async fn throttle(&self, endpoint: Endpoint) -> Duration {
+        let Some(bucket) = self.inner.rate_limits.get(&endpoint) else {
+            return Duration::ZERO;
+        };
+
+        let wait = bucket.acquire().await;
+        telemetry::observe_rate_limit_wait(endpoint, &self.inner.env, wait);
+        wait
+    }
+
     /// # Pesapal Authentication
     ///
     /// Generate an access token which is used to authenticate Pesapal
@@ -81,30 +261,177 @@ impl PesaPal {
     ///
     /// # Errors
     /// [`PesaPalError::AuthenticationError`] - Incase the authentication fails
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "pesapal.auth",
+            skip_all,
+            err(Display),
+            fields(
+                endpoint = "auth",
+                environment = %self.inner.env,
+                consumer_key = %telemetry::mask(&self.inner.consumer_key),
+                cache_hit = tracing::field::Empty,
+                http.status = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+            )
+        )
+    )]
     pub async fn authenticate(&self) -> PesaPalResult<AccessToken> {
         // Check if the access token is already cached
-        if let Some(token) = AUTH_CACHE.lock().await.cache_get(&self.consumer_key) {
-            return Ok(token.clone());
+        if let Some(cached) = AUTH_CACHE.lock().await.cache_get(&self.token_cache_key()) {
+            telemetry::record("cache_hit", "true");
+            telemetry::observe_token_cache_hit(&self.inner.env, cached.issued_at.elapsed());
+            return Ok(cached.token.clone());
         }
+        telemetry::record("cache_hit", "false");
+        telemetry::observe_token_cache_miss(&self.inner.env);
 
         // Generate a new access token
-        let new_token = match auth::auth_prime_cache(self).await {
-            Ok(token) => token,
-            Err(e) => return Err(e),
-        };
+        let started = Instant::now();
+        let result = auth::auth_prime_cache(self).await;
+        telemetry::observe_request(
+            Endpoint::Auth,
+            &self.inner.env,
+            started.elapsed(),
+            result.as_ref().err(),
+        );
+        let new_token = result?;
 
         // Double-check if the access token is cached by another thread
-        if let Some(token) = AUTH_CACHE.lock().await.cache_get(&self.consumer_key) {
-            return Ok(token.clone());
+        if let Some(cached) = AUTH_CACHE.lock().await.cache_get(&self.token_cache_key()) {
+            return Ok(cached.token.clone());
         }
 
         // Cache the new token
         AUTH_CACHE
             .lock()
             .await
-            .cache_set(self.consumer_key.clone(), new_token.clone());
+            .cache_set(self.token_cache_key(), new_token.clone());
 
-        Ok(new_token)
+        Ok(new_token.token)
+    }
+
+    /// Key of the access token in the cache
+    ///
+    /// The tokens are cached per environment and consumer key, hence clients
+    /// of different merchants never share a token.
+    pub(crate) fn token_cache_key(&self) -> String {
+        format!("{}:{}", self.inner.env, self.inner.consumer_key)
+    }
+
+    /// Drops the cached access token, the next call authenticates again
+    pub(crate) async fn evict_token(&self) {
+        AUTH_CACHE
+            .lock()
+            .await
+            .cache_remove(&self.token_cache_key());
+    }
+
+    /// Sends the request and deserializes the body, keeping the HTTP
+    /// metadata of the call in a [`PesaPalResponse`]
+    ///
+    /// Each request is tagged with a unique id which is sent as the
+    /// `X-Request-Id` header, waits for the rate limiter of the `endpoint`, is
+    /// given an access token and then goes through the circuit breaker before
+    /// being sent. The
+    /// deserialized body is passed to `check` which maps error responses into
+    /// a [`PesaPalError`](crate::PesaPalError) or converts the wire format into
+    /// the returned type, the outcome is then recorded against the `endpoint`.
+    /// Errors raised once `PesaPal` responded keep the [`ResponseMeta`] of the
+    /// response.
+    pub(crate) async fn execute<R, T, F>(
+        &self,
+        endpoint: Endpoint,
+        mut request: HttpRequest,
+        check: F,
+    ) -> PesaPalResult<PesaPalResponse<T>>
+    where
+        R: DeserializeOwned,
+        F: FnOnce(R) -> PesaPalResult<T>,
+    {
+        let request_id = ulid::Ulid::new().to_string();
+        let rate_limit_wait = self.throttle(endpoint).await;
+        // Fetched once the rate limiter let the request through, a long wait
+        // could otherwise outlast the token
+        request = request.bearer_auth(self.authenticate().await?.expose_secret())?;
+        // Taken once the rate limiter let the request through, a half-open
+        // probe slot is only held while a request is in flight
+        let permit = match self.circuit_permit() {
+            Ok(permit) => permit,
+            Err(error) => {
+                telemetry::observe_request(endpoint, &self.inner.env, Duration::ZERO, Some(&error));
+                return Err(error);
+            }
+        };
+        let started = Instant::now();
+
+        let result = async {
+            request.headers.insert(
+                REQUEST_ID_HEADER,
+                HeaderValue::from_str(&request_id)
+                    .map_err(|e| PesaPalError::Internal(e.to_string()))?,
+            );
+
+            let response = self.inner.transport.send(request).await;
+            if let Some(permit) = permit {
+                permit.record(&response);
+            }
+            let response = response?;
+            let elapsed = started.elapsed();
+            telemetry::record_response(response.status, elapsed);
+
+            let (raw_body, value) = match String::from_utf8(response.body) {
+                Ok(raw_body) => {
+                    let value = serde_json::from_str(&raw_body).map_err(|e| e.to_string());
+                    (raw_body, value)
+                }
+                Err(e) => (
+                    String::from_utf8_lossy(e.as_bytes()).into_owned(),
+                    Err(e.to_string()),
+                ),
+            };
+            let meta = ResponseMeta {
+                status: response.status,
+                headers: response.headers,
+                elapsed,
+                rate_limit_wait,
+                request_id,
+                raw_body,
+            };
+            let value = match value {
+                Ok(value) => value,
+                Err(reason) => {
+                    return Err(PesaPalError::InvalidResponse {
+                        reason,
+                        meta: Box::new(meta),
+                    })
+                }
+            };
+
+            match check(value) {
+                Ok(body) => Ok(PesaPalResponse {
+                    body,
+                    status: meta.status,
+                    headers: meta.headers,
+                    elapsed: meta.elapsed,
+                    rate_limit_wait: meta.rate_limit_wait,
+                    request_id: meta.request_id,
+                    raw_body: meta.raw_body,
+                }),
+                Err(error) => Err(error.with_meta(meta)),
+            }
+        }
+        .await;
+
+        telemetry::observe_request(
+            endpoint,
+            &self.inner.env,
+            started.elapsed(),
+            result.as_ref().err(),
+        );
+
+        result
     }
 
     /// # Submit Order Builder
@@ -151,7 +478,7 @@ impl PesaPal {
     /// ```
     #[must_use]
     pub fn submit_order(&self) -> SubmitOrderBuilder {
-        SubmitOrder::builder(self)
+        SubmitOrder::builder(self.clone())
     }
 
     /// # Refund Payment Builder
@@ -191,7 +518,21 @@ impl PesaPal {
     /// ```
     #[must_use]
     pub fn refund(&self) -> RefundBuilder {
-        Refund::builder(self)
+        Refund::builder(self.clone())
+    }
+
+    /// Follows the refund of the payment with the `order_tracking_id`
+    ///
+    /// A [`RefundTracker`] checks the status of the original payment, which
+    /// becomes REVERSED once the money is sent back.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let status = pesapal.track_refund(order_tracking_id).wait().await?;
+    /// ```
+    #[must_use]
+    pub fn track_refund(&self, order_tracking_id: impl Into<String>) -> RefundTracker {
+        RefundTracker::new(self.clone(), order_tracking_id.into())
     }
 
     /// Register IPN URL builder
@@ -232,7 +573,7 @@ impl PesaPal {
     /// unwrap();
     #[must_use]
     pub fn register_ipn_url(&self) -> RegisterIPNBuilder {
-        RegisterIPN::builder(self)
+        RegisterIPN::builder(self.clone())
     }
 
     /// List IPN URL builder
@@ -266,8 +607,37 @@ impl PesaPal {
     ///
     /// ```
     #[must_use]
-    pub const fn list_ipn_urls(&self) -> ListIPN {
-        ListIPN::new(self)
+    pub fn list_ipn_urls(&self) -> ListIPN {
+        ListIPN::new(self.clone())
+    }
+
+    /// Registers the IPN URL unless it already is
+    ///
+    /// The IPN URLs of the merchant are listed first, the id of an active one
+    /// with the same URL and notification type is returned. URLs are compared
+    /// regardless of the case and of trailing slashes. Otherwise the URL is
+    /// registered, [`EnsuredIpn::created`] tells which one happened. An IPN
+    /// listed without a notification type is never reused.
+    ///
+    /// Two calls running at the same time may both register the URL.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let ipn = pesapal
+    ///     .ensure_ipn("https://example.com/pesapal/ipn", NotificationType::Post)
+    ///     .await?;
+    /// let pesapal = pesapal.with_order_defaults(OrderDefaults::new().notification_id(ipn.ipn_id));
+    /// ```
+    ///
+    /// # Errors
+    /// [`PesaPalError::RegisterIPNError`] - Incase the registration fails,
+    /// or any error of listing the IPN URLs
+    pub async fn ensure_ipn(
+        &self,
+        url: impl AsRef<str>,
+        notification_type: NotificationType,
+    ) -> PesaPalResult<EnsuredIpn> {
+        register_ipn::ensure(self, url.as_ref(), notification_type).await
     }
 
     /// Transaction Status builder
@@ -304,6 +674,71 @@ impl PesaPal {
     /// ```
     #[must_use]
     pub fn transaction_status(&self) -> TransactionStatusBuilder {
-        TransactionStatus::builder(self)
+        TransactionStatus::builder(self.clone())
+    }
+
+    /// Bulk transaction status lookup
+    ///
+    /// Creates a [`TransactionStatuses`] which checks the status of all the
+    /// `order_tracking_ids` with bounded concurrency. The results are
+    /// available as a stream or collected into a map, a failed lookup doesn't
+    /// stop the others.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let statuses = pesapal
+    ///     .transaction_statuses(["tracking-id-1", "tracking-id-2"])
+    ///     .concurrency(16)
+    ///     .collect()
+    ///     .await;
+    ///
+    /// for (order_tracking_id, status) in statuses {
+    ///     match status {
+    ///         Ok(status) => println!("{order_tracking_id}: {:?}", status.status_code),
+    ///         Err(error) => eprintln!("{order_tracking_id}: {error}"),
+    ///     }
+    /// }
+    /// ```
+    #[must_use]
+    pub fn transaction_statuses<I>(&self, order_tracking_ids: I) -> TransactionStatuses
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        TransactionStatuses::new(self.clone(), order_tracking_ids)
+    }
+
+    /// Reconciles the local order ledger with `PesaPal`
+    ///
+    /// Creates a [`Reconciliation`] which checks the status of each record
+    /// and compares the merchant reference, amount, currency and status. See
+    /// the [`reconcile`](crate::reconcile) module.
+    #[must_use]
+    pub fn reconcile<I>(&self, records: I) -> Reconciliation
+    where
+        I: IntoIterator<Item = LocalRecord>,
+    {
+        Reconciliation::new(self.clone(), records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn test_debug_masks_the_credentials() {
+        let client = PesaPal::new_with_transport(
+            "debug-consumer-key",
+            "debug-consumer-secret",
+            Environment::Sandbox,
+            MockTransport::new(),
+        );
+
+        let debug = format!("{client:?}");
+        assert!(debug.contains("****-key"), "{debug}");
+        assert!(!debug.contains("debug-consumer"), "{debug}");
     }
 }
@@ -1,18 +1,24 @@
+use std::time::Instant;
+
 use cached::proc_macro::cached;
 use cached::TimedSizedCache;
 use chrono::{DateTime, NaiveDateTime, Utc};
+use http::{HeaderValue, Method};
 use serde::{Deserialize, Deserializer};
 use serde_aux::prelude::{deserialize_default_from_null, deserialize_number_from_string};
 use serde_json::json;
 
-use crate::{PesaPal, PesaPalError, PesaPalErrorResponse};
+use crate::response::{ResponseMeta, REQUEST_ID_HEADER};
+use crate::secret::Secret;
+use crate::transport::HttpRequest;
+use crate::{telemetry, Endpoint, PesaPal, PesaPalError, PesaPalErrorResponse};
 
 /// Response returned from the authentication function
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AuthenticationResponse {
     /// Access token which is used as the Bearer-Auth-Token
-    pub token: String,
+    pub token: Secret,
     #[serde(deserialize_with = "deserialize_utc_from_string")]
     /// Expiry date of the token
     pub expiry_date: DateTime<Utc>,
@@ -47,40 +53,90 @@ where
 }
 
 /// Access token which is cached
-pub type AccessToken = String;
+///
+/// Read it with [`Secret::expose_secret`] when setting the `Authorization`
+/// header.
+pub type AccessToken = Secret;
+
+/// Access token along with the time it was issued
+#[derive(Debug, Clone)]
+pub(crate) struct CachedToken {
+    pub(crate) token: AccessToken,
+    pub(crate) issued_at: Instant,
+}
+
+/// Number of merchants whose access tokens are cached
+pub(crate) const AUTH_CACHE_SIZE: usize = 256;
 
 #[cached(
     name = "AUTH_CACHE",
-    type = "TimedSizedCache<String,AccessToken>",
-    create = "{ TimedSizedCache::with_size_and_lifespan_and_refresh(1, 300,    true) }",
-    convert = r#"{ format!("{}", client.consumer_key) }"#,
+    type = "TimedSizedCache<String,CachedToken>",
+    create = "{ TimedSizedCache::with_size_and_lifespan_and_refresh(AUTH_CACHE_SIZE, 300, true) }",
+    convert = r#"{ client.token_cache_key() }"#,
     result = true
 )]
-pub async fn auth(client: &PesaPal) -> Result<AccessToken, PesaPalError> {
-    let url = format!("{}/api/Auth/RequestToken", client.env.base_url());
+pub(crate) async fn auth(client: &PesaPal) -> Result<CachedToken, PesaPalError> {
+    let url = format!("{}/api/Auth/RequestToken", client.inner.env.base_url());
     let payload = json!({
-        "consumer_key": client.consumer_key,
-        "consumer_secret": client.consumer_secret
+        "consumer_key": client.inner.consumer_key,
+        "consumer_secret": client.inner.consumer_secret.expose_secret()
     });
 
-    let response = client.http_client.post(url).json(&payload).send().await?;
-
-    if response.status().is_success() {
-        let value: AuthenticationResponse = response.json::<_>().await?;
-        return Ok(value.token);
+    let request_id = ulid::Ulid::new().to_string();
+    let mut request = HttpRequest::new(Method::POST, &url)?.json(&payload)?;
+    request.headers.insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_str(&request_id).map_err(|e| PesaPalError::Internal(e.to_string()))?,
+    );
+
+    let rate_limit_wait = client.throttle(Endpoint::Auth).await;
+    let permit = client.circuit_permit()?;
+    let started = Instant::now();
+    let response = client.inner.transport.send(request).await;
+    if let Some(permit) = permit {
+        permit.record(&response);
+    }
+    let response = response?;
+    let elapsed = started.elapsed();
+    telemetry::record_response(response.status, elapsed);
+
+    let body = if response.status.is_success() {
+        serde_json::from_slice::<AuthenticationResponse>(&response.body).map(Ok)
+    } else {
+        serde_json::from_slice::<PesaPalErrorResponse>(&response.body).map(Err)
+    };
+    let meta = || ResponseMeta {
+        status: response.status,
+        headers: response.headers.clone(),
+        elapsed,
+        rate_limit_wait,
+        request_id: request_id.clone(),
+        raw_body: String::from_utf8_lossy(&response.body).into_owned(),
+    };
+
+    match body {
+        Ok(Ok(value)) => Ok(CachedToken {
+            token: value.token,
+            issued_at: started,
+        }),
+        Ok(Err(error)) => Err(PesaPalError::AuthenticationError(error).with_meta(meta())),
+        Err(e) => Err(PesaPalError::InvalidResponse {
+            reason: e.to_string(),
+            meta: Box::new(meta()),
+        }),
     }
-
-    let err = response.json().await?;
-    Err(PesaPalError::AuthenticationError(err))
 }
 
 #[cfg(test)]
 mod tests {
 
+    #[cfg(feature = "reqwest")]
     use cached::Cached;
+    #[cfg(feature = "reqwest")]
     use dotenvy::dotenv;
 
     use super::*;
+    #[cfg(feature = "reqwest")]
     use crate::Environment;
 
     #[test]
@@ -108,6 +164,26 @@ mod tests {
         assert_eq!(response.expiry_date, expected_datetime);
     }
 
+    #[test]
+    fn test_secrets_are_redacted() {
+        let response: AuthenticationResponse = serde_json::from_str(
+            r#"{"expiryDate": "2021-08-26T12:29:30.5177702Z", "token": "bearer-token",
+                "status": "200", "message": "success", "error": null}"#,
+        )
+        .unwrap();
+        assert!(!format!("{response} {response:?}").contains("bearer-token"));
+        assert_eq!(response.token.expose_secret(), "bearer-token");
+
+        let client = PesaPal::new_with_transport(
+            "consumer-key",
+            "consumer-secret",
+            crate::Environment::Sandbox,
+            crate::transport::mock::MockTransport::new(),
+        );
+        assert!(!format!("{client:?} {client:#?}").contains("consumer-secret"));
+    }
+
+    #[cfg(feature = "reqwest")]
     #[tokio::test]
     async fn test_cached_access_token() {
         dotenv().ok();
@@ -121,8 +197,8 @@ mod tests {
 
         let mut cache = AUTH_CACHE.lock().await;
 
-        assert!(cache.cache_get(&client.consumer_key).is_some());
+        assert!(cache.cache_get(&client.token_cache_key()).is_some());
         assert_eq!(cache.cache_hits().unwrap(), 1);
-        assert_eq!(cache.cache_capacity().unwrap(), 1);
+        assert_eq!(cache.cache_capacity().unwrap(), AUTH_CACHE_SIZE);
     }
 }
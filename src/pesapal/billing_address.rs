@@ -0,0 +1,598 @@
+//! Billing address of the customer paying an order
+//!
+//! The address can be built field by field with [`BillingAddress::builder`],
+//! which checks the values against the limits of `PesaPal` before any request
+//! is sent.
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::error::PesaPalResult;
+use crate::{CountryCode, CountryCodeError, PhoneNumber, PhoneNumberError};
+
+/// Longest first, middle and last names
+const NAME_MAX_LENGTH: usize = 50;
+/// Longest address lines
+const LINE_MAX_LENGTH: usize = 100;
+/// Longest city name
+const CITY_MAX_LENGTH: usize = 50;
+/// Longest state, `PesaPal` expects its code
+const STATE_MAX_LENGTH: usize = 3;
+/// Longest postal and zip codes
+const POSTAL_CODE_MAX_LENGTH: usize = 10;
+/// Longest email address, as per RFC 5321
+const EMAIL_MAX_LENGTH: usize = 254;
+
+/// The billing address of a customer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Default)]
+pub struct BillingAddress {
+    /// customer's email address.
+    ///
+    /// This is Optional if phone number has been provided
+    /// Otherwise mandatory
+    ///
+    /// The BillingAddress will fail if this is not met
+    pub email_address: Option<String>,
+    /// Customer's Phone Number
+    ///
+    /// This is optional if email address has been provided,
+    /// Otherwise mandatory
+    ///
+    /// It is sent in its local form, see [`PhoneNumber`]
+    pub phone_number: Option<PhoneNumber>,
+    /// Country of the customer
+    pub country_code: Option<CountryCode>,
+    /// Customer's first name
+    pub first_name: Option<String>,
+    /// Customer's middle name
+    pub middle_name: Option<String>,
+    /// Customer's last name
+    pub last_name: Option<String>,
+    // Main Address
+    pub line_1: Option<String>,
+    // Alternative Address
+    pub line_2: Option<String>,
+    /// Customer's City
+    pub city: Option<String>,
+    /// Customer's state
+    ///
+    /// Maximum: three character long
+    pub state: Option<String>,
+    // Customer's postal code
+    pub postal_code: Option<String>,
+    /// Customer's zip code
+    pub zip_code: Option<String>,
+}
+
+impl BillingAddress {
+    /// Create a new billing address
+    ///
+    /// Either the phone number or the email must be provided,
+    /// otherwise the request will fail.
+    ///
+    /// # Returns
+    /// [`BillingAddress`] instance
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::BillingAddressError`](crate::PesaPalError::BillingAddressError) if neither the email nor the
+    /// phone number is provided, or the email isn't valid
+    pub fn new(phone_number: Option<PhoneNumber>, email: Option<String>) -> PesaPalResult<Self> {
+        let address = Self {
+            email_address: email,
+            phone_number,
+            ..Default::default()
+        };
+        address.validate()?;
+
+        Ok(address)
+    }
+
+    /// Creates a [`BillingAddressBuilder`], whose values are validated when
+    /// the address is built
+    ///
+    /// # Example
+    /// ```
+    /// use pesapal::{BillingAddress, CountryCode};
+    ///
+    /// let address = BillingAddress::builder()
+    ///     .country_code("ke")
+    ///     .phone_number("0712 345 678")
+    ///     .first_name("Jane")
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(address.country_code, Some(CountryCode::KE));
+    /// assert_eq!(address.phone_number.unwrap().e164(), "+254712345678");
+    /// ```
+    pub fn builder() -> BillingAddressBuilder {
+        BillingAddressBuilder::default()
+    }
+
+    /// Checks the address against the rules of `PesaPal`
+    ///
+    /// # Errors
+    ///
+    /// Every [`BillingAddressError`] found
+    pub fn validate(&self) -> Result<(), Vec<BillingAddressError>> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Every rule of `PesaPal` the address breaks
+    pub(crate) fn problems(&self) -> Vec<BillingAddressError> {
+        let mut problems = Vec::new();
+        if self.email_address.is_none() && self.phone_number.is_none() {
+            problems.push(BillingAddressError::MissingContact);
+        }
+        if let (Some(phone_number), Some(country)) = (&self.phone_number, self.country_code) {
+            if phone_number.country_code() != country {
+                problems.push(BillingAddressError::PhoneCountryMismatch {
+                    phone: phone_number.country_code(),
+                    country,
+                });
+            }
+        }
+        if let Some(email) = self.email_address.as_deref().filter(|e| !is_valid_email(e)) {
+            problems.push(BillingAddressError::InvalidEmail(email.to_string()));
+        }
+
+        let limits = [
+            (
+                BillingAddressField::FirstName,
+                &self.first_name,
+                NAME_MAX_LENGTH,
+            ),
+            (
+                BillingAddressField::MiddleName,
+                &self.middle_name,
+                NAME_MAX_LENGTH,
+            ),
+            (
+                BillingAddressField::LastName,
+                &self.last_name,
+                NAME_MAX_LENGTH,
+            ),
+            (BillingAddressField::Line1, &self.line_1, LINE_MAX_LENGTH),
+            (BillingAddressField::Line2, &self.line_2, LINE_MAX_LENGTH),
+            (BillingAddressField::City, &self.city, CITY_MAX_LENGTH),
+            (BillingAddressField::State, &self.state, STATE_MAX_LENGTH),
+            (
+                BillingAddressField::PostalCode,
+                &self.postal_code,
+                POSTAL_CODE_MAX_LENGTH,
+            ),
+            (
+                BillingAddressField::ZipCode,
+                &self.zip_code,
+                POSTAL_CODE_MAX_LENGTH,
+            ),
+        ];
+        for (field, value, max) in limits {
+            let length = value.as_deref().map_or(0, |value| value.chars().count());
+            if length > max {
+                problems.push(BillingAddressError::TooLong { field, length, max });
+            }
+        }
+
+        problems
+    }
+}
+
+/// Whether `email` looks like a deliverable address: a local part and a
+/// domain of at least two labels, without whitespace
+fn is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    let valid_local = !local.is_empty()
+        && local.len() <= 64
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..");
+    let valid_domain = domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        });
+
+    email.len() <= EMAIL_MAX_LENGTH
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        && valid_local
+        && valid_domain
+}
+
+/// Field of a [`BillingAddress`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum BillingAddressField {
+    EmailAddress,
+    PhoneNumber,
+    CountryCode,
+    FirstName,
+    MiddleName,
+    LastName,
+    Line1,
+    Line2,
+    City,
+    State,
+    PostalCode,
+    ZipCode,
+}
+
+impl BillingAddressField {
+    /// Name of the field in the request sent to `PesaPal`
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::EmailAddress => "email_address",
+            Self::PhoneNumber => "phone_number",
+            Self::CountryCode => "country_code",
+            Self::FirstName => "first_name",
+            Self::MiddleName => "middle_name",
+            Self::LastName => "last_name",
+            Self::Line1 => "line_1",
+            Self::Line2 => "line_2",
+            Self::City => "city",
+            Self::State => "state",
+            Self::PostalCode => "postal_code",
+            Self::ZipCode => "zip_code",
+        }
+    }
+}
+
+impl fmt::Display for BillingAddressField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Rule of `PesaPal` broken by a [`BillingAddress`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum BillingAddressError {
+    /// Neither the email address nor the phone number is provided
+    #[error("either the email address or the phone number must be provided")]
+    MissingContact,
+    /// The email address isn't valid
+    #[error("email_address {0:?} isn't a valid email address")]
+    InvalidEmail(String),
+    /// The phone number isn't valid
+    #[error("phone_number : {0}")]
+    InvalidPhoneNumber(PhoneNumberError),
+    /// The country isn't an ISO 3166-1 alpha-2 code
+    #[error("country_code : {0}")]
+    InvalidCountryCode(CountryCodeError),
+    /// The phone number is from another country than the address
+    #[error("phone_number is a {phone} number, the country_code is {country}")]
+    PhoneCountryMismatch {
+        /// Country of the phone number
+        phone: CountryCode,
+        /// Country of the address
+        country: CountryCode,
+    },
+    /// The value is longer than what `PesaPal` accepts
+    #[error("{field} is {length} characters long, at most {max} are allowed")]
+    TooLong {
+        field: BillingAddressField,
+        length: usize,
+        max: usize,
+    },
+}
+
+impl BillingAddressError {
+    /// Short, stable name of the broken rule
+    #[must_use]
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::MissingContact => "missing_contact",
+            Self::InvalidEmail(_) => "invalid_email",
+            Self::InvalidPhoneNumber(_) => "invalid_phone_number",
+            Self::InvalidCountryCode(_) => "invalid_country_code",
+            Self::PhoneCountryMismatch { .. } => "phone_country_mismatch",
+            Self::TooLong { .. } => "too_long",
+        }
+    }
+
+    /// Field the error is about, [`BillingAddressError::MissingContact`] is
+    /// about both the email address and the phone number and has none
+    #[must_use]
+    pub const fn field(&self) -> Option<BillingAddressField> {
+        match self {
+            Self::MissingContact => None,
+            Self::InvalidEmail(_) => Some(BillingAddressField::EmailAddress),
+            Self::InvalidPhoneNumber(_) => Some(BillingAddressField::PhoneNumber),
+            Self::InvalidCountryCode(_) => Some(BillingAddressField::CountryCode),
+            Self::PhoneCountryMismatch { .. } => Some(BillingAddressField::PhoneNumber),
+            Self::TooLong { field, .. } => Some(*field),
+        }
+    }
+}
+
+/// Every error of `errors`, separated by semicolons
+pub(crate) fn describe(errors: &[BillingAddressError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Builder of a validated [`BillingAddress`]
+///
+/// Values are trimmed and empty ones are left out. Phone numbers in their
+/// local form are read as numbers of the country of the address.
+#[derive(Debug, Clone, Default)]
+pub struct BillingAddressBuilder {
+    email_address: Option<String>,
+    phone_number: Option<String>,
+    country_code: Option<String>,
+    first_name: Option<String>,
+    middle_name: Option<String>,
+    last_name: Option<String>,
+    line_1: Option<String>,
+    line_2: Option<String>,
+    city: Option<String>,
+    state: Option<String>,
+    postal_code: Option<String>,
+    zip_code: Option<String>,
+}
+
+macro_rules! setters {
+    ($($(#[$doc:meta])* $field:ident),+ $(,)?) => {
+        $(
+            $(#[$doc])*
+            #[must_use]
+            pub fn $field(mut self, $field: impl ToString) -> Self {
+                self.$field = Some($field.to_string());
+                self
+            }
+        )+
+    };
+}
+
+impl BillingAddressBuilder {
+    setters!(
+        /// Customer's email address, mandatory without a phone number
+        email_address,
+        /// Customer's phone number, mandatory without an email address
+        ///
+        /// See [`PhoneNumber`] for the accepted formats.
+        phone_number,
+        /// ISO 3166-1 alpha-2 code of the customer's country, either a
+        /// [`CountryCode`] or its two letters
+        country_code,
+        /// Customer's first name
+        first_name,
+        /// Customer's middle name
+        middle_name,
+        /// Customer's last name
+        last_name,
+        /// Main address
+        line_1,
+        /// Alternative address
+        line_2,
+        /// Customer's city
+        city,
+        /// Customer's state code, three characters at most
+        state,
+        /// Customer's postal code
+        postal_code,
+        /// Customer's zip code
+        zip_code,
+    );
+
+    /// Builds the address
+    ///
+    /// # Errors
+    ///
+    /// Every [`BillingAddressError`] found
+    pub fn build(self) -> Result<BillingAddress, Vec<BillingAddressError>> {
+        let mut problems = Vec::new();
+
+        let country = present(self.country_code).map(|code| code.parse::<CountryCode>());
+        let invalid_country = matches!(country, Some(Err(_)));
+        let country_code = match country {
+            Some(Ok(country)) => Some(country),
+            Some(Err(e)) => {
+                problems.push(BillingAddressError::InvalidCountryCode(e));
+                None
+            }
+            None => None,
+        };
+        let phone = present(self.phone_number);
+        let phone_given = phone.is_some();
+        let phone_number = phone.and_then(|phone| {
+            let parsed = match country_code {
+                Some(country) => PhoneNumber::parse_local(&phone, country),
+                None => PhoneNumber::parse(&phone),
+            };
+            match parsed {
+                Ok(phone_number) => Some(phone_number),
+                // the country that was given isn't valid, it is already reported
+                Err(PhoneNumberError::MissingCountry(_)) if invalid_country => None,
+                Err(e) => {
+                    problems.push(BillingAddressError::InvalidPhoneNumber(e));
+                    None
+                }
+            }
+        });
+
+        let address = BillingAddress {
+            email_address: present(self.email_address),
+            phone_number,
+            country_code,
+            first_name: present(self.first_name),
+            middle_name: present(self.middle_name),
+            last_name: present(self.last_name),
+            line_1: present(self.line_1),
+            line_2: present(self.line_2),
+            city: present(self.city),
+            state: present(self.state),
+            postal_code: present(self.postal_code),
+            zip_code: present(self.zip_code),
+        };
+        // a phone number that couldn't be parsed isn't missing
+        problems.extend(address.problems().into_iter().filter(|problem| {
+            !(phone_given && matches!(problem, BillingAddressError::MissingContact))
+        }));
+
+        if problems.is_empty() {
+            Ok(address)
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+/// Trimmed value, `None` if it is empty
+fn present(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PesaPalError;
+
+    #[test]
+    fn test_build_billing_address() {
+        let address = BillingAddress::builder()
+            .email_address(" jane@example.co.ke ")
+            .phone_number("+254 712 345 678")
+            .country_code(CountryCode::KE)
+            .middle_name("")
+            .state("NBO")
+            .build()
+            .unwrap();
+
+        assert_eq!(address.email_address.as_deref(), Some("jane@example.co.ke"));
+        assert_eq!(
+            address.phone_number.unwrap().country_code(),
+            CountryCode::KE
+        );
+        assert_eq!(address.middle_name, None);
+
+        let address = BillingAddress::builder()
+            .phone_number("+256 712 345 678")
+            .build()
+            .unwrap();
+        assert_eq!(
+            address.phone_number.unwrap().country_code(),
+            CountryCode::UG
+        );
+    }
+
+    #[test]
+    fn test_billing_address_errors() {
+        let error = |builder: BillingAddressBuilder| {
+            let mut errors = builder.build().unwrap_err();
+            assert_eq!(errors.len(), 1, "{errors:?}");
+            errors.remove(0)
+        };
+
+        assert_eq!(
+            error(BillingAddress::builder().first_name("Jane")),
+            BillingAddressError::MissingContact
+        );
+        for email in [
+            "jane",
+            "jane@example",
+            "jane doe@example.com",
+            ".jane@example.com",
+        ] {
+            assert_eq!(
+                error(BillingAddress::builder().email_address(email)),
+                BillingAddressError::InvalidEmail(email.to_string())
+            );
+        }
+        assert!(matches!(
+            error(BillingAddress::builder().phone_number("0712345678")),
+            BillingAddressError::InvalidPhoneNumber(PhoneNumberError::MissingCountry(_))
+        ));
+        assert_eq!(
+            error(
+                BillingAddress::builder()
+                    .email_address("jane@example.com")
+                    .country_code("Kenya")
+            )
+            .field(),
+            Some(BillingAddressField::CountryCode)
+        );
+        assert_eq!(
+            error(
+                BillingAddress::builder()
+                    .email_address("jane@example.com")
+                    .state("Nairobi")
+            ),
+            BillingAddressError::TooLong {
+                field: BillingAddressField::State,
+                length: 7,
+                max: 3
+            }
+        );
+        assert_eq!(
+            error(
+                BillingAddress::builder()
+                    .phone_number("+256 712 345 678")
+                    .country_code(CountryCode::KE)
+            ),
+            BillingAddressError::PhoneCountryMismatch {
+                phone: CountryCode::UG,
+                country: CountryCode::KE
+            }
+        );
+        assert!(BillingAddress::new(None, Some("jane@example.com".to_string())).is_ok());
+        assert!(matches!(
+            BillingAddress::new(None, None),
+            Err(PesaPalError::BillingAddressError(errors))
+                if errors == [BillingAddressError::MissingContact]
+        ));
+    }
+
+    #[test]
+    fn test_billing_address_reports_every_error() {
+        let errors = BillingAddress::builder()
+            .country_code("Kenya")
+            .phone_number("+254 12")
+            .email_address("jane")
+            .state("Nairobi")
+            .build()
+            .unwrap_err();
+
+        let fields = errors
+            .iter()
+            .map(BillingAddressError::field)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            [
+                Some(BillingAddressField::CountryCode),
+                Some(BillingAddressField::PhoneNumber),
+                Some(BillingAddressField::EmailAddress),
+                Some(BillingAddressField::State),
+            ]
+        );
+        assert!(PesaPalError::from(errors)
+            .to_string()
+            .starts_with("invalid billing address : country_code : "));
+
+        // a number in its local form isn't reported again when the country is invalid
+        let errors = BillingAddress::builder()
+            .country_code("Kenya")
+            .phone_number("0712345678")
+            .build()
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field(), Some(BillingAddressField::CountryCode));
+    }
+}
@@ -0,0 +1,174 @@
+//! Sending the customer to the payment page of an order
+//!
+//! The `redirect_url` of a [`SubmitOrderResponse`] is either redirected to
+//! or loaded in an iframe within your site.
+//!
+//! ```rust,ignore
+//! let response = order.send().await?;
+//!
+//! // Send the customer to PesaPal
+//! let redirect: http::Response<()> = response.redirect_response()?;
+//!
+//! // Or keep them on your page
+//! let html = response.iframe(RedirectMode::TopWindow)?;
+//! ```
+
+use http::{header, HeaderValue, Response, StatusCode};
+use url::Url;
+
+use super::submit_order::{RedirectMode, SubmitOrderResponse};
+use crate::error::{PesaPalError, PesaPalResult};
+
+/// Width of the iframe recommended by `PesaPal`
+const IFRAME_WIDTH: &str = "100%";
+/// Height of the iframe recommended by `PesaPal`
+const IFRAME_HEIGHT: &str = "700px";
+
+impl SubmitOrderResponse {
+    /// Payment page of the order
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::InvalidRedirectUrl`] if the `redirect_url` returned by
+    /// `PesaPal` isn't an absolute HTTPS URL
+    pub fn checkout_url(&self) -> PesaPalResult<Url> {
+        let invalid = |reason: String| PesaPalError::InvalidRedirectUrl {
+            redirect_url: self.redirect_url.clone(),
+            reason,
+        };
+        let url = Url::parse(self.redirect_url.trim()).map_err(|e| invalid(e.to_string()))?;
+        if url.scheme() != "https" || url.host().is_none() {
+            return Err(invalid("not an HTTPS URL".to_string()));
+        }
+
+        Ok(url)
+    }
+
+    /// `302 Found` response sending the customer to the payment page
+    ///
+    /// The response isn't cached, the payment page is only valid for this
+    /// order.
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::InvalidRedirectUrl`] if the `redirect_url` returned by
+    /// `PesaPal` isn't an absolute HTTPS URL
+    pub fn redirect_response<B: Default>(&self) -> PesaPalResult<Response<B>> {
+        let url = self.checkout_url()?;
+        let location =
+            HeaderValue::from_str(url.as_str()).map_err(|e| PesaPalError::InvalidRedirectUrl {
+                redirect_url: self.redirect_url.clone(),
+                reason: e.to_string(),
+            })?;
+
+        let mut response = Response::new(B::default());
+        *response.status_mut() = StatusCode::FOUND;
+        response.headers_mut().insert(header::LOCATION, location);
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+        Ok(response)
+    }
+
+    /// HTML fragment loading the payment page in an iframe
+    ///
+    /// `redirect_mode` is the one the order was submitted with. With
+    /// [`RedirectMode::TopWindow`] the iframe is sandboxed, only allowing the
+    /// payment page to run, submit its forms, open popups (e.g. 3-D Secure)
+    /// and load the callback URL in the top window. A sandboxed iframe can't
+    /// load a page in its parent window, so with
+    /// [`RedirectMode::ParentWindow`] it isn't sandboxed.
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::InvalidRedirectUrl`] if the `redirect_url` returned by
+    /// `PesaPal` isn't an absolute HTTPS URL
+    pub fn iframe(&self, redirect_mode: RedirectMode) -> PesaPalResult<String> {
+        let url = self.checkout_url()?;
+        let sandbox = match redirect_mode {
+            RedirectMode::TopWindow => {
+                r#" sandbox="allow-forms allow-scripts allow-same-origin allow-popups allow-popups-to-escape-sandbox allow-top-navigation""#
+            }
+            RedirectMode::ParentWindow => "",
+        };
+
+        Ok(format!(
+            r#"<iframe src="{}" width="{IFRAME_WIDTH}" height="{IFRAME_HEIGHT}" style="border: 0" scrolling="auto" title="PesaPal checkout" allow="payment"{sandbox}></iframe>"#,
+            escape_attribute(url.as_str())
+        ))
+    }
+}
+
+/// Escapes `value` to be used in a double or single quoted HTML attribute
+fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(redirect_url: &str) -> SubmitOrderResponse {
+        SubmitOrderResponse {
+            order_tracking_id: "tracking".to_string(),
+            redirect_url: redirect_url.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_redirect_response() {
+        let redirect: Response<()> = response(
+            "https://cybqa.pesapal.com/pesapaliframe/PesapalIframe3/Index?OrderTrackingId=tracking",
+        )
+        .redirect_response()
+        .unwrap();
+
+        assert_eq!(redirect.status(), StatusCode::FOUND);
+        assert_eq!(
+            redirect.headers()[header::LOCATION],
+            "https://cybqa.pesapal.com/pesapaliframe/PesapalIframe3/Index?OrderTrackingId=tracking"
+        );
+        assert_eq!(redirect.headers()[header::CACHE_CONTROL], "no-store");
+
+        for invalid in [
+            "",
+            "/pesapaliframe",
+            "http://pay.pesapal.com",
+            "javascript:alert(1)",
+        ] {
+            assert!(matches!(
+                response(invalid).checkout_url(),
+                Err(PesaPalError::InvalidRedirectUrl { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_iframe_is_escaped() {
+        let order = response(r#"https://pay.pesapal.com/o'neil/iframe?id=1&name="><script>"#);
+
+        let top = order.iframe(RedirectMode::TopWindow).unwrap();
+        assert!(top.starts_with(
+            r#"<iframe src="https://pay.pesapal.com/o&#39;neil/iframe?id=1&amp;name=%22%3E%3Cscript%3E" "#
+        ));
+        assert!(!top.contains("<script>"));
+        assert!(top.contains("allow-top-navigation"));
+
+        let parent = order.iframe(RedirectMode::ParentWindow).unwrap();
+        assert!(!parent.contains("sandbox"));
+        assert!(parent.ends_with(r#"allow="payment"></iframe>"#));
+    }
+}
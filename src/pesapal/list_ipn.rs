@@ -1,45 +1,296 @@
 //! List IPN URLs
 //! This endpoint allows you to fetch all registered IPN URLs for a particular Pesapal merchant account.
 
-use serde::Deserialize;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use http::Method;
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_aux::prelude::{deserialize_default_from_null, deserialize_number_from_string};
+use url::Url;
 
-use crate::PesaPalErrorResponse;
+use crate::pesapal::register_ipn::NotificationType;
+use crate::transport::HttpRequest;
+use crate::{Endpoint, PesaPalErrorResponse, PesaPalResponse};
 
 const LIST_IPN_URL: &str = "api/URLSetup/GetIpnList";
 
+/// Whether `PesaPal` sends notifications to an IPN URL
+///
+/// `PesaPal` returns it either as a code, `1` for active and `0` for
+/// inactive, or as its description.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum IpnStatus {
+    Active,
+    Inactive,
+}
+
+impl IpnStatus {
+    /// Description of the status as returned by `PesaPal`
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "Active",
+            Self::Inactive => "Inactive",
+        }
+    }
+
+    fn from_code_or_name(value: &CodeOrName) -> Option<Self> {
+        match value {
+            CodeOrName::Code(1) => Some(Self::Active),
+            CodeOrName::Code(0) => Some(Self::Inactive),
+            CodeOrName::Name(name) if name.eq_ignore_ascii_case("active") => Some(Self::Active),
+            CodeOrName::Name(name) if name.eq_ignore_ascii_case("inactive") => Some(Self::Inactive),
+            _ => None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for IpnStatus {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = CodeOrName::deserialize(deserializer)?;
+        Self::from_code_or_name(&value)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown IPN status {value}")))
+    }
+}
+
+/// Enum value sent by `PesaPal` either as its numeric code or its name
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum CodeOrName {
+    Code(i64),
+    Name(String),
+}
+
+impl std::fmt::Display for CodeOrName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Code(code) => write!(f, "{code}"),
+            Self::Name(name) => write!(f, "{name:?}"),
+        }
+    }
+}
+
+/// IPN URL as sent on the wire, before its fields are validated
 #[derive(Debug, Deserialize)]
+pub(crate) struct RawIpn {
+    #[serde(default)]
+    pub(crate) url: String,
+    #[serde(default)]
+    pub(crate) created_date: String,
+    #[serde(default)]
+    pub(crate) ipn_id: String,
+    #[serde(default)]
+    pub(crate) notification_type: Option<CodeOrName>,
+    #[serde(default)]
+    pub(crate) ipn_notification_type_description: Option<CodeOrName>,
+    #[serde(default)]
+    pub(crate) ipn_status: Option<CodeOrName>,
+    #[serde(default)]
+    pub(crate) ipn_status_description: Option<CodeOrName>,
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    pub(crate) status: u16,
+    #[serde(default, deserialize_with = "deserialize_default_from_null")]
+    pub(crate) error: Option<PesaPalErrorResponse>,
+}
+
+/// An IPN URL registered for the merchant
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RawIpn")]
 pub struct IPNList {
     /// The notification URL Pesapal will send a status alert to
-    pub url: String,
+    pub url: Url,
     /// Date and time the IPN URL was registered
-    pub created_date: String,
+    pub created_date: DateTime<Utc>,
     /// A unique identifier that's linked to he IPN endpoint URL
     pub ipn_id: String,
+    /// HTTP method used to call the URL, when returned by `PesaPal`
+    pub notification_type: Option<NotificationType>,
+    /// Whether the URL is active, when returned by `PesaPal`
+    pub ipn_status: Option<IpnStatus>,
     /// Response code
-    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub status: u16,
-    /// Response error if any
-    #[serde(deserialize_with = "deserialize_default_from_null")]
+}
+
+impl IPNList {
+    /// Whether `PesaPal` sends notifications to the URL
+    ///
+    /// URLs whose status isn't returned are assumed to be active.
+    #[must_use]
+    pub fn is_active(&self) -> bool {
+        self.ipn_status
+            .is_none_or(|status| status == IpnStatus::Active)
+    }
+
+    /// Whether the URL is `url`, ignoring trailing slashes, case, default
+    /// ports and fragments
+    #[must_use]
+    pub fn matches_url(&self, url: &str) -> bool {
+        normalize_url(self.url.as_str()) == normalize_url(url)
+    }
+}
+
+impl TryFrom<RawIpn> for IPNList {
+    type Error = IpnEntryError;
+
+    fn try_from(raw: RawIpn) -> Result<Self, Self::Error> {
+        let invalid = |message: String| IpnEntryError {
+            ipn_id: raw.ipn_id.clone(),
+            message,
+            error: None,
+        };
+
+        if let Some(error) = &raw.error {
+            return Err(IpnEntryError {
+                ipn_id: raw.ipn_id.clone(),
+                message: error.message.clone(),
+                error: Some(error.clone()),
+            });
+        }
+
+        let url = Url::parse(raw.url.trim())
+            .map_err(|e| invalid(format!("invalid url {:?} : {e}", raw.url)))?;
+        let created_date = parse_created_date(&raw.created_date)
+            .map_err(|e| invalid(format!("invalid created_date {:?} : {e}", raw.created_date)))?;
+        let notification_type = raw
+            .notification_type
+            .as_ref()
+            .or(raw.ipn_notification_type_description.as_ref())
+            .map(|value| NotificationType::from_code_or_name(value).ok_or(value))
+            .transpose()
+            .map_err(|value| invalid(format!("unknown notification type {value}")))?;
+        let ipn_status = raw
+            .ipn_status
+            .as_ref()
+            .or(raw.ipn_status_description.as_ref())
+            .map(|value| IpnStatus::from_code_or_name(value).ok_or(value))
+            .transpose()
+            .map_err(|value| invalid(format!("unknown IPN status {value}")))?;
+
+        Ok(Self {
+            url,
+            created_date,
+            ipn_id: raw.ipn_id,
+            notification_type,
+            ipn_status,
+            status: raw.status,
+        })
+    }
+}
+
+/// Parses the registration date, `PesaPal` sends it in UTC with or without
+/// the trailing `Z`
+fn parse_created_date(value: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    let value = value.trim();
+    NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y-%m-%dT%H:%M:%S%.f")
+        .map(|date| date.and_utc())
+}
+
+/// IPN entry of the list that couldn't be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpnEntryError {
+    /// Id of the entry, empty if it wasn't returned
+    pub ipn_id: String,
+    /// What is wrong with the entry
+    pub message: String,
+    /// Error returned by `PesaPal` for the entry, if any
     pub error: Option<PesaPalErrorResponse>,
 }
 
+impl std::fmt::Display for IpnEntryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "IPN {:?} : {}", self.ipn_id, self.message)
+    }
+}
+
+impl std::error::Error for IpnEntryError {}
+
 /// Response from the list IPN endpoint
-#[derive(Debug, Deserialize)]
+///
+/// Entries that `PesaPal` returned with an error, or whose fields are invalid,
+/// are kept apart in [`IPNListResponse::errors`] rather than failing the whole
+/// list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct IPNListResponse {
+    /// IPN URLs registered for the merchant
     pub ipns: Vec<IPNList>,
+    /// Entries that couldn't be read
+    pub errors: Vec<IpnEntryError>,
+}
+
+impl IPNListResponse {
+    /// IPN URLs matching `url`, ignoring trailing slashes, case, default
+    /// ports and fragments
+    pub fn by_url<'a>(&'a self, url: &str) -> impl Iterator<Item = &'a IPNList> + 'a {
+        let url = normalize_url(url);
+        self.ipns
+            .iter()
+            .filter(move |ipn| normalize_url(ipn.url.as_str()) == url)
+    }
+
+    /// IPN URLs `PesaPal` sends notifications to, see [`IPNList::is_active`]
+    pub fn active(&self) -> impl Iterator<Item = &IPNList> {
+        self.ipns.iter().filter(|ipn| ipn.is_active())
+    }
+
+    /// IPN URLs called with `notification_type`
+    ///
+    /// URLs whose notification type isn't returned are left out.
+    pub fn by_type(&self, notification_type: NotificationType) -> impl Iterator<Item = &IPNList> {
+        self.ipns
+            .iter()
+            .filter(move |ipn| ipn.notification_type == Some(notification_type))
+    }
+}
+
+impl<'de> Deserialize<'de> for IPNListResponse {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Body {
+            Entries(Vec<RawIpn>),
+            Wrapped { ipns: Vec<RawIpn> },
+        }
+
+        let (Body::Entries(entries) | Body::Wrapped { ipns: entries }) =
+            Body::deserialize(deserializer)?;
+        let mut response = Self::default();
+        for entry in entries {
+            match IPNList::try_from(entry) {
+                Ok(ipn) => response.ipns.push(ipn),
+                Err(error) => response.errors.push(error),
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+/// URL in a form where trailing slashes, case, default ports and fragments
+/// don't matter
+pub(crate) fn normalize_url(url: &str) -> String {
+    let url = url.trim();
+    let normalized = match Url::parse(url) {
+        Ok(mut parsed) => {
+            parsed.set_fragment(None);
+            let path = parsed.path().trim_end_matches('/').to_string();
+            parsed.set_path(&path);
+            parsed.to_string()
+        }
+        Err(_) => url.to_string(),
+    };
+
+    normalized.trim_end_matches('/').to_lowercase()
 }
 
 /// A builder for listing IPN URLs
 #[derive(Debug, Clone)]
-pub struct ListIPN<'pesa> {
-    client: &'pesa crate::PesaPal,
+pub struct ListIPN {
+    client: crate::PesaPal,
 }
 
-impl<'pesa> ListIPN<'pesa> {
+impl ListIPN {
     /// Create a new instance of the `ListIPN` builder
     #[must_use]
-    pub const fn new(client: &'pesa crate::PesaPal) -> Self {
+    pub const fn new(client: crate::PesaPal) -> Self {
         Self { client }
     }
 
@@ -52,26 +303,132 @@ impl<'pesa> ListIPN<'pesa> {
     ///
     /// Returns a list of IPN URLs registered for the merchant.
     ///
+    /// Entries that can't be read are returned in
+    /// [`IPNListResponse::errors`].
+    ///
     /// # Errors
-    /// If it encounters error with any of the responses, we just print out the
-    /// error
+    /// Errors returned for individual IPN entries are emitted as `tracing`
+    /// events when the `tracing` feature is enabled
     pub async fn send(&self) -> crate::PesaPalResult<IPNListResponse> {
-        let url = format!("{}/{}", self.client.env.base_url(), LIST_IPN_URL);
-        let response = self
-            .client
-            .http_client
-            .get(url)
-            .bearer_auth(self.client.consumer_key.as_str())
-            .send()
-            .await?;
-        let response = response.json::<IPNListResponse>().await?;
-
-        response.ipns.iter().for_each(|ipn| {
-            if let Some(error) = &ipn.error {
-                eprintln!("Error: {error:?}");
-            }
+        self.send_with_meta().await.map(PesaPalResponse::into_inner)
+    }
+
+    /// # List IPN URLs with metadata
+    ///
+    /// Same as [`ListIPN::send`] but returns the HTTP metadata of the call
+    /// alongside the [`IPNListResponse`]
+    ///
+    /// # Errors
+    /// Errors returned for individual IPN entries are emitted as `tracing`
+    /// events when the `tracing` feature is enabled
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "pesapal.list_ipn",
+            skip_all,
+            err(Display),
+            fields(
+                endpoint = "list_ipn",
+                environment = %self.client.inner.env,
+                http.status = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+            )
+        )
+    )]
+    pub async fn send_with_meta(&self) -> crate::PesaPalResult<PesaPalResponse<IPNListResponse>> {
+        let url = format!("{}/{}", self.client.inner.env.base_url(), LIST_IPN_URL);
+        let request = HttpRequest::new(Method::GET, &url)?;
+
+        let response: PesaPalResponse<IPNListResponse> =
+            self.client.execute(Endpoint::ListIpn, request, Ok).await?;
+
+        #[cfg(feature = "tracing")]
+        response.body.errors.iter().for_each(|error| {
+            tracing::warn!(ipn_id = %error.ipn_id, %error, "IPN entry couldn't be read");
         });
 
         Ok(response)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_response_is_typed() {
+        let response: IPNListResponse = serde_json::from_str(
+            r#"[
+                {"url":"https://example.com/ipn","created_date":"2022-03-03T17:29:03.7208266Z","ipn_id":"codes","notification_type":0,"ipn_status":1,"error":null,"status":"200"},
+                {"url":"https://example.com/IPN/","created_date":"2022-03-03T17:29:03.72","ipn_id":"names","ipn_notification_type_description":"POST","ipn_status_description":"Inactive","error":null,"status":"200"},
+                {"url":"https://example.com/legacy","created_date":"2022-03-03T17:29:03Z","ipn_id":"legacy","error":null,"status":"200"},
+                {"url":"","created_date":"","ipn_id":"failed","error":{"code":"not_found","error_type":"api_error","message":"IPN not found"},"status":"500"},
+                {"url":"not a url","created_date":"2022-03-03T17:29:03Z","ipn_id":"invalid","error":null,"status":"200"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            response
+                .ipns
+                .iter()
+                .map(|ipn| ipn.ipn_id.as_str())
+                .collect::<Vec<_>>(),
+            ["codes", "names", "legacy"]
+        );
+        let codes = &response.ipns[0];
+        assert_eq!(codes.notification_type, Some(NotificationType::Get));
+        assert_eq!(codes.ipn_status, Some(IpnStatus::Active));
+        assert_eq!(
+            codes.created_date.to_rfc3339(),
+            "2022-03-03T17:29:03.720826600+00:00"
+        );
+
+        assert_eq!(response.errors.len(), 2);
+        assert_eq!(response.errors[0].ipn_id, "failed");
+        assert_eq!(response.errors[0].message, "IPN not found");
+        assert!(response.errors[0].error.is_some());
+        assert_eq!(response.errors[1].ipn_id, "invalid");
+        assert!(response.errors[1].error.is_none());
+
+        let ids = |ipns: Vec<&IPNList>| {
+            ipns.iter()
+                .map(|ipn| ipn.ipn_id.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(response.by_url("https://Example.com/ipn").collect()),
+            ["codes", "names"]
+        );
+        assert_eq!(ids(response.active().collect()), ["codes", "legacy"]);
+        assert_eq!(
+            ids(response.by_type(NotificationType::Post).collect()),
+            ["names"]
+        );
+    }
+
+    #[test]
+    fn test_list_response_accepts_wrapped_entries() {
+        let response: IPNListResponse = serde_json::from_str(
+            r#"{"ipns":[{"url":"https://example.com/ipn","created_date":"2022-03-03T17:29:03Z","ipn_id":"id","status":"200"}]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(response.ipns[0].ipn_id, "id");
+        assert!(response.ipns[0].is_active());
+        assert!(response.ipns[0].matches_url("https://example.com/ipn/"));
+        assert!(serde_json::from_str::<IpnStatus>(r#""unknown""#).is_err());
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(
+            normalize_url(" HTTPS://Example.com:443/IPN/#top "),
+            normalize_url("https://example.com/ipn")
+        );
+        assert_ne!(
+            normalize_url("https://example.com/ipn?shop=a"),
+            normalize_url("https://example.com/ipn?shop=b")
+        );
+    }
+}
@@ -11,15 +11,80 @@
 //! - You can only fully refund a payment mobile payment.
 //! - Refunds are performed in the currency of the original payment.
 //! - Multiple refunds are not allowed. You can only request one refund against
-//! a payment.
+//!   a payment.
+//!
+//! These rules are only enforced by `PesaPal`, which rejects the refund with a
+//! vague error. Set a [`RefundPreflight`] on the builder to look up the original
+//! transaction and check them before the refund is sent.
+//!
+//! ```rust,ignore
+//! let response = pesapal
+//!     .refund()
+//!     .amount(2500)
+//!     .remarks("Service not offered")
+//!     .confirmation_code("AA22BB33CC")
+//!     .username("John Doe")
+//!     .preflight(RefundPreflight::new(order_tracking_id).currency("KES"))
+//!     .build()?
+//!     .send()
+//!     .await?;
+//! ```
+//!
+//! An accepted refund only means `PesaPal` received it. The payment status
+//! becomes REVERSED once the money is sent back, follow it with a
+//! [`RefundTracker`]:
+//!
+//! ```rust,ignore
+//! let status = pesapal
+//!     .track_refund(order_tracking_id)
+//!     .poll_interval(Duration::from_secs(60))
+//!     .timeout(Duration::from_secs(3600))
+//!     .wait()
+//!     .await?;
+//! if status == RefundStatus::Reversed {
+//!     // the money has moved
+//! }
+//! ```
+
+use std::time::{Duration, Instant};
 
 use derive_builder::Builder;
+use http::Method;
 use serde::{Deserialize, Serialize};
+use serde_aux::prelude::deserialize_number_from_string;
 
-use crate::{PesaPal, PesaPalError, PesaPalResult};
+use super::transaction_status::{StatusCode, TransactionStatus, TransactionStatusResponse};
+use crate::response::ResponseMeta;
+use crate::transport::HttpRequest;
+use crate::{Endpoint, PesaPal, PesaPalError, PesaPalResponse, PesaPalResult};
 
 const REFUND_REQUEST_URL: &str = "api/Transactions/RefundRequest";
 
+/// `payment_method` values of the transaction status, in lower case, of the
+/// mobile money wallets which can only be refunded in full
+///
+/// These are the wallets `PesaPal` offers in Kenya, Uganda and Tanzania, as
+/// named in the status of their payments, e.g. `MpesaKE`.
+const MOBILE_MONEY_METHODS: &[&str] = &[
+    "mpesa", "mpesake", "m-pesa", "airtel", "airtelke", "airtelug", "airteltz", "mtn", "mtnug",
+    "tigopesa", "halopesa", "equitel", "tkash", "t-kash",
+];
+
+/// `payment_method` values of the transaction status, in lower case, of the
+/// cards which may be refunded in part
+///
+/// Neither list is published by `PesaPal`, so a payment method missing from
+/// both is assumed to be a mobile money wallet: its partial refunds are
+/// rejected by the [`RefundPreflight`].
+const CARD_METHODS: &[&str] = &[
+    "visa",
+    "mastercard",
+    "amex",
+    "americanexpress",
+    "american express",
+    "unionpay",
+];
+
 #[derive(Debug, Clone, Serialize)]
 pub struct RefundRequest {
     /// This refers to payment confirmation code that was returned by the
@@ -33,17 +98,69 @@ pub struct RefundRequest {
     pub remarks: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct RefundResponse {
     /// 200 - Refund received successfully and is being processed.
+    /// 202 - Refund received, waiting for the approval of the merchant.
     /// 500 - Refund rejected.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub status: u16,
     /// A brief summary of the response received.
     pub message: String,
 }
 
-impl From<Refund<'_>> for RefundRequest {
-    fn from(value: Refund<'_>) -> Self {
+impl RefundResponse {
+    /// Outcome of the refund request, read from the `status` only
+    ///
+    /// `PesaPal` documents `200`, received and being processed, which is
+    /// [`RefundOutcome::Accepted`]. `202`, received but not acted upon yet, is
+    /// [`RefundOutcome::PendingApproval`]. Every other status is
+    /// [`RefundOutcome::Rejected`]. The `message` is free text and isn't
+    /// interpreted.
+    #[must_use]
+    pub const fn outcome(&self) -> RefundOutcome {
+        match self.status {
+            200 => RefundOutcome::Accepted,
+            202 => RefundOutcome::PendingApproval,
+            _ => RefundOutcome::Rejected,
+        }
+    }
+}
+
+/// Outcome of a refund request
+///
+/// None of them means the money has moved yet, see [`RefundTracker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefundOutcome {
+    /// Received by `PesaPal` and being processed
+    Accepted,
+    /// Received by `PesaPal`, waiting for the approval of the merchant
+    PendingApproval,
+    /// Rejected by `PesaPal`, returned as [`PesaPalError::RefundError`]
+    Rejected,
+}
+
+/// Refund request rejected by `PesaPal`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RefundRejection {
+    /// Status returned by `PesaPal`
+    pub status: u16,
+    /// Reason given by `PesaPal`
+    pub message: String,
+    /// Metadata of the response the rejection was read from
+    pub meta: Option<Box<ResponseMeta>>,
+}
+
+impl std::fmt::Display for RefundRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "status: {} message: {}", self.status, self.message)
+    }
+}
+
+impl From<Refund> for RefundRequest {
+    fn from(value: Refund) -> Self {
         Self {
             confirmation_code: value.confirmation_code,
             amount: value.amount,
@@ -54,9 +171,9 @@ impl From<Refund<'_>> for RefundRequest {
 }
 
 #[derive(Builder, Debug)]
-pub struct Refund<'pesa> {
-    #[builder(pattern = "owned")]
-    client: &'pesa PesaPal,
+#[builder(derive(Debug))]
+pub struct Refund {
+    client: PesaPal,
     #[builder(setter(into))]
     #[doc = "This refers to payment confirmation code that was returned by the payment processor"]
     confirmation_code: String,
@@ -69,12 +186,291 @@ pub struct Refund<'pesa> {
     #[builder(setter(into))]
     #[doc = "A brief description on the reason for the refund."]
     remarks: String,
+    #[builder(setter(into, strip_option), default)]
+    #[doc = "Checks the refund against the original transaction before sending it."]
+    preflight: Option<RefundPreflight>,
+}
+
+/// Opt-in checks of a [`Refund`] against the original transaction
+///
+/// The transaction is looked up by its order tracking id, the refund is only
+/// sent if:
+/// - the transaction is COMPLETED and not already REVERSED,
+/// - its confirmation code is the one of the refund,
+/// - the refund amount is positive and not more than what was collected,
+/// - mobile money payments, and the payments whose method isn't a known card,
+///   are refunded in full,
+/// - the currency, if given, is the one of the payment.
+///
+/// A refund requested earlier which `PesaPal` has not processed yet can't be
+/// detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefundPreflight {
+    order_tracking_id: String,
+    currency: Option<String>,
+}
+
+impl RefundPreflight {
+    /// Checks the refund against the transaction with the order tracking id
+    pub fn new(order_tracking_id: impl Into<String>) -> Self {
+        Self {
+            order_tracking_id: order_tracking_id.into(),
+            currency: None,
+        }
+    }
+
+    /// Currency the refund is expected in, an ISO 4217 code
+    #[must_use]
+    pub fn currency(mut self, currency: impl Into<String>) -> Self {
+        self.currency = Some(currency.into());
+        self
+    }
+
+    /// Checks the refund rules against the original transaction
+    ///
+    /// # Errors
+    ///
+    /// [`RefundPreflightError`] for the first rule the refund breaks
+    pub fn check(
+        &self,
+        refund: &Refund,
+        transaction: &TransactionStatusResponse,
+    ) -> Result<(), RefundPreflightError> {
+        match transaction.status_code {
+            StatusCode::Completed => {}
+            StatusCode::Reversed => return Err(RefundPreflightError::AlreadyRefunded),
+            status => return Err(RefundPreflightError::NotCompleted { status }),
+        }
+
+        if transaction.confirmation_code != refund.confirmation_code {
+            return Err(RefundPreflightError::ConfirmationCodeMismatch {
+                requested: refund.confirmation_code.clone(),
+                original: transaction.confirmation_code.clone(),
+            });
+        }
+
+        let (requested, collected) = (refund.amount, transaction.amount);
+        if requested.is_nan() || requested <= 0.0 {
+            return Err(RefundPreflightError::InvalidAmount { requested });
+        }
+        if requested > collected as f64 {
+            return Err(RefundPreflightError::AmountExceedsCollected {
+                requested,
+                collected,
+            });
+        }
+        if requested < collected as f64 && !is_method(CARD_METHODS, &transaction.payment_method) {
+            let payment_method = transaction.payment_method.clone();
+            return Err(
+                if is_method(MOBILE_MONEY_METHODS, &transaction.payment_method) {
+                    RefundPreflightError::PartialMobileMoneyRefund {
+                        payment_method,
+                        requested,
+                        collected,
+                    }
+                } else {
+                    RefundPreflightError::PartialRefundOfUnknownMethod {
+                        payment_method,
+                        requested,
+                        collected,
+                    }
+                },
+            );
+        }
+
+        if let Some(currency) = &self.currency {
+            if !currency.eq_ignore_ascii_case(&transaction.currency) {
+                return Err(RefundPreflightError::CurrencyMismatch {
+                    requested: currency.clone(),
+                    original: transaction.currency.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn run(&self, refund: &Refund) -> PesaPalResult<()> {
+        let transaction = TransactionStatus {
+            client: refund.client.clone(),
+            order_tracking_id: self.order_tracking_id.clone(),
+        }
+        .send_uncached()
+        .await?;
+
+        self.check(refund, &transaction)
+            .map_err(PesaPalError::RefundPreflightError)
+    }
+}
+
+impl From<&str> for RefundPreflight {
+    fn from(order_tracking_id: &str) -> Self {
+        Self::new(order_tracking_id)
+    }
+}
+
+impl From<String> for RefundPreflight {
+    fn from(order_tracking_id: String) -> Self {
+        Self::new(order_tracking_id)
+    }
+}
+
+/// Refund rule broken, found by a [`RefundPreflight`]
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[non_exhaustive]
+pub enum RefundPreflightError {
+    /// Only COMPLETED payments can be refunded
+    #[error("payment is {status}, only COMPLETED payments can be refunded")]
+    NotCompleted { status: StatusCode },
+    /// The payment was already refunded or reversed
+    #[error("payment was already reversed, only one refund is allowed per payment")]
+    AlreadyRefunded,
+    /// The confirmation code isn't the one of the transaction
+    #[error("confirmation code {requested} doesn't match the payment's {original}")]
+    ConfirmationCodeMismatch { requested: String, original: String },
+    /// The refund amount is zero, negative or not a number
+    #[error("refund amount {requested} must be greater than 0")]
+    InvalidAmount { requested: f64 },
+    /// More than what was collected is refunded
+    #[error("refund amount {requested} exceeds the {collected} collected")]
+    AmountExceedsCollected { requested: f64, collected: u64 },
+    /// Mobile money payments can only be refunded in full
+    #[error(
+        "{payment_method} payments can only be refunded in full, {requested} of {collected} requested"
+    )]
+    PartialMobileMoneyRefund {
+        payment_method: String,
+        requested: f64,
+        collected: u64,
+    },
+    /// The payment method is neither a known card nor a known mobile money
+    /// wallet, so it may only be refunded in full
+    #[error(
+        "unknown payment method {payment_method:?} may only be refunded in full, {requested} of {collected} requested"
+    )]
+    PartialRefundOfUnknownMethod {
+        payment_method: String,
+        requested: f64,
+        collected: u64,
+    },
+    /// Refunds are made in the currency of the payment
+    #[error("refund currency {requested} doesn't match the payment's {original}")]
+    CurrencyMismatch { requested: String, original: String },
+}
+
+/// Status of a refund, as seen on the original payment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefundStatus {
+    /// The payment is still COMPLETED, the money hasn't moved yet
+    Pending,
+    /// The payment was REVERSED, the money was sent back
+    Reversed,
+    /// The payment was never completed, there is nothing to refund
+    NotRefundable(StatusCode),
+}
+
+impl From<StatusCode> for RefundStatus {
+    fn from(status: StatusCode) -> Self {
+        match status {
+            StatusCode::Completed => Self::Pending,
+            StatusCode::Reversed => Self::Reversed,
+            status => Self::NotRefundable(status),
+        }
+    }
 }
 
-impl Refund<'_> {
+/// Follows a refund through the status of the original payment
+///
+/// Created with [`PesaPal::track_refund`]. The status is always fetched from
+/// `PesaPal`, a [`StatusCache`](crate::StatusCache) set on the client is
+/// updated with it.
+#[derive(Debug, Clone)]
+pub struct RefundTracker {
+    client: PesaPal,
+    order_tracking_id: String,
+    poll_interval: Duration,
+    timeout: Duration,
+}
+
+impl RefundTracker {
+    /// Tracks the refund of the payment with the order tracking id, polling
+    /// every 30 seconds for up to 10 minutes
+    pub(crate) fn new(client: PesaPal, order_tracking_id: String) -> Self {
+        Self {
+            client,
+            order_tracking_id,
+            poll_interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(600),
+        }
+    }
+
+    /// Time between two status checks of [`RefundTracker::wait`]
+    ///
+    /// # Panics
+    /// Panics if `interval` is zero
+    #[must_use]
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "poll interval must be greater than 0");
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Time after which [`RefundTracker::wait`] gives up
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Checks the status of the refund once
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::TransactionStatusError`] - Incase the status lookup fails
+    pub async fn status(&self) -> PesaPalResult<RefundStatus> {
+        let transaction = TransactionStatus {
+            client: self.client.clone(),
+            order_tracking_id: self.order_tracking_id.clone(),
+        }
+        .send_uncached()
+        .await?;
+
+        Ok(transaction.status_code.into())
+    }
+
+    /// Polls the status until the refund isn't pending anymore or the
+    /// timeout elapses, returning the last status seen
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::TransactionStatusError`] - Incase a status lookup fails
+    pub async fn wait(&self) -> PesaPalResult<RefundStatus> {
+        let started = Instant::now();
+        loop {
+            let status = self.status().await?;
+            let elapsed = started.elapsed();
+            if status != RefundStatus::Pending || elapsed >= self.timeout {
+                return Ok(status);
+            }
+            futures_timer::Delay::new(self.poll_interval.min(self.timeout - elapsed)).await;
+        }
+    }
+}
+
+/// Whether the payment method is one of the `methods`, ignoring the case
+fn is_method(methods: &[&str], payment_method: &str) -> bool {
+    let payment_method = payment_method.trim();
+    methods
+        .iter()
+        .any(|method| payment_method.eq_ignore_ascii_case(method))
+}
+
+impl Refund {
     /// Initializes the builder for the Refund process
-    pub(crate) fn builder(client: &PesaPal) -> RefundBuilder {
-        RefundBuilder::default().client(client)
+    pub(crate) fn builder(client: PesaPal) -> RefundBuilder {
+        let mut builder = RefundBuilder::default();
+        builder.client(client);
+        builder
     }
 
     /// # Refund Request
@@ -84,31 +480,297 @@ impl Refund<'_> {
     /// ## Returns
     ///
     /// * status - 200 means your request to process the refund has been
-    /// successfully received
+    ///   successfully received
     ///
     /// *NB* It doesn't mean the refund has been effected
     ///
     /// ## Errors
     ///
-    /// [`PesaPalError::RefundError`] - with status 500 and error message incase the refund
-    /// fails
+    /// [`PesaPalError::RefundError`] - with the status and message incase the
+    /// refund is rejected
+    ///
+    /// [`PesaPalError::RefundPreflightError`] - incase a [`RefundPreflight`] is
+    /// set and the refund breaks one of the refund rules
     pub async fn send(self) -> PesaPalResult<RefundResponse> {
-        let url = format!("{}/{REFUND_REQUEST_URL}", self.client.env.base_url());
-        let response = self
-            .client
-            .http_client
-            .post(url)
-            .bearer_auth(&self.client.authenticate().await?)
-            .json::<RefundRequest>(&self.into())
-            .send()
-            .await?;
+        self.send_with_meta().await.map(PesaPalResponse::into_inner)
+    }
 
-        let res: RefundResponse = response.json().await?;
+    /// # Refund Request with metadata
+    ///
+    /// Same as [`Refund::send`] but returns the HTTP metadata of the call
+    /// alongside the [`RefundResponse`]
+    ///
+    /// ## Errors
+    ///
+    /// [`PesaPalError::RefundError`] - with the status and message incase the
+    /// refund is rejected
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "pesapal.refund",
+            skip_all,
+            err(Display),
+            fields(
+                endpoint = "refund",
+                environment = %self.client.inner.env,
+                confirmation_code = %self.confirmation_code,
+                http.status = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+            )
+        )
+    )]
+    pub async fn send_with_meta(self) -> PesaPalResult<PesaPalResponse<RefundResponse>> {
+        let url = format!("{}/{REFUND_REQUEST_URL}", self.client.inner.env.base_url());
+        let client = self.client.clone();
+
+        if let Some(preflight) = &self.preflight {
+            preflight.run(&self).await?;
+        }
+
+        let request = HttpRequest::new(Method::POST, &url)?.json::<RefundRequest>(&self.into())?;
+
+        client
+            .execute(Endpoint::Refund, request, |res: RefundResponse| {
+                match res.outcome() {
+                    RefundOutcome::Accepted | RefundOutcome::PendingApproval => Ok(res),
+                    RefundOutcome::Rejected => Err(PesaPalError::RefundError(RefundRejection {
+                        status: res.status,
+                        message: res.message,
+                        meta: None,
+                    })),
+                }
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+    use crate::Environment;
+
+    const TRANSACTION_STATUS_URL: &str = "api/Transactions/GetTransactionStatus";
+    const MPESA: &str = r#"{"paymentMethod":"MpesaKE","amount":100,"createdDate":"2022-04-30T07:41:09.763","confirmationCode":"QDU8K7EX9Q","paymentStatusDescription":1,"description":"","message":"ok","paymentAccount":"2547****1234","callBackUrl":"https://example.com","statusCode":1,"merchantReference":"TEST1515111119","currency":"KES","error":{"error_type":"","code":"","message":"","call_back_url":""},"status":"200"}"#;
+
+    fn refund(client: &PesaPal, amount: f64) -> Refund {
+        client
+            .refund()
+            .confirmation_code("QDU8K7EX9Q")
+            .amount(amount)
+            .username("John Doe")
+            .remarks("Service not offered")
+            .preflight(RefundPreflight::new("tracking").currency("KES"))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_refund_preflight_rules() {
+        let client = PesaPal::new_with_transport(
+            "refund-preflight-key",
+            "secret",
+            Environment::Sandbox,
+            MockTransport::new(),
+        );
+        let transaction: TransactionStatusResponse = serde_json::from_str(MPESA).unwrap();
+        let check = |refund: Refund, transaction: &TransactionStatusResponse| {
+            refund
+                .preflight
+                .as_ref()
+                .unwrap()
+                .check(&refund, transaction)
+        };
+
+        assert_eq!(check(refund(&client, 100.0), &transaction), Ok(()));
+        assert_eq!(
+            check(refund(&client, 150.0), &transaction),
+            Err(RefundPreflightError::AmountExceedsCollected {
+                requested: 150.0,
+                collected: 100
+            })
+        );
+        assert!(matches!(
+            check(refund(&client, 50.0), &transaction),
+            Err(RefundPreflightError::PartialMobileMoneyRefund { .. })
+        ));
+        assert!(matches!(
+            check(refund(&client, 0.0), &transaction),
+            Err(RefundPreflightError::InvalidAmount { .. })
+        ));
+
+        let mut unknown = transaction.clone();
+        unknown.payment_method = "SomeNewWallet".to_string();
+        assert!(matches!(
+            check(refund(&client, 50.0), &unknown),
+            Err(RefundPreflightError::PartialRefundOfUnknownMethod { .. })
+        ));
+        assert_eq!(check(refund(&client, 100.0), &unknown), Ok(()));
+
+        let mut card = transaction.clone();
+        card.payment_method = "Visa".to_string();
+        assert_eq!(check(refund(&client, 50.0), &card), Ok(()));
+        card.currency = "UGX".to_string();
+        assert!(matches!(
+            check(refund(&client, 50.0), &card),
+            Err(RefundPreflightError::CurrencyMismatch { .. })
+        ));
 
-        if res.status == 500 {
-            return Err(PesaPalError::RefundError(res.message));
+        let mut reversed = transaction;
+        reversed.status_code = StatusCode::Reversed;
+        assert_eq!(
+            check(refund(&client, 100.0), &reversed),
+            Err(RefundPreflightError::AlreadyRefunded)
+        );
+    }
+
+    #[test]
+    fn test_payment_methods_match_exactly() {
+        assert!(is_method(MOBILE_MONEY_METHODS, "MpesaKE"));
+        assert!(is_method(MOBILE_MONEY_METHODS, "AIRTELUG"));
+        assert!(is_method(MOBILE_MONEY_METHODS, " TigoPesa "));
+        assert!(!is_method(MOBILE_MONEY_METHODS, "Visa"));
+        assert!(!is_method(MOBILE_MONEY_METHODS, "MobileBanking"));
+        assert!(!is_method(MOBILE_MONEY_METHODS, "MTN Card"));
+        assert!(!is_method(MOBILE_MONEY_METHODS, ""));
+        assert!(is_method(CARD_METHODS, "MasterCard"));
+        assert!(!is_method(CARD_METHODS, "Visa Debit Mpesa"));
+    }
+
+    #[tokio::test]
+    async fn test_refund_preflight_blocks_the_request() {
+        let transport = std::sync::Arc::new(
+            MockTransport::new()
+                .on(TRANSACTION_STATUS_URL, 200, MPESA)
+                .on(REFUND_REQUEST_URL, 200, r#"{"status":200,"message":"ok"}"#),
+        );
+        let client = PesaPal::new_with_transport(
+            "refund-preflight-key",
+            "secret",
+            Environment::Sandbox,
+            std::sync::Arc::clone(&transport),
+        );
+
+        let error = refund(&client, 50.0).send().await.err().unwrap();
+        assert!(matches!(
+            error,
+            PesaPalError::RefundPreflightError(
+                RefundPreflightError::PartialMobileMoneyRefund { .. }
+            )
+        ));
+        assert!(transport.requests(REFUND_REQUEST_URL).is_empty());
+
+        refund(&client, 100.0).send().await.unwrap();
+        assert_eq!(transport.requests(REFUND_REQUEST_URL).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_refund_outcome() {
+        let response = |status: &str, message: &str| RefundResponse {
+            status: status.parse().unwrap(),
+            message: message.to_string(),
+        };
+        assert_eq!(
+            response("200", "Refund request successfully").outcome(),
+            RefundOutcome::Accepted
+        );
+        assert_eq!(
+            response("202", "Refund received").outcome(),
+            RefundOutcome::PendingApproval
+        );
+        assert_eq!(
+            response("500", "Refund approved").outcome(),
+            RefundOutcome::Rejected
+        );
+        for status in ["201", "204", "302"] {
+            assert_eq!(
+                response(status, "Refund request successfully").outcome(),
+                RefundOutcome::Rejected
+            );
         }
+        assert_eq!(
+            response("400", "Refund already requested").outcome(),
+            RefundOutcome::Rejected
+        );
 
-        Ok(res)
+        let client = PesaPal::new_with_transport(
+            "refund-outcome-key",
+            "secret",
+            Environment::Sandbox,
+            MockTransport::new()
+                .on(
+                    REFUND_REQUEST_URL,
+                    200,
+                    r#"{"status":"202","message":"Refund received"}"#,
+                )
+                .on(
+                    REFUND_REQUEST_URL,
+                    200,
+                    r#"{"status":"400","message":"Refund already requested"}"#,
+                )
+                .on(
+                    REFUND_REQUEST_URL,
+                    200,
+                    r#"{"status":"302","message":"Refund request successfully"}"#,
+                ),
+        );
+        let send = || {
+            client
+                .refund()
+                .confirmation_code("QDU8K7EX9Q")
+                .amount(100.0)
+                .username("John Doe")
+                .remarks("Service not offered")
+                .build()
+                .unwrap()
+                .send()
+        };
+
+        let response = send().await.unwrap();
+        assert_eq!(response.outcome(), RefundOutcome::PendingApproval);
+        for status in [400, 302] {
+            let error = send().await.err().unwrap();
+            assert!(matches!(
+                error,
+                PesaPalError::RefundError(RefundRejection { status: s, .. }) if s == status
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refund_tracker_waits_for_reversal() {
+        let reversed = MPESA.replace(r#""statusCode":1"#, r#""statusCode":3"#);
+        let transport = std::sync::Arc::new(
+            MockTransport::new()
+                .on(TRANSACTION_STATUS_URL, 200, MPESA)
+                .on(TRANSACTION_STATUS_URL, 200, MPESA)
+                .on(TRANSACTION_STATUS_URL, 200, &reversed),
+        );
+        let client = PesaPal::new_with_transport(
+            "refund-tracker-key",
+            "secret",
+            Environment::Sandbox,
+            std::sync::Arc::clone(&transport),
+        )
+        .with_transaction_status_cache(crate::StatusCache::new());
+        let tracker = client
+            .track_refund("tracking")
+            .poll_interval(Duration::from_millis(10));
+
+        assert_eq!(tracker.status().await.unwrap(), RefundStatus::Pending);
+        assert_eq!(tracker.wait().await.unwrap(), RefundStatus::Reversed);
+        assert_eq!(transport.requests(TRANSACTION_STATUS_URL).len(), 3);
+
+        // The cache was refreshed with the reversed status
+        let cached = client
+            .transaction_status()
+            .order_tracking_id("tracking")
+            .build()
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(cached.status_code, StatusCode::Reversed);
+        assert_eq!(transport.requests(TRANSACTION_STATUS_URL).len(), 3);
     }
 }
@@ -8,7 +8,7 @@
 
 //! -  Your client gets disconnected after payment due to internet issues
 //! -  Your client experiences server errors hence Pesapal and your application
-//! gets disconnected before callback URL is loaded.
+//!    gets disconnected before callback URL is loaded.
 //! - Your client exits your application / closes the browser during payment.
 //! - The transaction is rejected.
 //!
@@ -20,18 +20,22 @@
 //! IP whitelisting is not feasible as our IP may change without notice.
 
 //! - Before sending Submit Order Requests to Pesapal API 3.0, you are expected
-//! to register your IPN URL. Upon registration, you receive a notification Id
-//! which is a mandatory field when submitting an order request to Pesapal API
-//! 3.0.
-//! This `notification_id` uniquely identifies the endpoint Pesapal will send
-//! alerts to whenever a payment status changes for each transaction processed
-//! via API 3.0
+//!   to register your IPN URL. Upon registration, you receive a notification Id
+//!   which is a mandatory field when submitting an order request to Pesapal API
+//!   3.0.
+//!   This `notification_id` uniquely identifies the endpoint Pesapal will send
+//!   alerts to whenever a payment status changes for each transaction processed
+//!   via API 3.0
 
+use chrono::{DateTime, Utc};
 use derive_builder::Builder;
-use serde::{Deserialize, Serialize};
-use serde_aux::prelude::{deserialize_default_from_null, deserialize_number_from_string};
+use http::Method;
+use serde::{Deserialize, Deserializer, Serialize};
+use url::Url;
 
-use crate::{PesaPal, PesaPalError, PesaPalErrorResponse, PesaPalResult};
+use crate::pesapal::list_ipn::{CodeOrName, IPNList, IpnStatus, RawIpn};
+use crate::transport::HttpRequest;
+use crate::{Endpoint, PesaPal, PesaPalError, PesaPalResponse, PesaPalResult};
 
 const REGISTER_IPN_URL: &str = "api/URLSetup/RegisterIPN";
 
@@ -44,7 +48,7 @@ pub struct RegisterIPNRequest {
     pub ipn_notification_type: NotificationType,
 }
 
-impl From<RegisterIPN<'_>> for RegisterIPNRequest {
+impl From<RegisterIPN> for RegisterIPNRequest {
     fn from(value: RegisterIPN) -> Self {
         Self {
             url: value.url,
@@ -53,13 +57,43 @@ impl From<RegisterIPN<'_>> for RegisterIPNRequest {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum NotificationType {
     Get,
     Post,
 }
 
+impl NotificationType {
+    /// HTTP method `PesaPal` calls the IPN URL with
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Get => "GET",
+            Self::Post => "POST",
+        }
+    }
+
+    pub(crate) fn from_code_or_name(value: &CodeOrName) -> Option<Self> {
+        match value {
+            CodeOrName::Code(0) => Some(Self::Get),
+            CodeOrName::Code(1) => Some(Self::Post),
+            CodeOrName::Name(name) => Self::try_from(name.as_str()).ok(),
+            CodeOrName::Code(_) => None,
+        }
+    }
+}
+
+/// `PesaPal` returns the notification type either as a code, `0` for `GET`
+/// and `1` for `POST`, or as the HTTP method
+impl<'de> Deserialize<'de> for NotificationType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = CodeOrName::deserialize(deserializer)?;
+        Self::from_code_or_name(&value)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown notification type {value}")))
+    }
+}
+
 impl TryFrom<&str> for NotificationType {
     type Error = PesaPalError;
 
@@ -67,34 +101,99 @@ impl TryFrom<&str> for NotificationType {
         match value.to_lowercase().as_str() {
             "get" => Ok(Self::Get),
             "post" => Ok(Self::Post),
-            _ => Err(PesaPalError::Internal(
-                "could not parse {value} to notification type".to_string(),
-            )),
+            _ => Err(PesaPalError::Internal(format!(
+                "could not parse {value} to notification type"
+            ))),
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RawIpn")]
 pub struct RegisterIPNResponse {
     /// The notification url Pesapal will send a status alert to
-    pub url: String,
+    pub url: Url,
     /// Date and time the IPN URL was registered
     /// It is in UTC
-    pub created_date: String,
+    pub created_date: DateTime<Utc>,
     /// A unique identifier that's linked to he IPN endpoint URL
     pub ipn_id: String,
+    /// HTTP method `PesaPal` will call the URL with, when returned
+    pub notification_type: Option<NotificationType>,
+    /// Whether the URL is active, when returned
+    pub ipn_status: Option<IpnStatus>,
     /// Response code
-    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub status: u16,
-    #[serde(deserialize_with = "deserialize_default_from_null")]
-    /// Error response
-    pub error: Option<PesaPalErrorResponse>,
+}
+
+impl From<IPNList> for RegisterIPNResponse {
+    fn from(value: IPNList) -> Self {
+        Self {
+            url: value.url,
+            created_date: value.created_date,
+            ipn_id: value.ipn_id,
+            notification_type: value.notification_type,
+            ipn_status: value.ipn_status,
+            status: value.status,
+        }
+    }
+}
+
+impl TryFrom<RawIpn> for RegisterIPNResponse {
+    type Error = crate::pesapal::list_ipn::IpnEntryError;
+
+    fn try_from(value: RawIpn) -> Result<Self, Self::Error> {
+        IPNList::try_from(value).map(Self::from)
+    }
+}
+
+/// IPN URL returned by [`PesaPal::ensure_ipn`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnsuredIpn {
+    /// Id to send as the `notification_id` of the orders
+    pub ipn_id: String,
+    /// URL as registered with `PesaPal`
+    pub url: Url,
+    /// Whether the URL was registered by this call
+    pub created: bool,
+}
+
+/// Returns the id of the active IPN URL matching `url` and `notification_type`,
+/// registering it if there is none. An IPN without a notification type isn't
+/// reused, its method is unknown
+pub(crate) async fn ensure(
+    client: &PesaPal,
+    url: &str,
+    notification_type: NotificationType,
+) -> PesaPalResult<EnsuredIpn> {
+    let existing = client.list_ipn_urls().send().await?;
+    let matching = existing
+        .by_url(url)
+        .find(|ipn| ipn.is_active() && ipn.notification_type == Some(notification_type));
+
+    if let Some(ipn) = matching {
+        return Ok(EnsuredIpn {
+            ipn_id: ipn.ipn_id.clone(),
+            url: ipn.url.clone(),
+            created: false,
+        });
+    }
+
+    let mut builder = client.register_ipn_url();
+    builder.url(url).ipn_notification_type(notification_type);
+    let registered = builder.build()?.send().await?;
+
+    Ok(EnsuredIpn {
+        ipn_id: registered.ipn_id,
+        url: registered.url,
+        created: true,
+    })
 }
 
 #[derive(Debug, Builder)]
-pub struct RegisterIPN<'pesa> {
-    #[builder(pattern = "owned")]
-    client: &'pesa PesaPal,
+#[builder(derive(Debug))]
+pub struct RegisterIPN {
+    client: PesaPal,
     #[builder(setter(into))]
     #[doc = "THe notification URL pesapal will send status alert to"]
     url: String,
@@ -102,10 +201,12 @@ pub struct RegisterIPN<'pesa> {
     ipn_notification_type: NotificationType,
 }
 
-impl RegisterIPN<'_> {
+impl RegisterIPN {
     /// Creates an instance `RegisterIPNBuilder`
-    pub(crate) fn builder(client: &PesaPal) -> RegisterIPNBuilder {
-        RegisterIPNBuilder::default().client(client)
+    pub(crate) fn builder(client: PesaPal) -> RegisterIPNBuilder {
+        let mut builder = RegisterIPNBuilder::default();
+        builder.client(client);
+        builder
     }
 
     /// # Register IPN URL
@@ -121,23 +222,303 @@ impl RegisterIPN<'_> {
     ///
     /// [`PesaPalError::RegisterIPNError`] - Incase the registration fails
     pub async fn send(self) -> PesaPalResult<RegisterIPNResponse> {
-        let url = format!("{}/{REGISTER_IPN_URL}", self.client.env.base_url());
-
-        let response = self
-            .client
-            .http_client
-            .post(url)
-            .bearer_auth(&self.client.authenticate().await?)
-            .json::<RegisterIPNRequest>(&self.into())
+        self.send_with_meta().await.map(PesaPalResponse::into_inner)
+    }
+
+    /// # Register IPN URL with metadata
+    ///
+    /// Same as [`RegisterIPN::send`] but returns the HTTP metadata of the call
+    /// alongside the [`RegisterIPNResponse`]
+    ///
+    /// ## Errors
+    ///
+    /// [`PesaPalError::RegisterIPNError`] - Incase the registration fails
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "pesapal.register_ipn",
+            skip_all,
+            err(Display),
+            fields(
+                endpoint = "register_ipn",
+                environment = %self.client.inner.env,
+                url = %self.url,
+                http.status = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+            )
+        )
+    )]
+    pub async fn send_with_meta(self) -> PesaPalResult<PesaPalResponse<RegisterIPNResponse>> {
+        let url = format!("{}/{REGISTER_IPN_URL}", self.client.inner.env.base_url());
+        let client = self.client.clone();
+
+        let request =
+            HttpRequest::new(Method::POST, &url)?.json::<RegisterIPNRequest>(&self.into())?;
+
+        client
+            .execute(
+                Endpoint::RegisterIpn,
+                request,
+                |mut res: RawIpn| match res.error.take() {
+                    Some(error) => Err(PesaPalError::RegisterIPNError(error)),
+                    None => RegisterIPNResponse::try_from(res)
+                        .map_err(|e| PesaPalError::Internal(e.to_string())),
+                },
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::transport::mock::MockTransport;
+    use crate::{Environment, PesaPalErrorResponse, RateLimit};
+
+    #[tokio::test]
+    async fn test_register_ipn_through_transport() {
+        let transport = Arc::new(MockTransport::new().on(
+            REGISTER_IPN_URL,
+            200,
+            r#"{"url":"https://example.com/ipn","created_date":"2022-03-03T17:29:03.7208266Z","ipn_id":"e32182ca","error":null,"status":"200"}"#,
+        ));
+        let client = PesaPal::new_with_transport(
+            "register-ipn-key",
+            "secret",
+            Environment::Sandbox,
+            transport.clone(),
+        );
+
+        let response = client
+            .register_ipn_url()
+            .url("https://example.com/ipn")
+            .ipn_notification_type(NotificationType::Get)
+            .build()
+            .unwrap()
+            .send_with_meta()
+            .await
+            .unwrap();
+
+        assert_eq!(response.ipn_id, "e32182ca");
+        assert_eq!(response.status, http::StatusCode::OK);
+
+        let request = &transport.requests(REGISTER_IPN_URL)[0];
+        assert_eq!(request.method, Method::POST);
+        assert_eq!(request.headers["authorization"], "Bearer token");
+        assert_eq!(
+            request.headers["x-request-id"],
+            response.request_id.as_str()
+        );
+        assert_eq!(
+            request.body.as_deref().unwrap(),
+            br#"{"url":"https://example.com/ipn","ipn_notification_type":"GET"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_ipn_error() {
+        let transport = MockTransport::new().on(
+            REGISTER_IPN_URL,
+            400,
+            r#"{"url":"","created_date":"","ipn_id":"","error":{"code":"invalid_url","error_type":"api_error","message":"Invalid URL"},"status":"400"}"#,
+        );
+        let client = PesaPal::new_with_transport(
+            "register-ipn-key",
+            "secret",
+            Environment::Sandbox,
+            transport,
+        );
+
+        let response = client
+            .register_ipn_url()
+            .url("example")
+            .ipn_notification_type(NotificationType::Post)
+            .build()
+            .unwrap()
             .send()
-            .await?;
+            .await;
+
+        assert!(matches!(
+            response,
+            Err(PesaPalError::RegisterIPNError(PesaPalErrorResponse { ref code, .. })) if code == "invalid_url"
+        ));
+    }
 
-        let res: RegisterIPNResponse = response.json().await?;
+    #[tokio::test]
+    async fn test_register_ipn_request_is_static() {
+        let transport = MockTransport::new().on(
+            REGISTER_IPN_URL,
+            200,
+            r#"{"url":"https://example.com/ipn","created_date":"2022-03-03T17:29:03.7208266Z","ipn_id":"e32182ca","error":null,"status":"200"}"#,
+        );
+        let request = {
+            let client = PesaPal::new_with_transport(
+                "register-ipn-key",
+                "secret",
+                Environment::Sandbox,
+                transport,
+            );
+            client
+                .register_ipn_url()
+                .url("https://example.com/ipn")
+                .ipn_notification_type(NotificationType::Get)
+                .build()
+                .unwrap()
+        };
 
-        if let Some(error) = res.error {
-            return Err(PesaPalError::RegisterIPNError(error));
-        }
+        let response = tokio::spawn(request.send()).await.unwrap().unwrap();
+
+        assert_eq!(response.ipn_id, "e32182ca");
+    }
+
+    #[tokio::test]
+    async fn test_register_ipn_rate_limited() {
+        let transport = MockTransport::new().on(
+            REGISTER_IPN_URL,
+            200,
+            r#"{"url":"https://example.com/ipn","created_date":"2022-03-03T17:29:03.7208266Z","ipn_id":"e32182ca","error":null,"status":"200"}"#,
+        );
+        let client = PesaPal::new_with_transport(
+            "register-ipn-key",
+            "secret",
+            Environment::Sandbox,
+            transport,
+        )
+        .with_rate_limit(
+            Endpoint::RegisterIpn,
+            RateLimit::new(1, std::time::Duration::from_millis(50)),
+        );
+        let mut builder = client.register_ipn_url();
+        builder
+            .url("https://example.com/ipn")
+            .ipn_notification_type(NotificationType::Get);
+
+        let first = builder.build().unwrap().send_with_meta().await.unwrap();
+        let second = builder.build().unwrap().send_with_meta().await.unwrap();
+
+        assert!(first.rate_limit_wait.is_zero());
+        assert!(!second.rate_limit_wait.is_zero());
+    }
+
+    #[tokio::test]
+    async fn test_token_is_fetched_after_the_rate_limiter() {
+        let transport = Arc::new(MockTransport::new().on(
+            REGISTER_IPN_URL,
+            200,
+            r#"{"url":"https://example.com/ipn","created_date":"2022-03-03T17:29:03.7208266Z","ipn_id":"e32182ca","error":null,"status":"200"}"#,
+        ));
+        let client = PesaPal::new_with_transport(
+            "register-ipn-token-key",
+            "secret",
+            Environment::Sandbox,
+            Arc::clone(&transport),
+        )
+        .with_rate_limit(
+            Endpoint::RegisterIpn,
+            RateLimit::new(1, std::time::Duration::from_millis(100)),
+        );
+        let mut builder = client.register_ipn_url();
+        builder
+            .url("https://example.com/ipn")
+            .ipn_notification_type(NotificationType::Get);
+
+        builder.build().unwrap().send().await.unwrap();
+        // The second request is polled first and waits for the rate limiter,
+        // the token it was issued expires meanwhile
+        let (second, ()) = tokio::join!(builder.build().unwrap().send_with_meta(), async {
+            client.evict_token().await;
+        });
+
+        assert!(!second.unwrap().rate_limit_wait.is_zero());
+        assert_eq!(transport.requests("api/Auth/RequestToken").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_ensure_ipn_reuses_matching_url() {
+        let transport = Arc::new(
+            MockTransport::new()
+                .on(
+                    "api/URLSetup/GetIpnList",
+                    200,
+                    r#"{"ipns":[
+                        {"url":"https://Example.com/ipn/","created_date":"2022-03-03T17:29:03.7208266Z","ipn_id":"inactive","notification_type":1,"ipn_status":0,"error":null,"status":"200"},
+                        {"url":"https://Example.com/ipn/","created_date":"2022-03-03T17:29:03.7208266Z","ipn_id":"get","ipn_notification_type_description":"GET","ipn_status_description":"Active","error":null,"status":"200"},
+                        {"url":"https://Example.com/ipn/","created_date":"2022-03-03T17:29:03.7208266Z","ipn_id":"post","ipn_notification_type_description":"POST","ipn_status_description":"Active","error":null,"status":"200"}
+                    ]}"#,
+                )
+                .on(
+                    REGISTER_IPN_URL,
+                    200,
+                    r#"{"url":"https://example.com/other","created_date":"2022-03-03T17:29:03.7208266Z","ipn_id":"new","notification_type":1,"ipn_notification_type_description":"POST","ipn_status":1,"ipn_status_description":"Active","error":null,"status":"200"}"#,
+                ),
+        );
+        let client = PesaPal::new_with_transport(
+            "ensure-ipn-key",
+            "secret",
+            Environment::Sandbox,
+            Arc::clone(&transport),
+        );
+
+        let ipn = client
+            .ensure_ipn("https://example.com/IPN", NotificationType::Post)
+            .await
+            .unwrap();
+        assert_eq!(ipn.ipn_id, "post");
+        assert!(!ipn.created);
+        assert!(transport.requests(REGISTER_IPN_URL).is_empty());
+
+        let ipn = client
+            .ensure_ipn("https://example.com/other", NotificationType::Post)
+            .await
+            .unwrap();
+        assert_eq!(ipn.ipn_id, "new");
+        assert_eq!(ipn.url.as_str(), "https://example.com/other");
+        assert!(ipn.created);
+        assert_eq!(transport.requests(REGISTER_IPN_URL).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_ensure_ipn_registers_over_an_unknown_notification_type() {
+        let transport = Arc::new(
+            MockTransport::new()
+                .on(
+                    "api/URLSetup/GetIpnList",
+                    200,
+                    r#"{"ipns":[
+                        {"url":"https://example.com/ipn","created_date":"2022-03-03T17:29:03.7208266Z","ipn_id":"unknown","ipn_status":1,"error":null,"status":"200"}
+                    ]}"#,
+                )
+                .on(
+                    REGISTER_IPN_URL,
+                    200,
+                    r#"{"url":"https://example.com/ipn","created_date":"2022-03-03T17:29:03.7208266Z","ipn_id":"new","notification_type":0,"ipn_notification_type_description":"GET","ipn_status":1,"ipn_status_description":"Active","error":null,"status":"200"}"#,
+                ),
+        );
+        let client = PesaPal::new_with_transport(
+            "ensure-ipn-unknown-key",
+            "secret",
+            Environment::Sandbox,
+            Arc::clone(&transport),
+        );
+
+        let ipn = client
+            .ensure_ipn("https://example.com/ipn", NotificationType::Get)
+            .await
+            .unwrap();
+        assert_eq!(ipn.ipn_id, "new");
+        assert!(ipn.created);
+        assert_eq!(transport.requests(REGISTER_IPN_URL).len(), 1);
+    }
 
-        Ok(res)
+    #[test]
+    fn test_unknown_notification_type_error_names_the_value() {
+        let err = NotificationType::try_from("PUT").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            PesaPalError::Internal("could not parse PUT to notification type".to_string())
+                .to_string()
+        );
     }
 }
@@ -11,14 +11,23 @@
 //! as part of submit order request.
 
 use derive_builder::Builder;
+use http::Method;
 use serde::{Deserialize, Serialize};
 use serde_aux::prelude::deserialize_default_from_null;
 
+pub use super::billing_address::BillingAddress;
 use super::PesaPal;
 use crate::error::{PesaPalError, PesaPalErrorResponse, PesaPalResult};
+use crate::transport::HttpRequest;
+use crate::{telemetry, Endpoint, PesaPalResponse, ValidationErrors};
 
 const SUBMIT_ORDER_REQUEST_URL: &str = "api/Transactions/SubmitOrderRequest";
 
+/// Longest order description `PesaPal` accepts
+const DESCRIPTION_MAX_LENGTH: usize = 100;
+/// Longest merchant reference `PesaPal` accepts
+const MERCHANT_REFERENCE_MAX_LENGTH: usize = 50;
+
 /// Submit Order Request
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct SubmitOrderRequest {
@@ -35,7 +44,7 @@ pub struct SubmitOrderRequest {
     /// This parameter allows you to define where your callback URL will be
     /// loaded;
     /// * TOP_WINDOW returns to the topmost window in the hierarchy of
-    /// windows.
+    ///   windows.
     /// * PARENT_WINDOW returns the immediate parent of a window.
     pub redirect_mode: RedirectMode,
     /// A URL which PesaPal will redirect to process the payment
@@ -63,82 +72,12 @@ pub enum RedirectMode {
     ParentWindow,
 }
 
-/// The billing address of a customer
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Default)]
-pub struct BillingAddress {
-    /// customer's email address.
-    ///
-    /// This is Optional if phone number has been provided
-    /// Otherwise mandatory
-    ///
-    /// The BillingAddress will fail if this is not met
-    pub email_address: Option<String>,
-    /// Customer's Phone Number
-    ///
-    /// This is optional if email address has been provided,
-    /// Otherwise mandatory
-    ///
-    pub phone_number: Option<u64>,
-    /// country code in [ISO-3166-1]
-    ///
-    /// It is usually two characters long
-    ///
-    /// <https://en.wikipedia.org/wiki/ISO_3166-1>
-    pub country_code: Option<String>,
-    /// Customer's first name
-    pub first_name: Option<String>,
-    /// Customer's middle name
-    pub middle_name: Option<String>,
-    /// Customer's last name
-    pub last_name: Option<String>,
-    // Main Address
-    pub line_1: Option<String>,
-    // Alternative Address
-    pub line_2: Option<String>,
-    /// Customer's City
-    pub city: Option<String>,
-    /// Customer's state
-    ///
-    /// Maximum: three character long
-    pub state: Option<String>,
-    // Customer's postal code
-    pub postal_code: Option<String>,
-    /// Customer's zip code
-    pub zip_code: Option<String>,
-}
-
-impl BillingAddress {
-    /// Create a new billing address
-    ///
-    /// Either the phone number or the email must be provided,
-    /// otherwise the request will fail.
-    ///
-    /// # Returns
-    /// [`BillingAddress`] instance
-    ///
-    /// # Errors
-    ///
-    /// [`PesaPalError::ValidationError`] if either the email or the phone
-    /// number is not provided
-    pub fn new(&self, phone_number: Option<u64>, email: Option<String>) -> PesaPalResult<Self> {
-        if phone_number.is_none() && email.is_none() {
-            return Err(PesaPalError::ValidationError(
-                "either email or password need to be provided".to_string(),
-            ));
-        }
-
-        Ok(Self {
-            email_address: email,
-            phone_number,
-            ..Default::default()
-        })
-    }
-}
-
-impl From<SubmitOrder<'_>> for SubmitOrderRequest {
+impl From<SubmitOrder> for SubmitOrderRequest {
     fn from(value: SubmitOrder) -> Self {
         Self {
-            id: ulid::Ulid::new().to_string(),
+            id: value
+                .merchant_reference
+                .unwrap_or_else(|| ulid::Ulid::new().to_string()),
             currency: value.currency,
             amount: value.amount,
             description: value.description,
@@ -171,11 +110,20 @@ pub struct SubmitOrderResponse {
 }
 
 /// This is the submit order builder
+///
+/// `build` checks every field against the rules of `PesaPal` and reports all
+/// the problems found in a [`ValidationErrors`], before any request is sent.
 #[derive(Builder, Debug, Clone)]
-#[builder(build_fn(validate = "Self::validate"))]
-pub struct SubmitOrder<'pesa> {
-    #[builder(pattern = "owned")]
-    client: &'pesa PesaPal,
+#[builder(
+    derive(Debug),
+    build_fn(validate = "Self::validate", error = "ValidationErrors")
+)]
+pub struct SubmitOrder {
+    client: PesaPal,
+    #[builder(setter(into, strip_option), default)]
+    #[doc = r"Unique merchant reference of the order, generated if not set.
+    At most 50 alphanumeric, `-`, `_`, `.` and `:` characters"]
+    merchant_reference: Option<String>,
     #[builder(setter(into))]
     #[doc = r"Currency which is used to charge the customers"]
     currency: String,
@@ -212,23 +160,163 @@ pub struct SubmitOrder<'pesa> {
     billing_address: BillingAddress,
 }
 
-impl SubmitOrderBuilder<'_> {
-    /// Validate that either the email address or the phone number is provided
-    fn validate(&self) -> Result<(), String> {
-        if let Some(billing_address) = &self.billing_address {
-            if billing_address.email_address.is_none() && billing_address.phone_number.is_none() {
-                return Err("either email or phone number must be provided.".to_string());
+impl SubmitOrderBuilder {
+    /// Validate every field, reporting the missing ones along with the
+    /// invalid ones
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        match self.merchant_reference.as_ref().and_then(Option::as_deref) {
+            Some("") => {
+                errors.add("merchant_reference", "empty", "merchant_reference is empty");
             }
+            Some(reference) if reference.chars().count() > MERCHANT_REFERENCE_MAX_LENGTH => {
+                errors.add(
+                    "merchant_reference",
+                    "too_long",
+                    format!(
+                        "merchant_reference is {} characters long, at most {MERCHANT_REFERENCE_MAX_LENGTH} are allowed",
+                        reference.chars().count()
+                    ),
+                );
+            }
+            Some(reference)
+                if !reference
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':')) =>
+            {
+                errors.add(
+                    "merchant_reference",
+                    "invalid_characters",
+                    "merchant_reference may only contain letters, digits, '-', '_', '.' and ':'",
+                );
+            }
+            _ => {}
+        }
+
+        match self.currency.as_deref() {
+            None => {
+                errors.required("currency");
+            }
+            Some(currency)
+                if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_alphabetic()) =>
+            {
+                errors.add(
+                    "currency",
+                    "invalid_currency",
+                    format!("{currency:?} isn't an ISO 4217 currency code"),
+                );
+            }
+            Some(_) => {}
+        }
+
+        match self.amount {
+            None => {
+                errors.required("amount");
+            }
+            Some(0) => {
+                errors.add("amount", "zero", "amount must be greater than 0");
+            }
+            Some(_) => {}
         }
 
-        Ok(())
+        match self.description.as_deref().map(str::trim) {
+            None => {
+                errors.required("description");
+            }
+            Some("") => {
+                errors.add("description", "empty", "description is empty");
+            }
+            Some(description) if description.chars().count() > DESCRIPTION_MAX_LENGTH => {
+                errors.add(
+                    "description",
+                    "too_long",
+                    format!(
+                        "description is {} characters long, at most {DESCRIPTION_MAX_LENGTH} are allowed",
+                        description.chars().count()
+                    ),
+                );
+            }
+            Some(_) => {}
+        }
+
+        match self.callback_url.as_deref() {
+            None => {
+                errors.required("callback_url");
+            }
+            Some(url) => check_url(&mut errors, "callback_url", url),
+        }
+        if let Some(url) = self.cancellation_url.as_ref().and_then(Option::as_deref) {
+            check_url(&mut errors, "cancellation_url", url);
+        }
+
+        match self.notification_id.as_deref().map(str::trim) {
+            None => {
+                errors.required("notification_id");
+            }
+            Some("") => {
+                errors.add("notification_id", "empty", "notification_id is empty");
+            }
+            Some(_) => {}
+        }
+
+        match &self.billing_address {
+            None => {
+                errors.required("billing_address");
+            }
+            Some(billing_address) => {
+                for problem in billing_address.problems() {
+                    let path = problem.field().map_or_else(
+                        || "billing_address".to_string(),
+                        |field| format!("billing_address.{field}"),
+                    );
+                    errors.add(path, problem.code(), problem.to_string());
+                }
+            }
+        }
+
+        errors.into_result()
+    }
+}
+
+/// Records a problem if `url` isn't an absolute HTTPS URL
+fn check_url(errors: &mut ValidationErrors, path: &'static str, url: &str) {
+    match url::Url::parse(url.trim()) {
+        Err(e) => {
+            errors.add(
+                path,
+                "invalid_url",
+                format!("{url:?} isn't a valid URL : {e}"),
+            );
+        }
+        Ok(parsed) if parsed.scheme() != "https" => {
+            errors.add(path, "insecure_url", format!("{url:?} must use HTTPS"));
+        }
+        Ok(_) => {}
     }
 }
 
-impl SubmitOrder<'_> {
+impl SubmitOrder {
     /// This initializes the `SubmitOrder` with the client and returns a builder
-    pub(crate) fn builder(client: &PesaPal) -> SubmitOrderBuilder {
-        SubmitOrderBuilder::default().client(client)
+    ///
+    /// The builder is prefilled with the [`OrderDefaults`](crate::config::OrderDefaults) of the client.
+    pub(crate) fn builder(client: PesaPal) -> SubmitOrderBuilder {
+        let mut builder = SubmitOrderBuilder::default();
+        let defaults = &client.inner.defaults;
+        if let Some(currency) = &defaults.currency {
+            builder.currency(currency);
+        }
+        if let Some(callback_url) = &defaults.callback_url {
+            builder.callback_url(callback_url);
+        }
+        if let Some(cancellation_url) = &defaults.cancellation_url {
+            builder.cancellation_url(cancellation_url);
+        }
+        if let Some(notification_id) = &defaults.notification_id {
+            builder.notification_id(notification_id);
+        }
+        builder.client(client);
+        builder
     }
 
     /// # Submit Order Request
@@ -244,23 +332,197 @@ impl SubmitOrder<'_> {
     ///
     /// [`PesaPalError::SubmitOrderError`] - Incase the payment fails
     pub async fn send(self) -> PesaPalResult<SubmitOrderResponse> {
-        let url = format!("{}/{SUBMIT_ORDER_REQUEST_URL}", self.client.env.base_url());
-
-        let response = self
-            .client
-            .http_client
-            .post(url)
-            .bearer_auth(&self.client.authenticate().await?)
-            .json::<SubmitOrderRequest>(&self.into())
+        self.send_with_meta().await.map(PesaPalResponse::into_inner)
+    }
+
+    /// # Submit Order Request with metadata
+    ///
+    /// Same as [`SubmitOrder::send`] but returns the HTTP metadata of the
+    /// call alongside the [`SubmitOrderResponse`]
+    ///
+    /// ## Errors
+    ///
+    /// [`PesaPalError::SubmitOrderError`] - Incase the payment fails
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "pesapal.submit_order",
+            skip_all,
+            err(Display),
+            fields(
+                endpoint = "submit_order",
+                environment = %self.client.inner.env,
+                merchant_reference = tracing::field::Empty,
+                http.status = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+            )
+        )
+    )]
+    pub async fn send_with_meta(self) -> PesaPalResult<PesaPalResponse<SubmitOrderResponse>> {
+        let url = format!(
+            "{}/{SUBMIT_ORDER_REQUEST_URL}",
+            self.client.inner.env.base_url()
+        );
+        let client = self.client.clone();
+
+        let payload: SubmitOrderRequest = self.into();
+        telemetry::record("merchant_reference", &payload.id);
+
+        let request = HttpRequest::new(Method::POST, &url)?.json(&payload)?;
+
+        client
+            .execute(
+                Endpoint::SubmitOrder,
+                request,
+                |mut res: SubmitOrderResponse| match res.error.take() {
+                    Some(error) => Err(PesaPalError::SubmitOrderError(error)),
+                    None => Ok(res),
+                },
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::transport::mock::MockTransport;
+    use crate::Environment;
+
+    #[test]
+    fn test_validation_reports_every_problem() {
+        let client = PesaPal::new_with_transport(
+            "submit-order-key",
+            "secret",
+            Environment::Sandbox,
+            MockTransport::new(),
+        );
+
+        let errors = client
+            .submit_order()
+            .merchant_reference("a".repeat(51))
+            .currency("KES")
+            .amount(0)
+            .description("d".repeat(101))
+            .callback_url("http://example.com/callback")
+            .cancellation_url("example.com/cancel")
+            .billing_address(BillingAddress {
+                email_address: Some("customer@example".to_string()),
+                state: Some("Nairobi".to_string()),
+                ..Default::default()
+            })
+            .build()
+            .unwrap_err();
+
+        assert_eq!(
+            errors
+                .errors()
+                .iter()
+                .map(|error| (error.path.as_str(), error.code))
+                .collect::<Vec<_>>(),
+            [
+                ("merchant_reference", "too_long"),
+                ("amount", "zero"),
+                ("description", "too_long"),
+                ("callback_url", "insecure_url"),
+                ("cancellation_url", "invalid_url"),
+                ("notification_id", "required"),
+                ("billing_address.email_address", "invalid_email"),
+                ("billing_address.state", "too_long"),
+            ]
+        );
+        assert_eq!(errors.field("billing_address").count(), 2);
+        assert!(matches!(
+            PesaPalError::from(errors),
+            PesaPalError::ValidationErrors(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_merchant_reference_is_sent() {
+        let transport = Arc::new(MockTransport::new().on(
+            SUBMIT_ORDER_REQUEST_URL,
+            200,
+            r#"{"order_tracking_id":"tracking","merchant_reference":"order-42","redirect_url":"https://example.com","error":null,"status":"200"}"#,
+        ));
+        let client = PesaPal::new_with_transport(
+            "submit-order-key",
+            "secret",
+            Environment::Sandbox,
+            Arc::clone(&transport),
+        );
+
+        client
+            .submit_order()
+            .merchant_reference("order-42")
+            .currency("KES")
+            .amount(100)
+            .description("order")
+            .callback_url("https://example.com/callback")
+            .notification_id("ipn")
+            .billing_address(BillingAddress {
+                email_address: Some("customer@example.com".to_string()),
+                ..Default::default()
+            })
+            .build()
+            .unwrap()
             .send()
-            .await?;
+            .await
+            .unwrap();
 
-        let res: SubmitOrderResponse = response.json().await?;
+        let request = &transport.requests(SUBMIT_ORDER_REQUEST_URL)[0];
+        let body: serde_json::Value =
+            serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+        assert_eq!(body["id"], "order-42");
+    }
 
-        if let Some(error) = res.error {
-            return Err(PesaPalError::SubmitOrderError(error));
-        }
+    #[tokio::test]
+    async fn test_errors_keep_response_meta() {
+        let transport = Arc::new(
+            MockTransport::new()
+                .on(SUBMIT_ORDER_REQUEST_URL, 502, "<html>Bad Gateway</html>")
+                .on(
+                    SUBMIT_ORDER_REQUEST_URL,
+                    200,
+                    r#"{"order_tracking_id":"","merchant_reference":"","redirect_url":"","error":{"error_type":"api_error","code":"invalid_amount","message":"Invalid amount"},"status":"500"}"#,
+                ),
+        );
+        let client = PesaPal::new_with_transport(
+            "submit-order-meta-key",
+            "secret",
+            Environment::Sandbox,
+            Arc::clone(&transport),
+        );
+        let order = client
+            .submit_order()
+            .currency("KES")
+            .amount(100)
+            .description("order")
+            .callback_url("https://example.com/callback")
+            .notification_id("ipn")
+            .billing_address(BillingAddress {
+                email_address: Some("customer@example.com".to_string()),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+
+        let error = order.clone().send().await.unwrap_err();
+        assert!(matches!(error, PesaPalError::InvalidResponse { .. }));
+        let meta = error.response_meta().unwrap();
+        assert_eq!(meta.status, http::StatusCode::BAD_GATEWAY);
+        assert_eq!(meta.raw_body, "<html>Bad Gateway</html>");
 
-        Ok(res)
+        let error = order.send().await.unwrap_err();
+        assert!(
+            matches!(error, PesaPalError::SubmitOrderError(ref e) if e.code == "invalid_amount")
+        );
+        let requests = transport.requests(SUBMIT_ORDER_REQUEST_URL);
+        assert_eq!(
+            error.response_meta().unwrap().request_id,
+            requests[1].headers[crate::response::REQUEST_ID_HEADER]
+        );
     }
 }
@@ -3,7 +3,17 @@
 //! IPN URL, you need to check the status of the payment using the
 //! `OrderTrackingId`.
 
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cached::stores::{CanExpire, ExpiringValueCache};
+use cached::Cached;
 use derive_builder::Builder;
+use futures::channel::oneshot;
+use futures::future::{FutureExt, Shared};
+use futures::{Stream, StreamExt};
+use http::Method;
 use serde::Deserialize;
 
 const TRANSACTION_STATUS_URL: &str = "api/Transactions/GetTransactionStatus";
@@ -12,9 +22,10 @@ use serde_aux::prelude::deserialize_number_from_string;
 use serde_repr::Deserialize_repr;
 
 use crate::error::TransactionStatusError;
-use crate::{PesaPal, PesaPalError, PesaPalResult};
+use crate::transport::HttpRequest;
+use crate::{telemetry, Endpoint, PesaPal, PesaPalError, PesaPalResponse, PesaPalResult};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TransactionStatusResponse {
     /// This refers to the payment method used by your customers to make
@@ -55,7 +66,7 @@ pub struct TransactionStatusResponse {
     pub status: u16,
 }
 
-#[derive(Debug, Deserialize_repr)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize_repr)]
 #[repr(u8)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum StatusCode {
@@ -66,6 +77,31 @@ pub enum StatusCode {
     Reversed = 3,
 }
 
+impl StatusCode {
+    /// Whether the transaction reached a state which never changes
+    #[must_use]
+    pub const fn is_final(&self) -> bool {
+        matches!(self, Self::Completed | Self::Failed | Self::Reversed)
+    }
+
+    /// Name of the status as used by `PesaPal`
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Invalid => "INVALID",
+            Self::Completed => "COMPLETED",
+            Self::Failed => "FAILED",
+            Self::Reversed => "REVERSED",
+        }
+    }
+}
+
+impl std::fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 impl TryFrom<u8> for StatusCode {
     type Error = PesaPalError;
 
@@ -87,19 +123,21 @@ impl From<StatusCode> for u8 {
 }
 
 #[derive(Debug, Builder)]
-pub struct TransactionStatus<'pesa> {
-    #[builder(pattern = "owned")]
+#[builder(derive(Debug))]
+pub struct TransactionStatus {
     /// Pesapal Client
-    pub client: &'pesa PesaPal,
+    pub client: PesaPal,
     #[builder(setter(into))]
     /// Unique order id generated by Pesapal
     pub order_tracking_id: String,
 }
 
-impl<'pesa> TransactionStatus<'pesa> {
+impl TransactionStatus {
     /// Initiates a new [`TransactionStatusBuilder`]
-    pub(crate) fn builder(client: &'pesa PesaPal) -> TransactionStatusBuilder {
-        TransactionStatusBuilder::default().client(client)
+    pub(crate) fn builder(client: PesaPal) -> TransactionStatusBuilder {
+        let mut builder = TransactionStatusBuilder::default();
+        builder.client(client);
+        builder
     }
 
     /// # Sends a Transaction Status Request
@@ -115,23 +153,510 @@ impl<'pesa> TransactionStatus<'pesa> {
     /// [`PesaPalError::TransactionStatusError`] - with status 500 and error
     /// message incase the refund
     pub async fn send(&self) -> PesaPalResult<TransactionStatusResponse> {
-        let url = format!("{}/{TRANSACTION_STATUS_URL}", self.client.env.base_url());
+        self.send_with_meta().await.map(PesaPalResponse::into_inner)
+    }
+
+    /// # Sends a Transaction Status Request with metadata
+    ///
+    /// Same as [`TransactionStatus::send`] but returns the HTTP metadata of
+    /// the call alongside the [`TransactionStatusResponse`]
+    ///
+    /// Concurrent lookups of the same order tracking id made through the same
+    /// client share a single request. When a [`StatusCache`] is set on the
+    /// client, the cached response is returned with the metadata of the call
+    /// which fetched it.
+    ///
+    /// ## Errors
+    ///
+    /// [`PesaPalError::TransactionStatusError`] - with status 500 and error
+    /// message incase the refund
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "pesapal.transaction_status",
+            skip_all,
+            err(Display),
+            fields(
+                endpoint = "transaction_status",
+                environment = %self.client.inner.env,
+                order_tracking_id = %self.order_tracking_id,
+                cache_hit = tracing::field::Empty,
+                coalesced = tracing::field::Empty,
+                http.status = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+            )
+        )
+    )]
+    pub async fn send_with_meta(
+        &self,
+    ) -> PesaPalResult<PesaPalResponse<TransactionStatusResponse>> {
+        Arc::clone(&self.client.inner.status_lookups)
+            .lookup(self)
+            .await
+    }
+
+    /// Sends the request to `PesaPal` even if the status is cached, the cache
+    /// is then updated with the response
+    ///
+    /// Used to follow statuses which change after they are final, e.g.
+    /// Completed payments which get Reversed by a refund.
+    pub(crate) async fn send_uncached(&self) -> PesaPalResult<TransactionStatusResponse> {
+        let response = self.fetch().await?;
+        self.client
+            .inner
+            .status_lookups
+            .store(&self.order_tracking_id, &response);
+        Ok(response.into_inner())
+    }
+
+    /// Sends the request to `PesaPal`, bypassing the coalescing and the cache
+    async fn fetch(&self) -> PesaPalResult<PesaPalResponse<TransactionStatusResponse>> {
+        let url = format!(
+            "{}/{TRANSACTION_STATUS_URL}",
+            self.client.inner.env.base_url()
+        );
+
+        let request =
+            HttpRequest::new(Method::GET, &url)?.query("OrderTrackingId", &self.order_tracking_id);
+
+        self.client
+            .execute(
+                Endpoint::TransactionStatus,
+                request,
+                |res: TransactionStatusResponse| {
+                    if res.status != 200 {
+                        return Err(PesaPalError::TransactionStatusError(res.error));
+                    }
+                    Ok(res)
+                },
+            )
+            .await
+    }
+}
+
+/// Cache of the transaction status responses
+///
+/// Set on the client with
+/// [`PesaPal::with_transaction_status_cache`](crate::PesaPal::with_transaction_status_cache).
+/// Responses of transactions in a final state (Completed, Failed or Reversed)
+/// never change, hence they are kept longer than the pending ones. Error
+/// responses are not cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusCache {
+    pending_ttl: Duration,
+    final_ttl: Duration,
+    capacity: usize,
+}
+
+impl Default for StatusCache {
+    fn default() -> Self {
+        Self {
+            pending_ttl: Duration::from_secs(2),
+            final_ttl: Duration::from_secs(600),
+            capacity: 10_000,
+        }
+    }
+}
+
+impl StatusCache {
+    /// Creates a cache keeping pending statuses for 2 seconds and final
+    /// statuses for 10 minutes, with room for 10 000 orders
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time a status which may still change is cached, zero disables it
+    #[must_use]
+    pub const fn pending_ttl(mut self, ttl: Duration) -> Self {
+        self.pending_ttl = ttl;
+        self
+    }
+
+    /// Time a Completed, Failed or Reversed status is cached
+    #[must_use]
+    pub const fn final_ttl(mut self, ttl: Duration) -> Self {
+        self.final_ttl = ttl;
+        self
+    }
+
+    /// Maximum number of orders cached, the least recently used are evicted
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero
+    #[must_use]
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "status cache capacity must be greater than 0");
+        self.capacity = capacity;
+        self
+    }
+
+    /// Time the `response` can be cached for
+    fn ttl(&self, response: &TransactionStatusResponse) -> Duration {
+        if response.status_code.is_final() {
+            self.final_ttl
+        } else {
+            self.pending_ttl
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CachedStatus {
+    response: PesaPalResponse<TransactionStatusResponse>,
+    expires_at: Instant,
+}
+
+impl CanExpire for CachedStatus {
+    fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires_at
+    }
+}
+
+/// Result handed to the callers waiting on an in flight lookup
+type SharedResult = Result<PesaPalResponse<TransactionStatusResponse>, Arc<PesaPalError>>;
+
+/// Transaction status lookups shared by the clones of a client
+///
+/// The first caller for an order tracking id sends the request, the callers
+/// arriving while it is in flight wait for its result instead of sending
+/// their own.
+pub(crate) struct StatusLookups {
+    in_flight: Mutex<HashMap<String, Shared<oneshot::Receiver<SharedResult>>>>,
+    cache: Option<(StatusCache, Mutex<ExpiringValueCache<String, CachedStatus>>)>,
+}
+
+impl std::fmt::Debug for StatusLookups {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StatusLookups")
+            .field("in_flight", &self.lock_in_flight().len())
+            .field("cache", &self.cache.as_ref().map(|(config, _)| config))
+            .finish()
+    }
+}
+
+impl StatusLookups {
+    pub(crate) fn new(cache: Option<StatusCache>) -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+            cache: cache.map(|config| {
+                (
+                    config,
+                    Mutex::new(ExpiringValueCache::with_size(config.capacity)),
+                )
+            }),
+        }
+    }
+
+    /// Returns the cached status, joins the lookup in flight or sends a new
+    /// request
+    async fn lookup(
+        self: Arc<Self>,
+        request: &TransactionStatus,
+    ) -> PesaPalResult<PesaPalResponse<TransactionStatusResponse>> {
+        let order_tracking_id = &request.order_tracking_id;
+
+        loop {
+            if let Some(response) = self.cached(order_tracking_id) {
+                telemetry::record("cache_hit", "true");
+                return Ok(response);
+            }
+
+            let in_flight = {
+                let mut in_flight = self.lock_in_flight();
+                if let Some(lookup) = in_flight.get(order_tracking_id) {
+                    Err(lookup.clone())
+                } else {
+                    let (sender, receiver) = oneshot::channel();
+                    in_flight.insert(order_tracking_id.clone(), receiver.shared());
+                    Ok(sender)
+                }
+            };
+
+            match in_flight {
+                Ok(sender) => {
+                    telemetry::record("coalesced", "false");
+                    return self.lead(request, sender).await;
+                }
+                Err(lookup) => {
+                    telemetry::record("coalesced", "true");
+                    if let Ok(result) = lookup.await {
+                        return result.map_err(|error| error.duplicate());
+                    }
+                    // The caller which sent the request gave up, try again
+                }
+            }
+        }
+    }
+
+    /// Sends the request and hands its result to the callers waiting on it
+    async fn lead(
+        &self,
+        request: &TransactionStatus,
+        sender: oneshot::Sender<SharedResult>,
+    ) -> PesaPalResult<PesaPalResponse<TransactionStatusResponse>> {
+        let guard = InFlight {
+            lookups: self,
+            order_tracking_id: &request.order_tracking_id,
+        };
+
+        let result = request.fetch().await;
+        if let Ok(response) = &result {
+            self.store(&request.order_tracking_id, response);
+        }
+        drop(guard);
+
+        let shared = match &result {
+            Ok(response) => Ok(response.clone()),
+            Err(error) => Err(Arc::new(error.duplicate())),
+        };
+        let _ = sender.send(shared);
+
+        result
+    }
+
+    fn cached(
+        &self,
+        order_tracking_id: &str,
+    ) -> Option<PesaPalResponse<TransactionStatusResponse>> {
+        let (_, cache) = self.cache.as_ref()?;
+        let mut cache = cache.lock().expect("status cache lock poisoned");
+        cache
+            .cache_get(order_tracking_id)
+            .map(|cached| cached.response.clone())
+    }
+
+    fn store(
+        &self,
+        order_tracking_id: &str,
+        response: &PesaPalResponse<TransactionStatusResponse>,
+    ) {
+        let Some((config, cache)) = &self.cache else {
+            return;
+        };
+        let ttl = config.ttl(&response.body);
+        if ttl.is_zero() {
+            return;
+        }
+
+        cache.lock().expect("status cache lock poisoned").cache_set(
+            order_tracking_id.to_string(),
+            CachedStatus {
+                response: response.clone(),
+                expires_at: Instant::now() + ttl,
+            },
+        );
+    }
+
+    fn lock_in_flight(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<String, Shared<oneshot::Receiver<SharedResult>>>> {
+        self.in_flight.lock().expect("status lookups lock poisoned")
+    }
+}
+
+/// Removes the lookup from the in flight ones once it completes or is
+/// cancelled
+struct InFlight<'a> {
+    lookups: &'a StatusLookups,
+    order_tracking_id: &'a str,
+}
 
-        let response = self
-            .client
-            .http_client
-            .get(url)
-            .bearer_auth(&self.client.authenticate().await?)
-            .query(&[("OrderTrackingId", &self.order_tracking_id)])
-            .send()
-            .await?;
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.lookups.lock_in_flight().remove(self.order_tracking_id);
+    }
+}
 
-        let res: TransactionStatusResponse = response.json().await?;
+/// Default number of transaction status requests in flight at once
+const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Bulk transaction status lookup
+///
+/// Created with [`PesaPal::transaction_statuses`]. Each order tracking id is
+/// checked with its own [`TransactionStatus`] request, at most `concurrency`
+/// of them in flight at once. Rate limits set on the client are respected.
+/// A failed lookup doesn't stop the others, the result of each id is
+/// returned. Duplicate ids are only checked once.
+#[derive(Debug)]
+pub struct TransactionStatuses {
+    client: PesaPal,
+    order_tracking_ids: Vec<String>,
+    concurrency: usize,
+}
 
-        if res.status != 200 {
-            return Err(PesaPalError::TransactionStatusError(res.error));
+impl TransactionStatuses {
+    pub(crate) fn new<I>(client: PesaPal, order_tracking_ids: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let mut seen = HashSet::new();
+        let order_tracking_ids = order_tracking_ids
+            .into_iter()
+            .map(Into::into)
+            .filter(|id: &String| seen.insert(id.clone()))
+            .collect();
+
+        Self {
+            client,
+            order_tracking_ids,
+            concurrency: DEFAULT_CONCURRENCY,
         }
+    }
+
+    /// Sets the maximum number of requests in flight at once, defaults to 8
+    ///
+    /// `0` is raised to `1`.
+    #[must_use]
+    pub const fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = if concurrency == 0 { 1 } else { concurrency };
+        self
+    }
+
+    /// Streams the result of each order tracking id as soon as it is available
+    ///
+    /// The results are not in the order of the ids.
+    pub fn stream(
+        self,
+    ) -> impl Stream<Item = (String, PesaPalResult<TransactionStatusResponse>)> + Send + 'static
+    {
+        let client = self.client;
+
+        futures::stream::iter(self.order_tracking_ids)
+            .map(move |order_tracking_id| {
+                let request = TransactionStatus {
+                    client: client.clone(),
+                    order_tracking_id,
+                };
+                async move {
+                    let response = request.send().await;
+                    (request.order_tracking_id, response)
+                }
+            })
+            .buffer_unordered(self.concurrency)
+    }
+
+    /// Waits for all the lookups and collects the results by order tracking id
+    pub async fn collect(self) -> HashMap<String, PesaPalResult<TransactionStatusResponse>> {
+        self.stream().collect().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+    use crate::Environment;
+
+    const COMPLETED: &str = r#"{"paymentMethod":"Visa","amount":100,"createdDate":"2022-04-30T07:41:09.763","confirmationCode":"6513008693186320103009","paymentStatusDescription":1,"description":"","message":"ok","paymentAccount":"476173**0010","callBackUrl":"https://example.com","statusCode":1,"merchantReference":"TEST1515111119","currency":"KES","error":{"error_type":"","code":"","message":"","call_back_url":""},"status":"200"}"#;
+    const PENDING: &str = r#"{"paymentMethod":"Visa","amount":100,"createdDate":"2022-04-30T07:41:09.763","confirmationCode":"6513008693186320103009","paymentStatusDescription":1,"description":"","message":"ok","paymentAccount":"476173**0010","callBackUrl":"https://example.com","statusCode":0,"merchantReference":"TEST1515111119","currency":"KES","error":{"error_type":"","code":"","message":"","call_back_url":""},"status":"200"}"#;
+
+    #[tokio::test]
+    async fn test_concurrent_lookups_are_coalesced() {
+        let transport = Arc::new(
+            MockTransport::new()
+                .on(TRANSACTION_STATUS_URL, 200, PENDING)
+                .delay(Duration::from_millis(20)),
+        );
+        let client = PesaPal::new_with_transport(
+            "coalesced-status-key",
+            "secret",
+            Environment::Sandbox,
+            Arc::clone(&transport),
+        );
+        let request = |id: &str| {
+            client
+                .transaction_status()
+                .order_tracking_id(id)
+                .build()
+                .unwrap()
+        };
+        let (first, second, third) = (request("order"), request("order"), request("other"));
+
+        let (first, second, third) = tokio::join!(
+            first.send_with_meta(),
+            second.send_with_meta(),
+            third.send_with_meta()
+        );
+
+        assert_eq!(first.unwrap().request_id, second.unwrap().request_id);
+        assert!(third.is_ok());
+        assert_eq!(transport.requests(TRANSACTION_STATUS_URL).len(), 2);
+
+        // Without a cache the next lookup sends a new request
+        request("order").send().await.unwrap();
+        assert_eq!(transport.requests(TRANSACTION_STATUS_URL).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_final_statuses_are_cached_longer() {
+        let transport = Arc::new(
+            MockTransport::new()
+                .on(TRANSACTION_STATUS_URL, 200, PENDING)
+                .on(TRANSACTION_STATUS_URL, 200, PENDING)
+                .on(TRANSACTION_STATUS_URL, 200, COMPLETED),
+        );
+        let client = PesaPal::new_with_transport(
+            "cached-status-key",
+            "secret",
+            Environment::Sandbox,
+            Arc::clone(&transport),
+        )
+        .with_transaction_status_cache(StatusCache::new().pending_ttl(Duration::ZERO));
+        let status = || async {
+            client
+                .transaction_status()
+                .order_tracking_id("order")
+                .build()
+                .unwrap()
+                .send()
+                .await
+                .unwrap()
+                .status_code
+        };
+
+        assert_eq!(status().await, StatusCode::Invalid);
+        assert_eq!(status().await, StatusCode::Invalid);
+        assert_eq!(status().await, StatusCode::Completed);
+        assert_eq!(status().await, StatusCode::Completed);
+        assert_eq!(transport.requests(TRANSACTION_STATUS_URL).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_transaction_statuses_returns_every_result() {
+        let transport = Arc::new(
+            MockTransport::new()
+                .on(
+                    TRANSACTION_STATUS_URL,
+                    200,
+                    r#"{"paymentMethod":"Visa","amount":100,"createdDate":"2022-04-30T07:41:09.763","confirmationCode":"6513008693186320103009","paymentStatusDescription":1,"description":"","message":"ok","paymentAccount":"476173**0010","callBackUrl":"https://example.com","statusCode":1,"merchantReference":"TEST1515111119","currency":"KES","error":{"error_type":"","code":"","message":"","call_back_url":""},"status":"200"}"#,
+                )
+                .on(
+                    TRANSACTION_STATUS_URL,
+                    200,
+                    r#"{"paymentMethod":"Visa","amount":100,"createdDate":"2022-04-30T07:41:09.763","confirmationCode":"6513008693186320103009","paymentStatusDescription":1,"description":"","message":"ok","paymentAccount":"476173**0010","callBackUrl":"https://example.com","statusCode":1,"merchantReference":"TEST1515111119","currency":"KES","error":{"error_type":"api_error","code":"invalid_tracking_id","message":"Invalid tracking id","call_back_url":""},"status":"500"}"#,
+                ),
+        );
+        let client = PesaPal::new_with_transport(
+            "transaction-statuses-key",
+            "secret",
+            Environment::Sandbox,
+            transport.clone(),
+        );
+
+        let statuses = client
+            .transaction_statuses(["paid", "unknown", "paid"])
+            .concurrency(1)
+            .collect()
+            .await;
 
-        Ok(res)
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses["paid"].as_ref().unwrap().amount, 100);
+        assert!(matches!(
+            statuses["unknown"],
+            Err(PesaPalError::TransactionStatusError(ref error)) if error.code == "invalid_tracking_id"
+        ));
+        assert_eq!(transport.requests(TRANSACTION_STATUS_URL).len(), 2);
     }
 }
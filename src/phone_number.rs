@@ -0,0 +1,293 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer};
+
+use crate::CountryCode;
+
+/// Numbering plan of a country served by `PesaPal`
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Country {
+    code: CountryCode,
+    calling_code: &'static str,
+    /// Digits of the national number, without the trunk `0`
+    digits: usize,
+}
+
+const COUNTRIES: &[Country] = &[
+    Country::new(CountryCode::KE, "254", 9),
+    Country::new(CountryCode::UG, "256", 9),
+    Country::new(CountryCode::TZ, "255", 9),
+    Country::new(CountryCode::RW, "250", 9),
+    Country::new(CountryCode::MW, "265", 9),
+    Country::new(CountryCode::ZM, "260", 9),
+    Country::new(CountryCode::ZW, "263", 9),
+];
+
+impl Country {
+    const fn new(code: CountryCode, calling_code: &'static str, digits: usize) -> Self {
+        Self {
+            code,
+            calling_code,
+            digits,
+        }
+    }
+}
+
+/// Phone number of a customer in one of the countries served by `PesaPal`
+///
+/// Numbers are accepted in their international form, `+254712345678`,
+/// `254712345678` or `00254712345678`, and in their local form,
+/// `0712345678`, when the country is known. Spaces, dashes, dots and
+/// parentheses are ignored.
+///
+/// The number is sent to `PesaPal` in its local form, with the leading `0`,
+/// which is what mobile money prompts expect.
+///
+/// # Example
+/// ```
+/// use pesapal::{CountryCode, PhoneNumber};
+///
+/// let phone: PhoneNumber = "+254 712 345 678".parse().unwrap();
+/// assert_eq!(phone, PhoneNumber::parse_local("0712345678", CountryCode::KE).unwrap());
+/// assert_eq!(phone.national(), "0712345678");
+/// assert_eq!(phone.e164(), "+254712345678");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PhoneNumber {
+    country: &'static Country,
+    subscriber: String,
+}
+
+impl PhoneNumber {
+    /// Parses a number in its international form
+    ///
+    /// # Errors
+    ///
+    /// [`PhoneNumberError`] if the number isn't valid, is in its local form
+    /// or is from a country not served by `PesaPal`
+    pub fn parse(input: &str) -> Result<Self, PhoneNumberError> {
+        Self::parse_with(input, None)
+    }
+
+    /// Parses a number in its local or international form, numbers in their
+    /// local form are taken to be from `country_code`
+    ///
+    /// # Errors
+    ///
+    /// [`PhoneNumberError`] if the number isn't valid or the country isn't
+    /// served by `PesaPal`
+    pub fn parse_local(input: &str, country_code: CountryCode) -> Result<Self, PhoneNumberError> {
+        Self::parse_with(input, Some(country_code))
+    }
+
+    fn parse_with(input: &str, country: Option<CountryCode>) -> Result<Self, PhoneNumberError> {
+        let compact: String = input
+            .chars()
+            .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '.' | '(' | ')'))
+            .collect();
+        if compact.is_empty() {
+            return Err(PhoneNumberError::Empty);
+        }
+
+        let (international, digits) = match compact.strip_prefix('+') {
+            Some(digits) => (true, digits),
+            None => match compact.strip_prefix("00") {
+                Some(digits) => (true, digits),
+                None => (false, compact.as_str()),
+            },
+        };
+        if let Some(invalid) = digits.chars().find(|c| !c.is_ascii_digit()) {
+            return Err(PhoneNumberError::InvalidCharacter(invalid));
+        }
+
+        let calling_code = COUNTRIES.iter().find(|country| {
+            digits.starts_with(country.calling_code)
+                && (international || digits.len() > country.digits + 1)
+        });
+        let (country, national) = match (calling_code, country) {
+            (Some(found), _) => (found, &digits[found.calling_code.len()..]),
+            (None, _) if international => {
+                return Err(PhoneNumberError::UnsupportedCountry(format!(
+                    "+{}",
+                    &digits[..digits.len().min(3)]
+                )))
+            }
+            (None, Some(code)) => {
+                let country = COUNTRIES
+                    .iter()
+                    .find(|country| country.code == code)
+                    .ok_or_else(|| PhoneNumberError::UnsupportedCountry(code.to_string()))?;
+                (country, digits)
+            }
+            (None, None) => return Err(PhoneNumberError::MissingCountry(input.to_string())),
+        };
+
+        // The trunk `0` is often kept after the calling code, `+2540712345678`
+        let subscriber = national.strip_prefix('0').unwrap_or(national);
+        if subscriber.len() != country.digits || subscriber.starts_with('0') {
+            return Err(PhoneNumberError::InvalidLength {
+                country: country.code,
+                expected: country.digits,
+                found: subscriber.len(),
+            });
+        }
+
+        Ok(Self {
+            country,
+            subscriber: subscriber.to_string(),
+        })
+    }
+
+    /// Country of the number
+    #[must_use]
+    pub fn country_code(&self) -> CountryCode {
+        self.country.code
+    }
+
+    /// Calling code of the country of the number, without the `+`
+    #[must_use]
+    pub fn calling_code(&self) -> &'static str {
+        self.country.calling_code
+    }
+
+    /// Number in its local form, `0712345678`, as sent to `PesaPal`
+    #[must_use]
+    pub fn national(&self) -> String {
+        format!("0{}", self.subscriber)
+    }
+
+    /// Number in its E.164 form, `+254712345678`
+    #[must_use]
+    pub fn e164(&self) -> String {
+        format!("+{}{}", self.country.calling_code, self.subscriber)
+    }
+}
+
+impl fmt::Display for PhoneNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.e164())
+    }
+}
+
+impl FromStr for PhoneNumber {
+    type Err = PhoneNumberError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<&str> for PhoneNumber {
+    type Error = PhoneNumberError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::parse(value)
+    }
+}
+
+impl TryFrom<String> for PhoneNumber {
+    type Error = PhoneNumberError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl Serialize for PhoneNumber {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.national())
+    }
+}
+
+/// Reason a [`PhoneNumber`] couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum PhoneNumberError {
+    /// Nothing but separators was given
+    #[error("phone number is empty")]
+    Empty,
+    /// Only digits, separators and a leading `+` are allowed
+    #[error("phone number contains the invalid character {0:?}")]
+    InvalidCharacter(char),
+    /// A number in its local form was given without its country
+    #[error("phone number {0:?} is in its local form, its country is needed")]
+    MissingCountry(String),
+    /// The country or calling code isn't one `PesaPal` serves
+    #[error("country {0} isn't served by PesaPal")]
+    UnsupportedCountry(String),
+    /// The number is too short or too long for its country
+    #[error("{country} phone numbers have {expected} digits after the trunk 0, found {found}")]
+    InvalidLength {
+        country: CountryCode,
+        expected: usize,
+        found: usize,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_phone_numbers() {
+        let expected = PhoneNumber::parse_local("0712345678", CountryCode::KE).unwrap();
+        for input in [
+            "+254712345678",
+            "+254 712-345-678",
+            "254712345678",
+            "00254712345678",
+            "+254 (0) 712 345 678",
+        ] {
+            assert_eq!(PhoneNumber::parse(input), Ok(expected.clone()), "{input}");
+        }
+        assert_eq!(
+            PhoneNumber::parse_local("712345678", CountryCode::KE),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            PhoneNumber::parse_local("+256712345678", CountryCode::KE)
+                .unwrap()
+                .country_code(),
+            CountryCode::UG
+        );
+        assert_eq!(serde_json::to_string(&expected).unwrap(), r#""0712345678""#);
+        assert_eq!(expected.to_string(), "+254712345678");
+        assert_eq!(
+            PhoneNumber::parse_local("0754 123 456", CountryCode::TZ)
+                .unwrap()
+                .e164(),
+            "+255754123456"
+        );
+    }
+
+    #[test]
+    fn test_invalid_phone_numbers() {
+        assert_eq!(PhoneNumber::parse(" - "), Err(PhoneNumberError::Empty));
+        assert_eq!(
+            PhoneNumber::parse("+254 71234567x"),
+            Err(PhoneNumberError::InvalidCharacter('x'))
+        );
+        assert!(matches!(
+            PhoneNumber::parse("0712345678"),
+            Err(PhoneNumberError::MissingCountry(_))
+        ));
+        assert_eq!(
+            PhoneNumber::parse("+447911123456"),
+            Err(PhoneNumberError::UnsupportedCountry("+447".to_string()))
+        );
+        assert_eq!(
+            PhoneNumber::parse_local("0712345678", CountryCode::US),
+            Err(PhoneNumberError::UnsupportedCountry("US".to_string()))
+        );
+        assert!(PhoneNumber::parse_local("+254712345678", CountryCode::US).is_ok());
+        assert_eq!(
+            PhoneNumber::parse("+25471234567"),
+            Err(PhoneNumberError::InvalidLength {
+                country: CountryCode::KE,
+                expected: 9,
+                found: 8
+            })
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Client side rate limiting
+//!
+//! `PesaPal` throttles merchants which send bursts of requests, e.g. during
+//! flash sales. A [`RateLimit`] can be set per [`Endpoint`](crate::Endpoint)
+//! with [`PesaPal::with_rate_limit`](crate::PesaPal::with_rate_limit), the
+//! requests which exceed it wait for their turn instead of failing.
+//!
+//! Each endpoint gets its own token bucket. Callers are served in the order
+//! they reached the limiter, a caller is never overtaken by a later one.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token bucket configuration for an endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Requests allowed per `interval`
+    requests: u32,
+    /// Interval in which `requests` are allowed
+    interval: Duration,
+    /// Requests which can be sent at once after a quiet period
+    burst: u32,
+}
+
+impl RateLimit {
+    /// Allows `requests` per `interval`, with a burst of `requests`
+    ///
+    /// # Panics
+    /// Panics if `requests` or `interval` is zero
+    #[must_use]
+    pub fn new(requests: u32, interval: Duration) -> Self {
+        assert!(requests > 0, "rate limit requests must be greater than 0");
+        assert!(!interval.is_zero(), "rate limit interval must not be zero");
+
+        Self {
+            requests,
+            interval,
+            burst: requests,
+        }
+    }
+
+    /// Allows `requests` per second
+    ///
+    /// # Panics
+    /// Panics if `requests` is zero
+    #[must_use]
+    pub fn per_second(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(1))
+    }
+
+    /// Allows `requests` per minute
+    ///
+    /// # Panics
+    /// Panics if `requests` is zero
+    #[must_use]
+    pub fn per_minute(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(60))
+    }
+
+    /// Sets the number of requests which can be sent at once
+    ///
+    /// # Panics
+    /// Panics if `burst` is zero
+    #[must_use]
+    pub fn burst(mut self, burst: u32) -> Self {
+        assert!(burst > 0, "rate limit burst must be greater than 0");
+        self.burst = burst;
+        self
+    }
+
+    /// Tokens added to the bucket per second
+    fn rate(&self) -> f64 {
+        f64::from(self.requests) / self.interval.as_secs_f64()
+    }
+}
+
+/// Token bucket shared by all the clones of a client
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    /// Available tokens, negative when callers are queued
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(BucketState {
+                tokens: f64::from(limit.burst),
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token and returns how long the caller has to wait for it
+    ///
+    /// The bucket goes into debt when it is empty, so every caller reserves
+    /// the next free slot and the callers are served in order.
+    fn reserve(&self, now: Instant) -> Duration {
+        let rate = self.limit.rate();
+        let mut state = self.state.lock().expect("rate limiter lock poisoned");
+
+        let refill = now.saturating_duration_since(state.updated).as_secs_f64() * rate;
+        state.tokens = (state.tokens + refill).min(f64::from(self.limit.burst));
+        state.updated = now;
+        state.tokens -= 1.0;
+
+        if state.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-state.tokens / rate)
+    }
+
+    /// Waits until a request can be sent, returns the time waited
+    pub(crate) async fn acquire(&self) -> Duration {
+        let wait = self.reserve(Instant::now());
+        if !wait.is_zero() {
+            futures_timer::Delay::new(wait).await;
+        }
+        wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_allows_burst_then_queues_in_order() {
+        let bucket = TokenBucket::new(RateLimit::per_second(10).burst(2));
+        let now = Instant::now();
+
+        assert_eq!(bucket.reserve(now), Duration::ZERO);
+        assert_eq!(bucket.reserve(now), Duration::ZERO);
+        assert_eq!(bucket.reserve(now), Duration::from_millis(100));
+        assert_eq!(bucket.reserve(now), Duration::from_millis(200));
+
+        // The queued callers keep their slots once time moves on
+        let later = now + Duration::from_millis(150);
+        assert_eq!(bucket.reserve(later), Duration::from_millis(150));
+    }
+
+    #[test]
+    fn test_bucket_refills_up_to_burst() {
+        let bucket = TokenBucket::new(RateLimit::per_minute(60));
+        let now = Instant::now();
+        for _ in 0..60 {
+            assert_eq!(bucket.reserve(now), Duration::ZERO);
+        }
+
+        let later = now + Duration::from_secs(3600);
+        for _ in 0..60 {
+            assert_eq!(bucket.reserve(later), Duration::ZERO);
+        }
+        assert_eq!(bucket.reserve(later), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_acquire_waits() {
+        let bucket = TokenBucket::new(RateLimit::new(1, Duration::from_millis(50)));
+
+        assert_eq!(bucket.acquire().await, Duration::ZERO);
+        let started = Instant::now();
+        let waited = bucket.acquire().await;
+
+        assert!(waited > Duration::ZERO);
+        assert!(started.elapsed() >= waited);
+    }
+}
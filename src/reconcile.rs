@@ -0,0 +1,510 @@
+//! Reconciliation of a local order ledger against `PesaPal`
+//!
+//! Orders kept in a local table drift from `PesaPal` when IPNs are missed.
+//! [`PesaPal::reconcile`](crate::PesaPal::reconcile) checks the status of
+//! each [`LocalRecord`] with `PesaPal` and produces a
+//! [`ReconciliationReport`] listing the orders which match and the ones which
+//! don't, which can be exported to CSV or JSON.
+//!
+//! ```rust,ignore
+//! use pesapal::reconcile::LocalRecord;
+//! use pesapal::StatusCode;
+//!
+//! let records = orders.iter().map(|order| LocalRecord {
+//!     merchant_reference: order.id.to_string(),
+//!     order_tracking_id: order.tracking_id.clone(),
+//!     amount: order.amount,
+//!     currency: "KES".to_string(),
+//!     status: StatusCode::Completed,
+//! });
+//!
+//! let report = pesapal.reconcile(records).concurrency(16).run().await;
+//! println!("{:?}", report.summary);
+//! report.write_csv(std::fs::File::create("reconciliation.csv")?)?;
+//! ```
+
+use std::collections::HashMap;
+
+use serde::{Serialize, Serializer};
+
+use crate::{PesaPal, PesaPalError, PesaPalResult, StatusCode, TransactionStatusResponse};
+
+/// Error code taken as `PesaPal` not knowing the order tracking id
+///
+/// `PesaPal` doesn't document the error codes of the transaction status
+/// endpoint, this one is an unverified heuristic. Any other error is reported
+/// as [`Outcome::LookupFailed`] rather than [`Outcome::MissingAtPesapal`], so
+/// an unrecognized code is never mistaken for a missing order.
+const UNKNOWN_TRACKING_ID: &str = "invalid_tracking_id";
+
+/// Order as recorded in the local ledger
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LocalRecord {
+    /// Unique id of the order, sent as the `id` of the submit order request
+    pub merchant_reference: String,
+    /// Order tracking id returned by `PesaPal` when the order was submitted
+    pub order_tracking_id: String,
+    /// Amount the customer is expected to have paid
+    pub amount: u64,
+    /// Currency the customer is expected to have paid in
+    pub currency: String,
+    /// Status of the order in the local ledger
+    #[serde(serialize_with = "serialize_status")]
+    pub status: StatusCode,
+}
+
+/// Outcome of the reconciliation of a [`LocalRecord`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    /// The order matches `PesaPal`
+    Matched,
+    /// The order tracking id belongs to another order at `PesaPal`
+    MerchantReferenceMismatch {
+        /// Merchant reference in the local ledger
+        expected: String,
+        /// Merchant reference recorded by `PesaPal`
+        actual: String,
+    },
+    /// `PesaPal` recorded a different amount
+    AmountMismatch {
+        /// Amount in the local ledger
+        expected: u64,
+        /// Amount recorded by `PesaPal`
+        actual: u64,
+    },
+    /// `PesaPal` recorded a different currency
+    CurrencyMismatch {
+        /// Currency in the local ledger
+        expected: String,
+        /// Currency recorded by `PesaPal`
+        actual: String,
+    },
+    /// `PesaPal` has the order in a different status
+    StatusMismatch {
+        /// Status in the local ledger
+        #[serde(serialize_with = "serialize_status")]
+        expected: StatusCode,
+        /// Status reported by `PesaPal`
+        #[serde(serialize_with = "serialize_status")]
+        actual: StatusCode,
+    },
+    /// `PesaPal` answered with the error code of an unknown order tracking id
+    MissingAtPesapal,
+    /// The status couldn't be checked, e.g. because of a network error or an
+    /// error of `PesaPal` other than an unknown order tracking id
+    LookupFailed {
+        /// Description of the error
+        error: String,
+    },
+}
+
+impl Outcome {
+    /// Name of the outcome as used in the exports
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Matched => "matched",
+            Self::MerchantReferenceMismatch { .. } => "merchant_reference_mismatch",
+            Self::AmountMismatch { .. } => "amount_mismatch",
+            Self::CurrencyMismatch { .. } => "currency_mismatch",
+            Self::StatusMismatch { .. } => "status_mismatch",
+            Self::MissingAtPesapal => "missing_at_pesapal",
+            Self::LookupFailed { .. } => "lookup_failed",
+        }
+    }
+
+    /// Compares the record with the status reported by `PesaPal`
+    fn compare(
+        record: &LocalRecord,
+        status: &PesaPalResult<TransactionStatusResponse>,
+    ) -> Vec<Self> {
+        let status = match status {
+            Ok(status) => status,
+            Err(PesaPalError::TransactionStatusError(error))
+                if error.code.eq_ignore_ascii_case(UNKNOWN_TRACKING_ID) =>
+            {
+                return vec![Self::MissingAtPesapal]
+            }
+            Err(error) => {
+                return vec![Self::LookupFailed {
+                    error: error.to_string(),
+                }]
+            }
+        };
+
+        let mut outcomes = Vec::new();
+        if record.merchant_reference != status.merchant_reference {
+            outcomes.push(Self::MerchantReferenceMismatch {
+                expected: record.merchant_reference.clone(),
+                actual: status.merchant_reference.clone(),
+            });
+        }
+        if record.amount != status.amount {
+            outcomes.push(Self::AmountMismatch {
+                expected: record.amount,
+                actual: status.amount,
+            });
+        }
+        if !record.currency.eq_ignore_ascii_case(&status.currency) {
+            outcomes.push(Self::CurrencyMismatch {
+                expected: record.currency.clone(),
+                actual: status.currency.clone(),
+            });
+        }
+        if record.status != status.status_code {
+            outcomes.push(Self::StatusMismatch {
+                expected: record.status,
+                actual: status.status_code,
+            });
+        }
+        if outcomes.is_empty() {
+            outcomes.push(Self::Matched);
+        }
+
+        outcomes
+    }
+}
+
+/// Outcome of the reconciliation of a single record
+///
+/// A record which differs from `PesaPal` in more than one way has an entry
+/// per difference.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReconciliationEntry {
+    /// Order in the local ledger
+    pub record: LocalRecord,
+    /// How the order compares to `PesaPal`
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+/// Number of records per outcome
+///
+/// A record which differs from `PesaPal` in more than one way is counted once
+/// per difference, so the counts can add up to more than `records`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReconciliationSummary {
+    /// Records reconciled
+    pub records: usize,
+    /// Records which match `PesaPal`
+    pub matched: usize,
+    /// Records whose order tracking id belongs to another order at `PesaPal`
+    pub merchant_reference_mismatches: usize,
+    /// Records with a different amount at `PesaPal`
+    pub amount_mismatches: usize,
+    /// Records with a different currency at `PesaPal`
+    pub currency_mismatches: usize,
+    /// Records with a different status at `PesaPal`
+    pub status_mismatches: usize,
+    /// Records unknown to `PesaPal`
+    pub missing_at_pesapal: usize,
+    /// Records which couldn't be checked
+    pub lookup_failures: usize,
+}
+
+/// Result of a reconciliation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReconciliationReport {
+    /// Number of records per outcome
+    pub summary: ReconciliationSummary,
+    /// Outcome of each record, in the order of the records
+    pub entries: Vec<ReconciliationEntry>,
+}
+
+impl ReconciliationReport {
+    fn new(entries: Vec<ReconciliationEntry>, records: usize) -> Self {
+        let mut summary = ReconciliationSummary {
+            records,
+            ..ReconciliationSummary::default()
+        };
+        for entry in &entries {
+            let count = match entry.outcome {
+                Outcome::Matched => &mut summary.matched,
+                Outcome::MerchantReferenceMismatch { .. } => {
+                    &mut summary.merchant_reference_mismatches
+                }
+                Outcome::AmountMismatch { .. } => &mut summary.amount_mismatches,
+                Outcome::CurrencyMismatch { .. } => &mut summary.currency_mismatches,
+                Outcome::StatusMismatch { .. } => &mut summary.status_mismatches,
+                Outcome::MissingAtPesapal => &mut summary.missing_at_pesapal,
+                Outcome::LookupFailed { .. } => &mut summary.lookup_failures,
+            };
+            *count += 1;
+        }
+
+        Self { summary, entries }
+    }
+
+    /// Entries which need attention, i.e. everything but the matched ones
+    pub fn discrepancies(&self) -> impl Iterator<Item = &ReconciliationEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.outcome != Outcome::Matched)
+    }
+
+    /// Exports the report as JSON
+    ///
+    /// # Errors
+    /// [`PesaPalError::Internal`] - Incase the report can't be serialized
+    pub fn to_json(&self) -> PesaPalResult<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Writes the entries as CSV, with a header row
+    ///
+    /// The columns are `merchant_reference`, `order_tracking_id`, `outcome`,
+    /// `expected` and `actual`. For failed lookups the error is in `actual`.
+    ///
+    /// # Errors
+    /// [`PesaPalError::Internal`] - Incase writing to the `writer` fails
+    pub fn write_csv<W: std::io::Write>(&self, writer: W) -> PesaPalResult<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for entry in &self.entries {
+            let (expected, actual) = match &entry.outcome {
+                Outcome::Matched | Outcome::MissingAtPesapal => (String::new(), String::new()),
+                Outcome::AmountMismatch { expected, actual } => {
+                    (expected.to_string(), actual.to_string())
+                }
+                Outcome::MerchantReferenceMismatch { expected, actual }
+                | Outcome::CurrencyMismatch { expected, actual } => {
+                    (expected.clone(), actual.clone())
+                }
+                Outcome::StatusMismatch { expected, actual } => {
+                    (expected.to_string(), actual.to_string())
+                }
+                Outcome::LookupFailed { error } => (String::new(), error.clone()),
+            };
+
+            writer
+                .serialize(CsvRow {
+                    merchant_reference: &entry.record.merchant_reference,
+                    order_tracking_id: &entry.record.order_tracking_id,
+                    outcome: entry.outcome.as_str(),
+                    expected,
+                    actual,
+                })
+                .map_err(|e| PesaPalError::Internal(e.to_string()))?;
+        }
+
+        writer
+            .flush()
+            .map_err(|e| PesaPalError::Internal(e.to_string()))
+    }
+
+    /// Exports the entries as CSV
+    ///
+    /// # Errors
+    /// [`PesaPalError::Internal`] - Incase the report can't be serialized
+    pub fn to_csv(&self) -> PesaPalResult<String> {
+        let mut buffer = Vec::new();
+        self.write_csv(&mut buffer)?;
+        String::from_utf8(buffer).map_err(|e| PesaPalError::Internal(e.to_string()))
+    }
+}
+
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    merchant_reference: &'a str,
+    order_tracking_id: &'a str,
+    outcome: &'static str,
+    expected: String,
+    actual: String,
+}
+
+fn serialize_status<S: Serializer>(status: &StatusCode, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(status.as_str())
+}
+
+/// Reconciliation of local records, created with
+/// [`PesaPal::reconcile`](crate::PesaPal::reconcile)
+#[derive(Debug)]
+pub struct Reconciliation {
+    client: PesaPal,
+    records: Vec<LocalRecord>,
+    concurrency: Option<usize>,
+}
+
+impl Reconciliation {
+    pub(crate) fn new<I>(client: PesaPal, records: I) -> Self
+    where
+        I: IntoIterator<Item = LocalRecord>,
+    {
+        Self {
+            client,
+            records: records.into_iter().collect(),
+            concurrency: None,
+        }
+    }
+
+    /// Sets the maximum number of status lookups in flight at once
+    ///
+    /// Defaults to the one of [`TransactionStatuses`](crate::TransactionStatuses),
+    /// `0` is raised to `1`.
+    #[must_use]
+    pub const fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = Some(if concurrency == 0 { 1 } else { concurrency });
+        self
+    }
+
+    /// Checks the status of every record with `PesaPal` and compares them
+    ///
+    /// A failed lookup is reported as [`Outcome::LookupFailed`] and doesn't
+    /// stop the reconciliation of the other records.
+    pub async fn run(self) -> ReconciliationReport {
+        let ids = self
+            .records
+            .iter()
+            .map(|record| record.order_tracking_id.clone());
+        let mut lookup = self.client.transaction_statuses(ids);
+        if let Some(concurrency) = self.concurrency {
+            lookup = lookup.concurrency(concurrency);
+        }
+        let statuses: HashMap<_, _> = lookup.collect().await;
+
+        let records = self.records.len();
+        let entries = self
+            .records
+            .into_iter()
+            .flat_map(|record| {
+                let outcomes = statuses
+                    .get(&record.order_tracking_id)
+                    .map_or_else(Vec::new, |status| Outcome::compare(&record, status));
+                outcomes
+                    .into_iter()
+                    .map(move |outcome| ReconciliationEntry {
+                        record: record.clone(),
+                        outcome,
+                    })
+            })
+            .collect();
+
+        ReconciliationReport::new(entries, records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::transport::mock::MockTransport;
+    use crate::Environment;
+
+    fn status(id: &str, amount: u64, currency: &str, status_code: u8) -> String {
+        format!(
+            r#"{{"paymentMethod":"Visa","amount":{amount},"createdDate":"2022-04-30T07:41:09.763","confirmationCode":"6513008693186320103009","paymentStatusDescription":{status_code},"description":"","message":"","paymentAccount":"476173**0010","callBackUrl":"https://example.com","statusCode":{status_code},"merchantReference":"ref-{id}","currency":"{currency}","error":{{"error_type":"","code":"","message":"","call_back_url":""}},"status":"200"}}"#
+        )
+    }
+
+    fn error(code: &str, message: &str) -> String {
+        format!(
+            r#"{{"paymentMethod":"","amount":0,"createdDate":"","confirmationCode":"","paymentStatusDescription":0,"description":"","message":"","paymentAccount":"","callBackUrl":"","statusCode":0,"merchantReference":"","currency":"","error":{{"error_type":"api_error","code":"{code}","message":"{message}","call_back_url":""}},"status":"500"}}"#
+        )
+    }
+
+    fn record(id: &str, amount: u64, currency: &str, status: StatusCode) -> LocalRecord {
+        LocalRecord {
+            merchant_reference: format!("ref-{id}"),
+            order_tracking_id: id.to_string(),
+            amount,
+            currency: currency.to_string(),
+            status,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_report() {
+        let transport = Arc::new(
+            MockTransport::new()
+                .on("GetTransactionStatus", 200, &status("a", 100, "KES", 1))
+                .on("GetTransactionStatus", 200, &status("b", 150, "USD", 1))
+                .on("GetTransactionStatus", 200, &status("c", 100, "KES", 2))
+                .on(
+                    "GetTransactionStatus",
+                    200,
+                    &error("invalid_tracking_id", "Invalid tracking id"),
+                )
+                .on("GetTransactionStatus", 200, &status("x", 100, "KES", 1))
+                .on(
+                    "GetTransactionStatus",
+                    500,
+                    &error("internal_error", "An error occurred"),
+                ),
+        );
+        let client =
+            PesaPal::new_with_transport("reconcile-key", "secret", Environment::Sandbox, transport);
+
+        let report = client
+            .reconcile([
+                record("a", 100, "kes", StatusCode::Completed),
+                record("b", 100, "KES", StatusCode::Completed),
+                record("c", 100, "KES", StatusCode::Completed),
+                record("d", 100, "KES", StatusCode::Completed),
+                record("e", 100, "KES", StatusCode::Completed),
+                record("f", 100, "KES", StatusCode::Completed),
+            ])
+            .concurrency(1)
+            .run()
+            .await;
+
+        assert_eq!(
+            report.summary,
+            ReconciliationSummary {
+                records: 6,
+                matched: 1,
+                merchant_reference_mismatches: 1,
+                amount_mismatches: 1,
+                currency_mismatches: 1,
+                status_mismatches: 1,
+                missing_at_pesapal: 1,
+                lookup_failures: 1,
+            }
+        );
+        assert_eq!(report.discrepancies().count(), 6);
+        assert_eq!(
+            report.entries[1].outcome,
+            Outcome::AmountMismatch {
+                expected: 100,
+                actual: 150
+            }
+        );
+
+        let csv = report.to_csv().unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("merchant_reference,order_tracking_id,outcome,expected,actual")
+        );
+        assert_eq!(lines.next(), Some("ref-a,a,matched,,"));
+        assert_eq!(lines.next(), Some("ref-b,b,amount_mismatch,100,150"));
+        assert!(csv.contains("ref-c,c,status_mismatch,COMPLETED,FAILED"));
+        assert!(csv.contains("ref-d,d,missing_at_pesapal,,"));
+        assert!(csv.contains("ref-e,e,merchant_reference_mismatch,ref-e,ref-x"));
+        assert!(csv.contains("ref-f,f,lookup_failed,,"));
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["summary"]["matched"], 1);
+        assert_eq!(json["entries"][2]["outcome"], "currency_mismatch");
+        assert_eq!(json["entries"][2]["expected"], "KES");
+        assert_eq!(json["entries"][0]["record"]["status"], "COMPLETED");
+    }
+
+    #[tokio::test]
+    async fn test_zero_concurrency_is_raised_to_one() {
+        let client = PesaPal::new_with_transport(
+            "reconcile-concurrency-key",
+            "secret",
+            Environment::Sandbox,
+            MockTransport::new().on("GetTransactionStatus", 200, &status("a", 100, "KES", 1)),
+        );
+
+        let report = client
+            .reconcile([record("a", 100, "KES", StatusCode::Completed)])
+            .concurrency(0)
+            .run()
+            .await;
+
+        assert_eq!(report.summary.matched, 1);
+    }
+}
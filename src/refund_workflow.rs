@@ -0,0 +1,1073 @@
+//! Maker-checker approval of refunds
+//!
+//! A [`RefundProposal`] is made by one user and approved or rejected by
+//! another one, the refund is only sent to `PesaPal` once the
+//! [`ApprovalPolicy`] is satisfied. Every step is recorded as an
+//! [`AuditEvent`] in a [`RefundStore`].
+//!
+//! ```rust,ignore
+//! use pesapal::refund_workflow::{
+//!     Actor, InMemoryRefundStore, ProposalStatus, RefundDetails, RefundWorkflow, ThresholdPolicy,
+//! };
+//!
+//! let workflow = RefundWorkflow::new(
+//!     pesapal,
+//!     // Refunds above 10 000 need the approval of someone in finance
+//!     ThresholdPolicy::new(10_000.0).approver_role("finance"),
+//!     InMemoryRefundStore::new(),
+//! );
+//!
+//! let proposal = workflow
+//!     .propose(
+//!         &Actor::new("alice"),
+//!         RefundDetails::new("AA22BB33CC", 25_000.0, "Service not offered")
+//!             .preflight(order_tracking_id),
+//!     )
+//!     .await?;
+//!
+//! let proposal = workflow
+//!     .approve(&proposal.id, &Actor::new("bob").role("finance"), None)
+//!     .await?;
+//! assert_eq!(proposal.status, ProposalStatus::Sent);
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{PesaPal, PesaPalError, PesaPalResult, RefundOutcome, RefundPreflight};
+
+/// User taking part in a refund
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Actor {
+    /// Unique name of the user, sent as the `username` of the refund when
+    /// they propose it
+    pub username: String,
+    /// Roles of the user, checked by the [`ApprovalPolicy`]
+    pub roles: Vec<String>,
+}
+
+impl Actor {
+    /// Creates a user without any role
+    pub fn new(username: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            roles: Vec::new(),
+        }
+    }
+
+    /// Adds a role to the user
+    #[must_use]
+    pub fn role(mut self, role: impl Into<String>) -> Self {
+        self.roles.push(role.into());
+        self
+    }
+
+    /// Whether the user has the role
+    #[must_use]
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+}
+
+/// Refund to be proposed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RefundDetails {
+    /// Payment confirmation code that was returned by the payment processor
+    pub confirmation_code: String,
+    /// Amount to be refunded
+    pub amount: f64,
+    /// A brief description on the reason for the refund
+    pub remarks: String,
+    /// Order tracking id of the payment, to run a [`RefundPreflight`] before
+    /// the refund is sent
+    pub order_tracking_id: Option<String>,
+}
+
+impl RefundDetails {
+    /// Creates the details of a refund, the amount must be a positive number
+    /// or [`RefundWorkflow::propose`] rejects it
+    pub fn new(
+        confirmation_code: impl Into<String>,
+        amount: f64,
+        remarks: impl Into<String>,
+    ) -> Self {
+        Self {
+            confirmation_code: confirmation_code.into(),
+            amount,
+            remarks: remarks.into(),
+            order_tracking_id: None,
+        }
+    }
+
+    /// Checks the refund against the payment with the order tracking id
+    /// before it is sent, see [`RefundPreflight`]
+    #[must_use]
+    pub fn preflight(mut self, order_tracking_id: impl Into<String>) -> Self {
+        self.order_tracking_id = Some(order_tracking_id.into());
+        self
+    }
+}
+
+/// Status of a [`RefundProposal`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatus {
+    /// Waiting for approvals
+    Pending,
+    /// Rejected by an approver, never sent
+    Rejected,
+    /// Approved, the refund is being sent to `PesaPal`
+    Sending,
+    /// Approved and accepted by `PesaPal`
+    Sent,
+    /// Approved but the refund request failed
+    Failed,
+}
+
+/// Approval or rejection of a [`RefundProposal`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decision {
+    /// User who decided
+    pub actor: Actor,
+    /// Whether the proposal was approved
+    pub approved: bool,
+    /// Note left by the user
+    pub note: Option<String>,
+    /// Time of the decision
+    pub at: SystemTime,
+}
+
+/// Refund waiting for, or done after, the approval of other users
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RefundProposal {
+    /// Unique id of the proposal
+    pub id: String,
+    /// Refund to be sent
+    pub details: RefundDetails,
+    /// User who proposed the refund
+    pub proposed_by: Actor,
+    /// Time the refund was proposed
+    pub proposed_at: SystemTime,
+    /// Number of approvals needed, set by the [`ApprovalPolicy`] when the
+    /// refund is proposed
+    pub required_approvals: usize,
+    /// Approvals and rejection, in the order they were made
+    pub decisions: Vec<Decision>,
+    /// Status of the proposal
+    pub status: ProposalStatus,
+    /// Outcome returned by `PesaPal` once the refund is sent
+    pub outcome: Option<RefundOutcome>,
+    /// Error of the refund request, if it failed
+    pub failure: Option<String>,
+    /// Error of the store if the result of the refund couldn't be saved, the
+    /// stored proposal is then left [`ProposalStatus::Sending`] until it is
+    /// settled with [`RefundWorkflow::resolve`]
+    #[serde(skip)]
+    pub unrecorded: Option<String>,
+}
+
+impl RefundProposal {
+    /// Number of approvals received
+    #[must_use]
+    pub fn approvals(&self) -> usize {
+        self.decisions.iter().filter(|d| d.approved).count()
+    }
+}
+
+/// Decides who may approve a refund and how many approvals it needs
+///
+/// The proposer can never approve their own refund, nor can a user approve
+/// it twice, whatever the policy.
+pub trait ApprovalPolicy: fmt::Debug + Send + Sync {
+    /// Number of approvals the refund needs before it is sent, zero sends it
+    /// as soon as it is proposed
+    fn required_approvals(&self, proposal: &RefundProposal) -> usize;
+
+    /// Whether the user may approve or reject the refund
+    fn may_decide(&self, proposal: &RefundProposal, approver: &Actor) -> bool;
+}
+
+/// Refunds above an amount need approvals, from users with a role if set
+///
+/// An amount which can't be compared with the threshold, e.g. NaN, needs the
+/// approvals too.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThresholdPolicy {
+    threshold: f64,
+    approvals: usize,
+    approver_role: Option<String>,
+}
+
+impl ThresholdPolicy {
+    /// Refunds above `threshold` need one approval, the other ones are sent
+    /// right away
+    #[must_use]
+    pub const fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            approvals: 1,
+            approver_role: None,
+        }
+    }
+
+    /// Number of approvals needed above the threshold
+    ///
+    /// # Panics
+    /// Panics if `approvals` is zero
+    #[must_use]
+    pub fn approvals(mut self, approvals: usize) -> Self {
+        assert!(approvals > 0, "approvals must be greater than 0");
+        self.approvals = approvals;
+        self
+    }
+
+    /// Role the approvers must have
+    #[must_use]
+    pub fn approver_role(mut self, role: impl Into<String>) -> Self {
+        self.approver_role = Some(role.into());
+        self
+    }
+}
+
+impl ApprovalPolicy for ThresholdPolicy {
+    fn required_approvals(&self, proposal: &RefundProposal) -> usize {
+        match proposal.details.amount.partial_cmp(&self.threshold) {
+            Some(Ordering::Less | Ordering::Equal) => 0,
+            Some(Ordering::Greater) | None => self.approvals,
+        }
+    }
+
+    fn may_decide(&self, _proposal: &RefundProposal, approver: &Actor) -> bool {
+        self.approver_role
+            .as_deref()
+            .is_none_or(|role| approver.has_role(role))
+    }
+}
+
+/// Step of a refund recorded in the audit trail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// The refund was proposed
+    Proposed,
+    /// The refund was approved
+    Approved,
+    /// The refund was rejected
+    Rejected,
+    /// The refund was accepted by `PesaPal`
+    Sent,
+    /// The refund request failed
+    SendFailed,
+    /// The result of a refund left sending was set by hand
+    Resolved,
+}
+
+/// Entry of the audit trail of a [`RefundProposal`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// Id of the proposal
+    pub proposal_id: String,
+    /// What was done
+    pub action: AuditAction,
+    /// Username of who did it
+    pub actor: String,
+    /// When it was done
+    pub at: SystemTime,
+    /// Note of the approver, or the error of a failed refund
+    pub details: Option<String>,
+}
+
+/// Storage of the proposals and their audit trail
+///
+/// Implement it to keep them in a database. Return storage failures as
+/// [`RefundWorkflowError::Storage`].
+#[async_trait::async_trait]
+pub trait RefundStore: fmt::Debug + Send + Sync {
+    /// Inserts or replaces the proposal
+    async fn save(&self, proposal: &RefundProposal) -> PesaPalResult<()>;
+
+    /// Proposal with the id, if any
+    async fn load(&self, id: &str) -> PesaPalResult<Option<RefundProposal>>;
+
+    /// Appends the event to the audit trail
+    async fn record(&self, event: &AuditEvent) -> PesaPalResult<()>;
+
+    /// Audit trail of the proposal, in the order it was recorded
+    async fn audit_trail(&self, id: &str) -> PesaPalResult<Vec<AuditEvent>>;
+}
+
+/// [`RefundStore`] keeping everything in memory, for tests and single
+/// process deployments which don't need to keep the audit trail
+#[derive(Debug, Default)]
+pub struct InMemoryRefundStore {
+    proposals: Mutex<HashMap<String, RefundProposal>>,
+    events: Mutex<Vec<AuditEvent>>,
+}
+
+impl InMemoryRefundStore {
+    /// Creates an empty store
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl RefundStore for InMemoryRefundStore {
+    async fn save(&self, proposal: &RefundProposal) -> PesaPalResult<()> {
+        self.proposals
+            .lock()
+            .expect("refund store lock poisoned")
+            .insert(proposal.id.clone(), proposal.clone());
+        Ok(())
+    }
+
+    async fn load(&self, id: &str) -> PesaPalResult<Option<RefundProposal>> {
+        Ok(self
+            .proposals
+            .lock()
+            .expect("refund store lock poisoned")
+            .get(id)
+            .cloned())
+    }
+
+    async fn record(&self, event: &AuditEvent) -> PesaPalResult<()> {
+        self.events
+            .lock()
+            .expect("refund store lock poisoned")
+            .push(event.clone());
+        Ok(())
+    }
+
+    async fn audit_trail(&self, id: &str) -> PesaPalResult<Vec<AuditEvent>> {
+        Ok(self
+            .events
+            .lock()
+            .expect("refund store lock poisoned")
+            .iter()
+            .filter(|event| event.proposal_id == id)
+            .cloned()
+            .collect())
+    }
+}
+
+/// Why a step of the refund workflow was refused
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[non_exhaustive]
+pub enum RefundWorkflowError {
+    /// The refund amount is zero, negative or not a finite number
+    #[error("refund amount {0} must be a finite number greater than 0")]
+    InvalidAmount(f64),
+    /// No proposal with the id
+    #[error("unknown refund proposal {0}")]
+    UnknownProposal(String),
+    /// The proposal was already decided on
+    #[error("refund proposal is {status:?}, not pending")]
+    NotPending { status: ProposalStatus },
+    /// The proposal isn't being sent, there is nothing to resolve
+    #[error("refund proposal is {status:?}, not sending")]
+    NotSending { status: ProposalStatus },
+    /// A proposal can only be resolved as sent or failed
+    #[error("refund proposal can't be resolved as {0:?}")]
+    InvalidResolution(ProposalStatus),
+    /// The proposer tried to approve or reject their own refund
+    #[error("{0} can't decide on a refund they proposed")]
+    SelfApproval(String),
+    /// The user already approved the refund
+    #[error("{0} already approved the refund")]
+    AlreadyApproved(String),
+    /// The policy doesn't let the user decide on the refund
+    #[error("{0} isn't allowed to decide on the refund")]
+    NotAllowed(String),
+    /// The store failed
+    #[error("refund store failed : {0}")]
+    Storage(String),
+}
+
+impl From<RefundWorkflowError> for PesaPalError {
+    fn from(value: RefundWorkflowError) -> Self {
+        Self::RefundWorkflowError(value)
+    }
+}
+
+/// Proposes, approves and sends refunds, see the [module documentation](self)
+///
+/// The decisions made through a workflow are serialized, share the workflow
+/// (it is cheap to clone) rather than creating one per request. Several
+/// processes sharing a store need the store to serialize the updates.
+///
+/// An approved proposal is saved as [`ProposalStatus::Sending`] before the
+/// refund is sent, so the decisions on other proposals don't wait for
+/// `PesaPal`. It stays so if its result can't be saved, settle it with
+/// [`RefundWorkflow::resolve`].
+#[derive(Debug, Clone)]
+pub struct RefundWorkflow {
+    client: PesaPal,
+    policy: Arc<dyn ApprovalPolicy>,
+    store: Arc<dyn RefundStore>,
+    decisions: Arc<futures::lock::Mutex<()>>,
+}
+
+impl RefundWorkflow {
+    /// Creates a workflow sending the approved refunds with the client
+    pub fn new(
+        client: PesaPal,
+        policy: impl ApprovalPolicy + 'static,
+        store: impl RefundStore + 'static,
+    ) -> Self {
+        Self {
+            client,
+            policy: Arc::new(policy),
+            store: Arc::new(store),
+            decisions: Arc::new(futures::lock::Mutex::new(())),
+        }
+    }
+
+    /// Proposes a refund, it is sent right away if the policy requires no
+    /// approval
+    ///
+    /// A refund request which fails doesn't fail the call, the returned
+    /// proposal is [`ProposalStatus::Failed`] with the error in
+    /// [`RefundProposal::failure`]. Nor does a store failing to save the
+    /// result of the refund, see [`RefundProposal::unrecorded`].
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::RefundWorkflowError`] - Incase the amount isn't a
+    /// positive finite number, or the store fails
+    pub async fn propose(
+        &self,
+        proposer: &Actor,
+        details: RefundDetails,
+    ) -> PesaPalResult<RefundProposal> {
+        if !details.amount.is_finite() || details.amount <= 0.0 {
+            return Err(RefundWorkflowError::InvalidAmount(details.amount).into());
+        }
+
+        let mut proposal = RefundProposal {
+            id: ulid::Ulid::new().to_string(),
+            details,
+            proposed_by: proposer.clone(),
+            proposed_at: SystemTime::now(),
+            required_approvals: 0,
+            decisions: Vec::new(),
+            status: ProposalStatus::Pending,
+            outcome: None,
+            failure: None,
+            unrecorded: None,
+        };
+        proposal.required_approvals = self.policy.required_approvals(&proposal);
+        if proposal.required_approvals == 0 {
+            proposal.status = ProposalStatus::Sending;
+        }
+
+        self.store.save(&proposal).await?;
+        self.audit(&proposal, AuditAction::Proposed, proposer, None)
+            .await?;
+
+        if proposal.status == ProposalStatus::Sending {
+            self.send(&mut proposal).await;
+        }
+        Ok(proposal)
+    }
+
+    /// Approves the refund, it is sent once it has all the approvals it needs
+    ///
+    /// A refund request which fails doesn't fail the call, the returned
+    /// proposal is [`ProposalStatus::Failed`] with the error in
+    /// [`RefundProposal::failure`]. Nor does a store failing to save the
+    /// result of the refund, see [`RefundProposal::unrecorded`].
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::RefundWorkflowError`] - Incase the proposal isn't
+    /// pending, the approver proposed it, already approved it or isn't
+    /// allowed by the policy, or the store fails
+    pub async fn approve(
+        &self,
+        proposal_id: &str,
+        approver: &Actor,
+        note: Option<String>,
+    ) -> PesaPalResult<RefundProposal> {
+        let decisions = self.decisions.lock().await;
+
+        let mut proposal = self.decidable(proposal_id, approver).await?;
+        if proposal
+            .decisions
+            .iter()
+            .any(|d| d.approved && d.actor.username == approver.username)
+        {
+            return Err(RefundWorkflowError::AlreadyApproved(approver.username.clone()).into());
+        }
+
+        proposal.decisions.push(Decision {
+            actor: approver.clone(),
+            approved: true,
+            note: note.clone(),
+            at: SystemTime::now(),
+        });
+        if proposal.approvals() >= proposal.required_approvals {
+            proposal.status = ProposalStatus::Sending;
+        }
+        self.store.save(&proposal).await?;
+        self.audit(&proposal, AuditAction::Approved, approver, note)
+            .await?;
+        drop(decisions);
+
+        if proposal.status == ProposalStatus::Sending {
+            self.send(&mut proposal).await;
+        }
+        Ok(proposal)
+    }
+
+    /// Rejects the refund, it is never sent
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::RefundWorkflowError`] - Incase the proposal isn't
+    /// pending, the approver proposed it or isn't allowed by the policy, or
+    /// the store fails
+    pub async fn reject(
+        &self,
+        proposal_id: &str,
+        approver: &Actor,
+        reason: Option<String>,
+    ) -> PesaPalResult<RefundProposal> {
+        let _decisions = self.decisions.lock().await;
+
+        let mut proposal = self.decidable(proposal_id, approver).await?;
+        proposal.decisions.push(Decision {
+            actor: approver.clone(),
+            approved: false,
+            note: reason.clone(),
+            at: SystemTime::now(),
+        });
+        proposal.status = ProposalStatus::Rejected;
+        self.store.save(&proposal).await?;
+        self.audit(&proposal, AuditAction::Rejected, approver, reason)
+            .await?;
+
+        Ok(proposal)
+    }
+
+    /// Proposal with the id
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::RefundWorkflowError`] - Incase the proposal doesn't
+    /// exist or the store fails
+    pub async fn proposal(&self, proposal_id: &str) -> PesaPalResult<RefundProposal> {
+        self.store
+            .load(proposal_id)
+            .await?
+            .ok_or_else(|| RefundWorkflowError::UnknownProposal(proposal_id.to_string()).into())
+    }
+
+    /// Audit trail of the proposal, oldest first
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::RefundWorkflowError`] - Incase the store fails
+    pub async fn audit_trail(&self, proposal_id: &str) -> PesaPalResult<Vec<AuditEvent>> {
+        self.store.audit_trail(proposal_id).await
+    }
+
+    /// Loads the pending proposal the approver may decide on
+    async fn decidable(
+        &self,
+        proposal_id: &str,
+        approver: &Actor,
+    ) -> PesaPalResult<RefundProposal> {
+        let proposal = self.proposal(proposal_id).await?;
+
+        if proposal.status != ProposalStatus::Pending {
+            return Err(RefundWorkflowError::NotPending {
+                status: proposal.status,
+            }
+            .into());
+        }
+        if proposal.proposed_by.username == approver.username {
+            return Err(RefundWorkflowError::SelfApproval(approver.username.clone()).into());
+        }
+        if !self.policy.may_decide(&proposal, approver) {
+            return Err(RefundWorkflowError::NotAllowed(approver.username.clone()).into());
+        }
+
+        Ok(proposal)
+    }
+
+    /// Settles a proposal left [`ProposalStatus::Sending`], e.g. because the
+    /// result of its refund couldn't be saved or the process stopped while it
+    /// was sent
+    ///
+    /// Check the refund with `PesaPal` first, e.g. with a
+    /// [`RefundTracker`](crate::RefundTracker): the refund isn't sent again,
+    /// `status` is only recorded along with the note of the resolver.
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::RefundWorkflowError`] - Incase the proposal isn't
+    /// sending, `status` is neither [`ProposalStatus::Sent`] nor
+    /// [`ProposalStatus::Failed`], or the store fails
+    pub async fn resolve(
+        &self,
+        proposal_id: &str,
+        resolver: &Actor,
+        status: ProposalStatus,
+        note: Option<String>,
+    ) -> PesaPalResult<RefundProposal> {
+        if !matches!(status, ProposalStatus::Sent | ProposalStatus::Failed) {
+            return Err(RefundWorkflowError::InvalidResolution(status).into());
+        }
+
+        let _decisions = self.decisions.lock().await;
+
+        let mut proposal = self.proposal(proposal_id).await?;
+        if proposal.status != ProposalStatus::Sending {
+            return Err(RefundWorkflowError::NotSending {
+                status: proposal.status,
+            }
+            .into());
+        }
+
+        proposal.status = status;
+        if status == ProposalStatus::Failed {
+            proposal.failure.clone_from(&note);
+        }
+        self.store.save(&proposal).await?;
+        self.audit(&proposal, AuditAction::Resolved, resolver, note)
+            .await?;
+
+        Ok(proposal)
+    }
+
+    /// Sends the refund of a [`ProposalStatus::Sending`] proposal and records
+    /// its result, without holding the decisions lock
+    ///
+    /// A store failure doesn't hide the result of the refund, it is kept in
+    /// [`RefundProposal::unrecorded`] instead.
+    async fn send(&self, proposal: &mut RefundProposal) {
+        let details = &proposal.details;
+        let mut builder = self.client.refund();
+        builder
+            .confirmation_code(details.confirmation_code.clone())
+            .amount(details.amount)
+            .username(proposal.proposed_by.username.clone())
+            .remarks(details.remarks.clone());
+        if let Some(order_tracking_id) = &details.order_tracking_id {
+            builder.preflight(RefundPreflight::new(order_tracking_id));
+        }
+        let result = match builder.build() {
+            Ok(refund) => refund.send().await,
+            Err(error) => Err(error.into()),
+        };
+
+        let (action, details) = match result {
+            Ok(response) => {
+                proposal.status = ProposalStatus::Sent;
+                proposal.outcome = Some(response.outcome());
+                (AuditAction::Sent, response.message)
+            }
+            Err(error) => {
+                proposal.status = ProposalStatus::Failed;
+                proposal.failure = Some(error.to_string());
+                (AuditAction::SendFailed, error.to_string())
+            }
+        };
+        let recorded = async {
+            self.store.save(proposal).await?;
+            self.audit(proposal, action, &Actor::new("system"), Some(details))
+                .await
+        }
+        .await;
+        if let Err(error) = recorded {
+            #[cfg(feature = "tracing")]
+            tracing::error!(
+                proposal_id = %proposal.id,
+                status = ?proposal.status,
+                %error,
+                "refund result couldn't be saved"
+            );
+            proposal.unrecorded = Some(error.to_string());
+        }
+    }
+
+    async fn audit(
+        &self,
+        proposal: &RefundProposal,
+        action: AuditAction,
+        actor: &Actor,
+        details: Option<String>,
+    ) -> PesaPalResult<()> {
+        self.store
+            .record(&AuditEvent {
+                proposal_id: proposal.id.clone(),
+                action,
+                actor: actor.username.clone(),
+                at: SystemTime::now(),
+                details,
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+    use crate::Environment;
+
+    const REFUND_REQUEST_URL: &str = "api/Transactions/RefundRequest";
+
+    fn workflow(transport: Arc<MockTransport>) -> RefundWorkflow {
+        let client = PesaPal::new_with_transport(
+            "refund-workflow-key",
+            "secret",
+            Environment::Sandbox,
+            transport,
+        );
+        RefundWorkflow::new(
+            client,
+            ThresholdPolicy::new(1_000.0).approver_role("finance"),
+            InMemoryRefundStore::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_refund_needs_a_second_approver() {
+        let transport = Arc::new(MockTransport::new().on(
+            REFUND_REQUEST_URL,
+            200,
+            r#"{"status":"200","message":"Refund request successfully"}"#,
+        ));
+        let workflow = workflow(Arc::clone(&transport));
+        let alice = Actor::new("alice").role("finance");
+        let bob = Actor::new("bob").role("finance");
+
+        let proposal = workflow
+            .propose(
+                &alice,
+                RefundDetails::new("AA22BB33CC", 2_500.0, "Service not offered"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Pending);
+        assert_eq!(proposal.required_approvals, 1);
+
+        let error = |result: PesaPalResult<RefundProposal>| match result {
+            Err(PesaPalError::RefundWorkflowError(error)) => error,
+            other => panic!("expected a workflow error, got {other:?}"),
+        };
+        assert_eq!(
+            error(workflow.approve(&proposal.id, &alice, None).await),
+            RefundWorkflowError::SelfApproval("alice".to_string())
+        );
+        assert_eq!(
+            error(
+                workflow
+                    .approve(&proposal.id, &Actor::new("carol"), None)
+                    .await
+            ),
+            RefundWorkflowError::NotAllowed("carol".to_string())
+        );
+        assert!(transport.requests(REFUND_REQUEST_URL).is_empty());
+
+        let proposal = workflow
+            .approve(&proposal.id, &bob, Some("checked the order".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Sent);
+        assert_eq!(proposal.outcome, Some(RefundOutcome::Accepted));
+
+        let request: serde_json::Value = serde_json::from_slice(
+            transport.requests(REFUND_REQUEST_URL)[0]
+                .body
+                .as_ref()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(request["username"], "alice");
+
+        let trail: Vec<_> = workflow
+            .audit_trail(&proposal.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| (event.action, event.actor))
+            .collect();
+        assert_eq!(
+            trail,
+            [
+                (AuditAction::Proposed, "alice".to_string()),
+                (AuditAction::Approved, "bob".to_string()),
+                (AuditAction::Sent, "system".to_string()),
+            ]
+        );
+        assert_eq!(
+            error(workflow.reject(&proposal.id, &bob, None).await),
+            RefundWorkflowError::NotPending {
+                status: ProposalStatus::Sent
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_small_refunds_are_sent_right_away() {
+        let transport = Arc::new(MockTransport::new().on(
+            REFUND_REQUEST_URL,
+            200,
+            r#"{"status":"500","message":"Refund rejected"}"#,
+        ));
+        let workflow = workflow(Arc::clone(&transport));
+
+        let proposal = workflow
+            .propose(
+                &Actor::new("alice"),
+                RefundDetails::new("AA22BB33CC", 500.0, "Service not offered"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Failed);
+        assert!(proposal.failure.unwrap().contains("Refund rejected"));
+        assert_eq!(transport.requests(REFUND_REQUEST_URL).len(), 1);
+
+        let actions: Vec<_> = workflow
+            .audit_trail(&proposal.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| event.action)
+            .collect();
+        assert_eq!(actions, [AuditAction::Proposed, AuditAction::SendFailed]);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_amounts_are_never_sent() {
+        let transport = Arc::new(MockTransport::new().on(
+            REFUND_REQUEST_URL,
+            200,
+            r#"{"status":"200","message":"Refund request successfully"}"#,
+        ));
+        let workflow = workflow(Arc::clone(&transport));
+
+        for amount in [0.0, -500.0, f64::NAN, f64::INFINITY] {
+            let result = workflow
+                .propose(
+                    &Actor::new("alice"),
+                    RefundDetails::new("AA22BB33CC", amount, "Service not offered"),
+                )
+                .await;
+            assert!(
+                matches!(
+                    result,
+                    Err(PesaPalError::RefundWorkflowError(
+                        RefundWorkflowError::InvalidAmount(_)
+                    ))
+                ),
+                "{amount} was accepted"
+            );
+        }
+        assert!(transport.requests(REFUND_REQUEST_URL).is_empty());
+
+        let policy = ThresholdPolicy::new(1_000.0);
+        let mut proposal = RefundProposal {
+            id: "proposal".to_string(),
+            details: RefundDetails::new("AA22BB33CC", f64::NAN, "Service not offered"),
+            proposed_by: Actor::new("alice"),
+            proposed_at: SystemTime::now(),
+            required_approvals: 0,
+            decisions: Vec::new(),
+            status: ProposalStatus::Pending,
+            outcome: None,
+            failure: None,
+            unrecorded: None,
+        };
+        assert_eq!(policy.required_approvals(&proposal), 1);
+        proposal.details.amount = 500.0;
+        assert_eq!(policy.required_approvals(&proposal), 0);
+    }
+
+    #[tokio::test]
+    async fn test_decisions_dont_wait_for_a_refund_being_sent() {
+        use futures::StreamExt;
+
+        let gate = Arc::new(futures::lock::Mutex::new(()));
+        let held = gate.lock().await;
+        let (arrived, mut in_flight) = futures::channel::mpsc::unbounded();
+        let transport = Arc::new(
+            MockTransport::new()
+                .on(
+                    REFUND_REQUEST_URL,
+                    200,
+                    r#"{"status":"200","message":"Refund request successfully"}"#,
+                )
+                .hold(REFUND_REQUEST_URL, Arc::clone(&gate), arrived),
+        );
+        let workflow = workflow(Arc::clone(&transport));
+        let alice = Actor::new("alice").role("finance");
+        let bob = Actor::new("bob").role("finance");
+        let carol = Actor::new("carol").role("finance");
+        let details = || RefundDetails::new("AA22BB33CC", 2_500.0, "Service not offered");
+
+        let sent = workflow.propose(&alice, details()).await.unwrap();
+        let other = workflow.propose(&alice, details()).await.unwrap();
+
+        let events = Mutex::new(Vec::new());
+        let approve = async {
+            let result = workflow.approve(&sent.id, &bob, None).await;
+            events.lock().unwrap().push("sent");
+            result
+        };
+        // Decides while the refund request is held by the transport, which
+        // only answers once the decisions are made
+        let decide = async {
+            in_flight.next().await;
+            let sending = workflow.reject(&sent.id, &carol, None).await;
+            let rejected = workflow.reject(&other.id, &carol, None).await;
+            events.lock().unwrap().push("decided");
+            drop(held);
+            (sending, rejected)
+        };
+        let both = futures::future::join(approve, decide);
+        let timeout = futures_timer::Delay::new(std::time::Duration::from_secs(10));
+        let (sent, (sending, rejected)) =
+            match futures::future::select(Box::pin(both), timeout).await {
+                futures::future::Either::Left((results, _)) => results,
+                futures::future::Either::Right(_) => panic!("the decisions waited for the refund"),
+            };
+
+        assert_eq!(*events.lock().unwrap(), ["decided", "sent"]);
+        assert_eq!(sent.unwrap().status, ProposalStatus::Sent);
+        assert!(matches!(
+            sending,
+            Err(PesaPalError::RefundWorkflowError(
+                RefundWorkflowError::NotPending {
+                    status: ProposalStatus::Sending
+                }
+            ))
+        ));
+        assert_eq!(rejected.unwrap().status, ProposalStatus::Rejected);
+        assert_eq!(transport.requests(REFUND_REQUEST_URL).len(), 1);
+    }
+
+    /// Store whose saves fail from the `fail_from`-th one on
+    #[derive(Debug, Default)]
+    struct FailingStore {
+        inner: InMemoryRefundStore,
+        saves: std::sync::atomic::AtomicUsize,
+        fail_from: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl RefundStore for Arc<FailingStore> {
+        async fn save(&self, proposal: &RefundProposal) -> PesaPalResult<()> {
+            use std::sync::atomic::Ordering;
+
+            let save = self.saves.fetch_add(1, Ordering::SeqCst) + 1;
+            if save >= self.fail_from.load(Ordering::SeqCst) {
+                return Err(RefundWorkflowError::Storage("database is down".to_string()).into());
+            }
+            self.inner.save(proposal).await
+        }
+
+        async fn load(&self, id: &str) -> PesaPalResult<Option<RefundProposal>> {
+            self.inner.load(id).await
+        }
+
+        async fn record(&self, event: &AuditEvent) -> PesaPalResult<()> {
+            self.inner.record(event).await
+        }
+
+        async fn audit_trail(&self, id: &str) -> PesaPalResult<Vec<AuditEvent>> {
+            self.inner.audit_trail(id).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unsaved_refund_result_is_returned_and_resolved() {
+        use std::sync::atomic::Ordering;
+
+        let transport = Arc::new(MockTransport::new().on(
+            REFUND_REQUEST_URL,
+            200,
+            r#"{"status":"200","message":"Refund request successfully"}"#,
+        ));
+        let store = Arc::new(FailingStore::default());
+        store.fail_from.store(2, Ordering::SeqCst);
+        let workflow = RefundWorkflow::new(
+            PesaPal::new_with_transport(
+                "refund-workflow-key",
+                "secret",
+                Environment::Sandbox,
+                Arc::clone(&transport),
+            ),
+            ThresholdPolicy::new(1_000.0),
+            Arc::clone(&store),
+        );
+
+        let proposal = workflow
+            .propose(
+                &Actor::new("alice"),
+                RefundDetails::new("AA22BB33CC", 500.0, "Service not offered"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Sent);
+        assert_eq!(proposal.outcome, Some(RefundOutcome::Accepted));
+        assert!(proposal.unrecorded.unwrap().contains("database is down"));
+        assert_eq!(transport.requests(REFUND_REQUEST_URL).len(), 1);
+
+        store.fail_from.store(usize::MAX, Ordering::SeqCst);
+        let ops = Actor::new("ops");
+        let stuck = workflow.proposal(&proposal.id).await.unwrap();
+        assert_eq!(stuck.status, ProposalStatus::Sending);
+        assert!(matches!(
+            workflow
+                .resolve(&proposal.id, &ops, ProposalStatus::Pending, None)
+                .await,
+            Err(PesaPalError::RefundWorkflowError(
+                RefundWorkflowError::InvalidResolution(ProposalStatus::Pending)
+            ))
+        ));
+
+        let resolved = workflow
+            .resolve(
+                &proposal.id,
+                &ops,
+                ProposalStatus::Sent,
+                Some("refund found at PesaPal".to_string()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resolved.status, ProposalStatus::Sent);
+        assert_eq!(
+            workflow.proposal(&proposal.id).await.unwrap().status,
+            ProposalStatus::Sent
+        );
+        assert_eq!(
+            workflow
+                .audit_trail(&proposal.id)
+                .await
+                .unwrap()
+                .last()
+                .map(|event| (event.action, event.actor.as_str())),
+            Some((AuditAction::Resolved, "ops"))
+        );
+        assert!(matches!(
+            workflow
+                .resolve(&proposal.id, &ops, ProposalStatus::Failed, None)
+                .await,
+            Err(PesaPalError::RefundWorkflowError(
+                RefundWorkflowError::NotSending {
+                    status: ProposalStatus::Sent
+                }
+            ))
+        ));
+        assert_eq!(transport.requests(REFUND_REQUEST_URL).len(), 1);
+    }
+}
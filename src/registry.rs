@@ -0,0 +1,270 @@
+//! Clients of several merchants, looked up by merchant id
+//!
+//! Each merchant has its own [`PesaPal`] client, hence its own credentials,
+//! [`OrderDefaults`](crate::OrderDefaults) (IPN id, currency, callback URLs)
+//! and access token. Merchants can be added and removed while the registry is
+//! in use.
+//!
+//! ```rust,ignore
+//! use pesapal::{OrderDefaults, PesaPal, PesaPalRegistry};
+//!
+//! let registry = PesaPalRegistry::new();
+//! registry.insert(
+//!     "shop-a",
+//!     PesaPal::new(key_a, secret_a, Environment::Production)
+//!         .with_order_defaults(OrderDefaults::new().currency("KES").notification_id(ipn_a)),
+//! ).await;
+//! registry.insert_config("shop-b", PesaPalConfig::from_file("shop-b.toml")?).await?;
+//!
+//! let response = registry
+//!     .merchant("shop-a")?
+//!     .submit_order()
+//!     .amount(2500)
+//!     .description("Order 42")
+//!     .billing_address(billing_address)
+//!     .build()?
+//!     .send()
+//!     .await?;
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::error::{PesaPalError, PesaPalResult};
+use crate::pesapal::PesaPal;
+
+/// Registry of the [`PesaPal`] clients of several merchants
+///
+/// The registry is cheap to clone, all the clones share the same merchants.
+#[derive(Debug, Clone, Default)]
+pub struct PesaPalRegistry {
+    merchants: Arc<RwLock<HashMap<String, PesaPal>>>,
+}
+
+impl PesaPalRegistry {
+    /// Creates an empty registry
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the merchant, returning the client it replaces if any
+    ///
+    /// The cached access token of the replaced client is dropped, e.g. after
+    /// its secret was rotated. Requests already sent by the replaced client
+    /// are not cancelled.
+    pub async fn insert(&self, merchant_id: impl Into<String>, client: PesaPal) -> Option<PesaPal> {
+        let replaced = self.write().insert(merchant_id.into(), client)?;
+        replaced.evict_token().await;
+        Some(replaced)
+    }
+
+    /// Adds the merchant with a client built from the configuration, see
+    /// [`PesaPal::from_config`]
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ReqwestError`] if the HTTP client can't be built
+    #[cfg(feature = "reqwest")]
+    pub async fn insert_config(
+        &self,
+        merchant_id: impl Into<String>,
+        config: crate::PesaPalConfig,
+    ) -> PesaPalResult<Option<PesaPal>> {
+        Ok(self
+            .insert(merchant_id, PesaPal::from_config(config)?)
+            .await)
+    }
+
+    /// Removes the merchant and drops its cached access token
+    pub async fn remove(&self, merchant_id: &str) -> Option<PesaPal> {
+        let client = self.write().remove(merchant_id)?;
+        client.evict_token().await;
+        Some(client)
+    }
+
+    /// Client of the merchant, to send its requests
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::UnknownMerchant`] if the merchant isn't registered
+    pub fn merchant(&self, merchant_id: &str) -> PesaPalResult<PesaPal> {
+        self.read()
+            .get(merchant_id)
+            .cloned()
+            .ok_or_else(|| PesaPalError::UnknownMerchant(merchant_id.to_string()))
+    }
+
+    /// Whether the merchant is registered
+    #[must_use]
+    pub fn contains(&self, merchant_id: &str) -> bool {
+        self.read().contains_key(merchant_id)
+    }
+
+    /// Ids of the registered merchants, in no particular order
+    #[must_use]
+    pub fn merchant_ids(&self) -> Vec<String> {
+        self.read().keys().cloned().collect()
+    }
+
+    /// Number of registered merchants
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Whether no merchant is registered
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, PesaPal>> {
+        self.merchants
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, PesaPal>> {
+        self.merchants
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cached::Cached;
+
+    use super::*;
+    use crate::pesapal::auth::AUTH_CACHE;
+    use crate::transport::mock::MockTransport;
+    use crate::{BillingAddress, Environment, OrderDefaults};
+
+    const AUTH_URL: &str = "api/Auth/RequestToken";
+    const SUBMIT_ORDER_URL: &str = "api/Transactions/SubmitOrderRequest";
+
+    fn merchant(token: &str) -> Arc<MockTransport> {
+        Arc::new(
+            MockTransport::default()
+                .on(
+                    AUTH_URL,
+                    200,
+                    &format!(r#"{{"token":"{token}","expiryDate":"2030-01-01T00:00:00.000Z","error":null,"status":"200","message":"success"}}"#),
+                )
+                .on(
+                    SUBMIT_ORDER_URL,
+                    200,
+                    r#"{"order_tracking_id":"tracking","merchant_reference":"reference","redirect_url":"https://example.com","error":null,"status":"200"}"#,
+                ),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_dispatched_by_merchant() {
+        let registry = PesaPalRegistry::new();
+        let (shop_a, shop_b) = (merchant("token-a"), merchant("token-b"));
+        registry
+            .insert(
+                "shop-a",
+                PesaPal::new_with_transport(
+                    "registry-key-a",
+                    "secret",
+                    Environment::Sandbox,
+                    Arc::clone(&shop_a),
+                )
+                .with_order_defaults(
+                    OrderDefaults::new()
+                        .currency("kes")
+                        .callback_url("https://a.example.com/callback")
+                        .notification_id("ipn-a"),
+                ),
+            )
+            .await;
+        registry
+            .insert(
+                "shop-b",
+                PesaPal::new_with_transport(
+                    "registry-key-b",
+                    "secret",
+                    Environment::Sandbox,
+                    Arc::clone(&shop_b),
+                )
+                .with_order_defaults(
+                    OrderDefaults::new()
+                        .currency("UGX")
+                        .callback_url("https://b.example.com/callback")
+                        .notification_id("ipn-b"),
+                ),
+            )
+            .await;
+
+        for merchant_id in ["shop-a", "shop-b"] {
+            registry
+                .merchant(merchant_id)
+                .unwrap()
+                .submit_order()
+                .amount(100)
+                .description("order")
+                .billing_address(BillingAddress {
+                    email_address: Some("customer@example.com".to_string()),
+                    ..Default::default()
+                })
+                .build()
+                .unwrap()
+                .send()
+                .await
+                .unwrap();
+        }
+
+        for (transport, token, ipn_id, currency) in [
+            (&shop_a, "token-a", "ipn-a", "KES"),
+            (&shop_b, "token-b", "ipn-b", "UGX"),
+        ] {
+            let request = &transport.requests(SUBMIT_ORDER_URL)[0];
+            let body: serde_json::Value =
+                serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+            assert_eq!(
+                request.headers[http::header::AUTHORIZATION],
+                format!("Bearer {token}").as_str()
+            );
+            assert_eq!(body["notification_id"], ipn_id);
+            assert_eq!(body["currency"], currency);
+        }
+
+        let removed = registry.remove("shop-a").await.unwrap();
+        assert!(AUTH_CACHE
+            .lock()
+            .await
+            .cache_get(&removed.token_cache_key())
+            .is_none());
+        assert!(matches!(
+            registry.merchant("shop-a"),
+            Err(PesaPalError::UnknownMerchant(id)) if id == "shop-a"
+        ));
+        assert_eq!(registry.merchant_ids(), ["shop-b"]);
+    }
+
+    #[tokio::test]
+    async fn test_replacing_a_merchant_drops_its_token() {
+        let registry = PesaPalRegistry::new();
+        let (old, new) = (merchant("old-token"), merchant("new-token"));
+        let client = |transport: &Arc<MockTransport>| {
+            PesaPal::new_with_transport(
+                "registry-rotated-key",
+                "secret",
+                Environment::Sandbox,
+                Arc::clone(transport),
+            )
+        };
+
+        registry.insert("shop", client(&old)).await;
+        let token = registry.merchant("shop").unwrap().authenticate().await;
+        assert_eq!(token.unwrap().expose_secret(), "old-token");
+
+        assert!(registry.insert("shop", client(&new)).await.is_some());
+        let token = registry.merchant("shop").unwrap().authenticate().await;
+        assert_eq!(token.unwrap().expose_secret(), "new-token");
+        assert_eq!(new.requests(AUTH_URL).len(), 1);
+    }
+}
@@ -0,0 +1,108 @@
+//! Response envelope
+//!
+//! Every endpoint exposes a `send_with_meta` method which returns a
+//! [`PesaPalResponse`], this contains the deserialized body together with the
+//! HTTP metadata of the call. This is useful when raising support tickets with
+//! `PesaPal` as they usually ask for the status, headers and raw payload.
+//!
+//! The same metadata is kept as a [`ResponseMeta`] by the errors returned
+//! once `PesaPal` responded, see
+//! [`PesaPalError::response_meta`](crate::PesaPalError::response_meta).
+
+use std::time::Duration;
+
+use http::{HeaderMap, StatusCode};
+
+/// Header which carries the client generated request id
+pub(crate) const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Typed response together with the HTTP metadata of the request
+#[derive(Debug, Clone)]
+pub struct PesaPalResponse<T> {
+    /// Deserialized response body
+    pub body: T,
+    /// HTTP status code returned by `PesaPal`
+    pub status: StatusCode,
+    /// HTTP headers returned by `PesaPal`
+    pub headers: HeaderMap,
+    /// Time taken from sending the request to reading the whole body
+    pub elapsed: Duration,
+    /// Time spent waiting for the client side rate limiter before sending
+    pub rate_limit_wait: Duration,
+    /// Unique id generated for the request, sent as the `X-Request-Id` header
+    pub request_id: String,
+    /// Raw JSON body as returned by `PesaPal`
+    pub raw_body: String,
+}
+
+impl<T> PesaPalResponse<T> {
+    /// Consumes the envelope and returns the deserialized body
+    pub fn into_inner(self) -> T {
+        self.body
+    }
+
+    /// Maps the body of the response, keeping the metadata as is
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> PesaPalResponse<U> {
+        PesaPalResponse {
+            body: f(self.body),
+            status: self.status,
+            headers: self.headers,
+            elapsed: self.elapsed,
+            rate_limit_wait: self.rate_limit_wait,
+            request_id: self.request_id,
+            raw_body: self.raw_body,
+        }
+    }
+}
+
+/// HTTP metadata of a call which `PesaPal` answered with an error or with a
+/// body which couldn't be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseMeta {
+    /// HTTP status code returned by `PesaPal`
+    pub status: StatusCode,
+    /// HTTP headers returned by `PesaPal`
+    pub headers: HeaderMap,
+    /// Time taken from sending the request to reading the whole body
+    pub elapsed: Duration,
+    /// Time spent waiting for the client side rate limiter before sending
+    pub rate_limit_wait: Duration,
+    /// Unique id generated for the request, sent as the `X-Request-Id` header
+    pub request_id: String,
+    /// Raw body as returned by `PesaPal`, invalid UTF-8 is replaced
+    pub raw_body: String,
+}
+
+impl<T> std::ops::Deref for PesaPalResponse<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_keeps_metadata() {
+        let response = PesaPalResponse {
+            body: 1_u8,
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            elapsed: Duration::from_millis(20),
+            rate_limit_wait: Duration::from_millis(5),
+            request_id: "01H8".to_string(),
+            raw_body: "1".to_string(),
+        };
+
+        let mapped = response.map(|body| body.to_string());
+
+        assert_eq!(mapped.body, "1");
+        assert_eq!(mapped.status, StatusCode::OK);
+        assert_eq!(mapped.request_id, "01H8");
+        assert_eq!(mapped.elapsed, Duration::from_millis(20));
+        assert_eq!(mapped.rate_limit_wait, Duration::from_millis(5));
+    }
+}
@@ -0,0 +1,363 @@
+//! Helpers for the optional `tracing` and `metrics` instrumentation
+//!
+//! When the respective feature is disabled every helper compiles down to a
+//! no-op, so call sites don't need to be feature gated.
+//!
+//! Secrets and customer contact details are never recorded. Consumer keys are
+//! masked, only their last four characters are kept to tell merchants apart.
+
+use std::time::Duration;
+
+use http::StatusCode;
+
+use crate::{CircuitState, Endpoint, Environment, PesaPalError};
+
+/// Counter of requests sent to `PesaPal`
+#[cfg(feature = "metrics")]
+pub(crate) const REQUESTS_TOTAL: &str = "pesapal_requests_total";
+/// Histogram of the request latency in seconds
+#[cfg(feature = "metrics")]
+pub(crate) const REQUEST_DURATION_SECONDS: &str = "pesapal_request_duration_seconds";
+/// Counter of access token refreshes
+#[cfg(feature = "metrics")]
+pub(crate) const TOKEN_REFRESHES_TOTAL: &str = "pesapal_token_refreshes_total";
+/// Counter of access token cache hits
+#[cfg(feature = "metrics")]
+pub(crate) const TOKEN_CACHE_HITS_TOTAL: &str = "pesapal_token_cache_hits_total";
+/// Counter of access token cache misses
+#[cfg(feature = "metrics")]
+pub(crate) const TOKEN_CACHE_MISSES_TOTAL: &str = "pesapal_token_cache_misses_total";
+/// Gauge of the age in seconds of the access token in use
+#[cfg(feature = "metrics")]
+pub(crate) const TOKEN_AGE_SECONDS: &str = "pesapal_token_age_seconds";
+/// Histogram of the time spent waiting for the rate limiter in seconds
+#[cfg(feature = "metrics")]
+pub(crate) const RATE_LIMIT_WAIT_SECONDS: &str = "pesapal_rate_limit_wait_seconds";
+/// Counter of circuit breaker state changes
+#[cfg(feature = "metrics")]
+pub(crate) const CIRCUIT_TRANSITIONS_TOTAL: &str = "pesapal_circuit_transitions_total";
+
+/// Records the outcome and latency of a call to an endpoint
+#[cfg(feature = "metrics")]
+pub(crate) fn observe_request(
+    endpoint: Endpoint,
+    env: &Environment,
+    elapsed: Duration,
+    error: Option<&PesaPalError>,
+) {
+    let (outcome, error_class) = match error {
+        Some(error) => ("error", error.class()),
+        None => ("success", "none"),
+    };
+    let labels = [
+        ("endpoint", endpoint.as_str()),
+        ("environment", env.as_str()),
+        ("outcome", outcome),
+        ("error_class", error_class),
+    ];
+
+    metrics::counter!(REQUESTS_TOTAL, &labels).increment(1);
+    metrics::histogram!(REQUEST_DURATION_SECONDS, &labels[..3]).record(elapsed.as_secs_f64());
+
+    if endpoint == Endpoint::Auth {
+        metrics::counter!(TOKEN_REFRESHES_TOTAL, &labels[1..]).increment(1);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn observe_request(
+    _endpoint: Endpoint,
+    _env: &Environment,
+    _elapsed: Duration,
+    _error: Option<&PesaPalError>,
+) {
+}
+
+/// Records a hit on the access token cache along with the token age
+#[cfg(feature = "metrics")]
+pub(crate) fn observe_token_cache_hit(env: &Environment, age: Duration) {
+    let labels = [("environment", env.as_str())];
+    metrics::counter!(TOKEN_CACHE_HITS_TOTAL, &labels).increment(1);
+    metrics::gauge!(TOKEN_AGE_SECONDS, &labels).set(age.as_secs_f64());
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn observe_token_cache_hit(_env: &Environment, _age: Duration) {}
+
+/// Records a miss on the access token cache
+#[cfg(feature = "metrics")]
+pub(crate) fn observe_token_cache_miss(env: &Environment) {
+    let labels = [("environment", env.as_str())];
+    metrics::counter!(TOKEN_CACHE_MISSES_TOTAL, &labels).increment(1);
+    metrics::gauge!(TOKEN_AGE_SECONDS, &labels).set(0.0);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn observe_token_cache_miss(_env: &Environment) {}
+
+/// Records the time a request waited for the rate limiter
+pub(crate) fn observe_rate_limit_wait(endpoint: Endpoint, env: &Environment, wait: Duration) {
+    #[cfg(feature = "metrics")]
+    metrics::histogram!(
+        RATE_LIMIT_WAIT_SECONDS,
+        "endpoint" => endpoint.as_str(),
+        "environment" => env.as_str()
+    )
+    .record(wait.as_secs_f64());
+
+    #[cfg(feature = "tracing")]
+    if !wait.is_zero() {
+        tracing::debug!(
+            endpoint = endpoint.as_str(),
+            environment = env.as_str(),
+            rate_limit_wait_ms = u64::try_from(wait.as_millis()).unwrap_or(u64::MAX),
+            "waited for the rate limiter"
+        );
+    }
+
+    #[cfg(not(any(feature = "metrics", feature = "tracing")))]
+    let _ = (endpoint, env, wait);
+}
+
+/// Records a state change of the circuit breaker
+pub(crate) fn observe_circuit_state(from: CircuitState, to: CircuitState) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(
+        CIRCUIT_TRANSITIONS_TOTAL,
+        "from" => from.as_str(),
+        "to" => to.as_str()
+    )
+    .increment(1);
+
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        from = from.as_str(),
+        to = to.as_str(),
+        "pesapal circuit breaker changed state"
+    );
+
+    #[cfg(not(any(feature = "metrics", feature = "tracing")))]
+    let _ = (from, to);
+}
+
+/// Records the HTTP status and the latency of a call on the current span
+#[cfg(feature = "tracing")]
+pub(crate) fn record_response(status: StatusCode, elapsed: Duration) {
+    let latency_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+    let span = tracing::Span::current();
+    span.record("http.status", status.as_u16());
+    span.record("latency_ms", latency_ms);
+
+    tracing::debug!(
+        http.status = status.as_u16(),
+        latency_ms,
+        "received response from pesapal"
+    );
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record_response(_status: StatusCode, _elapsed: Duration) {}
+
+/// Records a plain field on the current span
+#[cfg(feature = "tracing")]
+pub(crate) fn record(field: &'static str, value: &str) {
+    tracing::Span::current().record(field, value);
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record(_field: &'static str, _value: &str) {}
+
+/// Masks all but the last four characters of a value, for the spans and the
+/// `Debug` output of the client
+pub(crate) fn mask(value: &str) -> String {
+    let visible = value.chars().count().saturating_sub(4);
+    let last: String = value.chars().skip(visible).collect();
+    format!("****{last}")
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "tracing", feature = "metrics"))]
+    use super::*;
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_mask_keeps_last_characters() {
+        assert_eq!(mask("qkio1BGGYAXTu2JOfm7XSXNruoZsrqEW"), "****rqEW");
+        assert_eq!(mask("abc"), "****abc");
+    }
+
+    /// Subscriber writing every span field and event to a string
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct Capture {
+        output: std::sync::Arc<std::sync::Mutex<String>>,
+        spans: std::sync::Arc<std::sync::Mutex<Vec<(u64, &'static tracing::Metadata<'static>)>>>,
+        entered: std::sync::Arc<std::sync::Mutex<Vec<u64>>>,
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::field::Visit for Capture {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            use std::fmt::Write;
+
+            let mut output = self.output.lock().unwrap();
+            write!(output, "{}={value:?} ", field.name()).unwrap();
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for Capture {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            span.record(&mut self.clone());
+            let mut spans = self.spans.lock().unwrap();
+            let id = spans.len() as u64 + 1;
+            spans.push((id, span.metadata()));
+            tracing::span::Id::from_u64(id)
+        }
+
+        fn record(&self, _: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            values.record(&mut self.clone());
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            event.record(&mut self.clone());
+        }
+
+        fn enter(&self, span: &tracing::span::Id) {
+            self.entered.lock().unwrap().push(span.into_u64());
+        }
+
+        fn exit(&self, _: &tracing::span::Id) {
+            self.entered.lock().unwrap().pop();
+        }
+
+        fn current_span(&self) -> tracing_core::span::Current {
+            let entered = self.entered.lock().unwrap();
+            let spans = self.spans.lock().unwrap();
+            entered
+                .last()
+                .and_then(|id| spans.iter().find(|(span, _)| span == id))
+                .map_or_else(tracing_core::span::Current::none, |(id, metadata)| {
+                    tracing_core::span::Current::new(tracing::span::Id::from_u64(*id), metadata)
+                })
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn test_contact_details_never_reach_the_subscriber() {
+        use crate::transport::mock::MockTransport;
+        use crate::{BillingAddress, PesaPal, PhoneNumber};
+
+        let capture = Capture::default();
+        let _guard = tracing::subscriber::set_default(capture.clone());
+
+        let client = PesaPal::new_with_transport(
+            "telemetry-consumer-key",
+            "secret",
+            Environment::Sandbox,
+            MockTransport::new().on(
+                "api/Transactions/SubmitOrderRequest",
+                200,
+                r#"{"order_tracking_id":"tracking","merchant_reference":"order-42","redirect_url":"https://example.com","error":null,"status":"200"}"#,
+            ),
+        );
+        client
+            .submit_order()
+            .merchant_reference("order-42")
+            .currency("KES")
+            .amount(100)
+            .description("order")
+            .callback_url("https://example.com/callback")
+            .notification_id("ipn")
+            .billing_address(BillingAddress {
+                email_address: Some("jane.doe@example.com".to_string()),
+                phone_number: Some(PhoneNumber::parse("+254712345678").unwrap()),
+                ..Default::default()
+            })
+            .build()
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+
+        let output = capture.output.lock().unwrap().clone();
+        assert!(
+            output.contains(r#"merchant_reference="order-42""#),
+            "{output}"
+        );
+        assert!(output.contains("consumer_key=****-key"), "{output}");
+        for leaked in ["jane.doe", "712345678", "telemetry-consumer"] {
+            assert!(!output.contains(leaked), "{leaked} in {output}");
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_observe_request_labels() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            observe_request(
+                Endpoint::SubmitOrder,
+                &Environment::Sandbox,
+                Duration::from_millis(250),
+                None,
+            );
+            observe_request(
+                Endpoint::Refund,
+                &Environment::Sandbox,
+                Duration::from_millis(100),
+                Some(&PesaPalError::RefundError(
+                    crate::pesapal::refund::RefundRejection {
+                        status: 500,
+                        message: "rejected".to_string(),
+                        meta: None,
+                    },
+                )),
+            );
+            observe_token_cache_hit(&Environment::Sandbox, Duration::from_secs(42));
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let find = |name: &str, endpoint: Option<&str>| {
+            snapshot
+                .iter()
+                .find(|(key, ..)| {
+                    key.key().name() == name
+                        && endpoint.is_none_or(|endpoint| {
+                            key.key()
+                                .labels()
+                                .any(|l| l.key() == "endpoint" && l.value() == endpoint)
+                        })
+                })
+                .map(|(key, _, _, value)| (key.key(), value))
+                .unwrap()
+        };
+
+        let (key, value) = find(REQUESTS_TOTAL, Some("refund"));
+        assert_eq!(value, &DebugValue::Counter(1));
+        assert!(key
+            .labels()
+            .any(|l| l.key() == "error_class" && l.value() == "refund"));
+        assert!(key
+            .labels()
+            .any(|l| l.key() == "outcome" && l.value() == "error"));
+
+        let (_, value) = find(REQUEST_DURATION_SECONDS, Some("submit_order"));
+        assert!(matches!(value, DebugValue::Histogram(v) if v.len() == 1));
+
+        let (_, value) = find(TOKEN_AGE_SECONDS, None);
+        assert!(matches!(value, DebugValue::Gauge(v) if v.into_inner() == 42.0));
+    }
+}
@@ -0,0 +1,324 @@
+//! Pluggable HTTP transport
+//!
+//! All the requests made by the [`PesaPal`](crate::PesaPal) client go through
+//! an [`HttpTransport`]. By default the client uses [`ReqwestTransport`], but
+//! any other HTTP client (e.g. one with mTLS egress, or a fake in tests) can be
+//! plugged in with [`PesaPal::new_with_transport`](crate::PesaPal::new_with_transport).
+//!
+//! ```rust,ignore
+//! use pesapal::transport::{HttpRequest, HttpResponse, HttpTransport};
+//! use pesapal::{Environment, PesaPal, PesaPalError, PesaPalResult};
+//!
+//! #[derive(Debug)]
+//! struct HyperTransport { /* .. */ }
+//!
+//! #[async_trait::async_trait]
+//! impl HttpTransport for HyperTransport {
+//!     async fn send(&self, request: HttpRequest) -> PesaPalResult<HttpResponse> {
+//!         // send the request with your own client
+//!     }
+//! }
+//!
+//! let pesapal = PesaPal::new_with_transport(
+//!     "consumer_key",
+//!     "consumer_secret",
+//!     Environment::Sandbox,
+//!     HyperTransport { /* .. */ },
+//! );
+//! ```
+
+use http::header::{HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use http::{HeaderMap, Method, StatusCode};
+use serde::Serialize;
+use url::Url;
+
+use crate::{PesaPalError, PesaPalResult};
+
+/// Description of an HTTP request made to `PesaPal`
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    /// HTTP method of the request
+    pub method: Method,
+    /// Full URL of the request, including the query string
+    pub url: Url,
+    /// HTTP headers of the request
+    pub headers: HeaderMap,
+    /// JSON encoded body of the request, if any
+    pub body: Option<Vec<u8>>,
+}
+
+impl HttpRequest {
+    /// Creates a new request with the `Accept: application/json` header
+    ///
+    /// # Errors
+    /// [`PesaPalError::Internal`] - Incase the url is invalid
+    pub fn new(method: Method, url: &str) -> PesaPalResult<Self> {
+        let url = Url::parse(url).map_err(|e| PesaPalError::Internal(e.to_string()))?;
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+        Ok(Self {
+            method,
+            url,
+            headers,
+            body: None,
+        })
+    }
+
+    /// Sets the `Authorization` header with the bearer token
+    ///
+    /// # Errors
+    /// [`PesaPalError::Internal`] - Incase the token is not a valid header value
+    pub fn bearer_auth(mut self, token: &str) -> PesaPalResult<Self> {
+        let mut value = HeaderValue::from_str(&format!("Bearer {token}"))
+            .map_err(|e| PesaPalError::Internal(e.to_string()))?;
+        value.set_sensitive(true);
+        self.headers.insert(AUTHORIZATION, value);
+
+        Ok(self)
+    }
+
+    /// Appends the key value pair to the query string
+    #[must_use]
+    pub fn query(mut self, key: &str, value: &str) -> Self {
+        self.url.query_pairs_mut().append_pair(key, value);
+        self
+    }
+
+    /// Serializes the payload as the JSON body of the request
+    ///
+    /// # Errors
+    /// [`PesaPalError::Internal`] - Incase the payload can't be serialized
+    pub fn json<T: Serialize + ?Sized>(mut self, payload: &T) -> PesaPalResult<Self> {
+        self.body = Some(serde_json::to_vec(payload)?);
+        self.headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        Ok(self)
+    }
+}
+
+/// HTTP response returned by an [`HttpTransport`]
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    /// HTTP status code
+    pub status: StatusCode,
+    /// HTTP headers
+    pub headers: HeaderMap,
+    /// Raw body
+    pub body: Vec<u8>,
+}
+
+/// Transport used by the [`PesaPal`](crate::PesaPal) client to send requests
+#[async_trait::async_trait]
+pub trait HttpTransport: std::fmt::Debug + Send + Sync {
+    /// Sends the request and returns the response
+    ///
+    /// Non success status codes must be returned as an [`HttpResponse`] as
+    /// `PesaPal` sends the error details in the body.
+    ///
+    /// # Errors
+    /// [`PesaPalError::TransportError`] - Incase the request couldn't be sent
+    async fn send(&self, request: HttpRequest) -> PesaPalResult<HttpResponse>;
+}
+
+#[async_trait::async_trait]
+impl<T: HttpTransport + ?Sized> HttpTransport for std::sync::Arc<T> {
+    async fn send(&self, request: HttpRequest) -> PesaPalResult<HttpResponse> {
+        (**self).send(request).await
+    }
+}
+
+/// Default [`HttpTransport`] backed by [`reqwest::Client`]
+#[cfg(feature = "reqwest")]
+#[derive(Debug, Clone)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "reqwest")]
+impl ReqwestTransport {
+    /// Wraps an existing [`reqwest::Client`]
+    #[must_use]
+    pub const fn from_client(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl Default for ReqwestTransport {
+    fn default() -> Self {
+        let client = reqwest::Client::builder()
+            .connect_timeout(std::time::Duration::from_secs(10))
+            .user_agent(format!("pesapal-rs @{}", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("Error building http client");
+
+        Self { client }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+#[async_trait::async_trait]
+impl HttpTransport for ReqwestTransport {
+    async fn send(&self, request: HttpRequest) -> PesaPalResult<HttpResponse> {
+        let mut builder = self
+            .client
+            .request(request.method, request.url)
+            .headers(request.headers);
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+
+        let response = builder.send().await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes().await?.to_vec();
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// In memory transport used to test the client without network access
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Returns canned responses based on the path of the request
+    ///
+    /// Responses registered for a path are returned in order, the last one is
+    /// repeated. Authentication requests always succeed.
+    #[derive(Debug, Default)]
+    pub(crate) struct MockTransport {
+        routes: Mutex<HashMap<String, Vec<HttpResponse>>>,
+        requests: Mutex<Vec<HttpRequest>>,
+        delay: Option<std::time::Duration>,
+        hold: Option<Hold>,
+    }
+
+    /// Gate the responses to a path wait for
+    #[derive(Debug)]
+    struct Hold {
+        path: String,
+        gate: Arc<futures::lock::Mutex<()>>,
+        arrived: futures::channel::mpsc::UnboundedSender<()>,
+    }
+
+    impl MockTransport {
+        pub(crate) fn new() -> Self {
+            Self::default().on(
+                "api/Auth/RequestToken",
+                200,
+                r#"{"token":"token","expiryDate":"2030-01-01T00:00:00.000Z","error":null,"status":"200","message":"success"}"#,
+            )
+        }
+
+        /// Registers a response for the requests made to the `path`
+        pub(crate) fn on(self, path: &str, status: u16, body: &str) -> Self {
+            self.routes
+                .lock()
+                .unwrap()
+                .entry(path.to_string())
+                .or_default()
+                .push(HttpResponse {
+                    status: StatusCode::from_u16(status).unwrap(),
+                    headers: HeaderMap::new(),
+                    body: body.as_bytes().to_vec(),
+                });
+            self
+        }
+
+        /// Delays every response, to keep the requests in flight for a while
+        pub(crate) fn delay(mut self, delay: std::time::Duration) -> Self {
+            self.delay = Some(delay);
+            self
+        }
+
+        /// Holds the responses to the `path` while `gate` is locked, sending
+        /// on `arrived` once a request is held
+        pub(crate) fn hold(
+            mut self,
+            path: &str,
+            gate: Arc<futures::lock::Mutex<()>>,
+            arrived: futures::channel::mpsc::UnboundedSender<()>,
+        ) -> Self {
+            self.hold = Some(Hold {
+                path: path.to_string(),
+                gate,
+                arrived,
+            });
+            self
+        }
+
+        /// Requests sent to the `path`
+        pub(crate) fn requests(&self, path: &str) -> Vec<HttpRequest> {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|request| request.url.path().ends_with(path))
+                .cloned()
+                .collect()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl HttpTransport for MockTransport {
+        async fn send(&self, request: HttpRequest) -> PesaPalResult<HttpResponse> {
+            self.requests.lock().unwrap().push(request.clone());
+            if let Some(delay) = self.delay {
+                futures_timer::Delay::new(delay).await;
+            }
+            if let Some(hold) = &self.hold {
+                if request.url.path().ends_with(hold.path.as_str()) {
+                    let _ = hold.arrived.unbounded_send(());
+                    drop(hold.gate.lock().await);
+                }
+            }
+
+            let mut routes = self.routes.lock().unwrap();
+            let responses = routes
+                .iter_mut()
+                .find(|(path, _)| request.url.path().ends_with(path.as_str()))
+                .map(|(_, responses)| responses)
+                .ok_or_else(|| PesaPalError::TransportError(request.url.to_string().into()))?;
+
+            if responses.len() > 1 {
+                return Ok(responses.remove(0));
+            }
+            Ok(responses[0].clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_http_request_helpers() {
+        let request = HttpRequest::new(Method::GET, "https://example.com/api")
+            .unwrap()
+            .query("OrderTrackingId", "a b&c")
+            .bearer_auth("token")
+            .unwrap()
+            .json(&serde_json::json!({"a": 1}))
+            .unwrap();
+
+        assert_eq!(
+            request.url.as_str(),
+            "https://example.com/api?OrderTrackingId=a+b%26c"
+        );
+        assert_eq!(request.headers[AUTHORIZATION], "Bearer token");
+        assert!(request.headers[AUTHORIZATION].is_sensitive());
+        assert_eq!(request.headers[CONTENT_TYPE], "application/json");
+        assert_eq!(request.body.unwrap(), br#"{"a":1}"#);
+    }
+}
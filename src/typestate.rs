@@ -0,0 +1,525 @@
+//! Builders which check the mandatory fields at compile time
+//!
+//! Each mandatory field is tracked in the type of the builder, as [`Unset`]
+//! until its setter is called and [`Set`] afterwards. `build`, `send` and
+//! `send_with_meta` only exist once every mandatory field is [`Set`], so a
+//! forgotten field is a compile error rather than an error returned by
+//! `build()`. The remaining checks, e.g. the
+//! [`ValidationErrors`](crate::ValidationErrors) of an order, are returned as
+//! a [`PesaPalError`](crate::PesaPalError).
+//!
+//! Like [`PesaPal::submit_order`], an order starts from the
+//! [`OrderDefaults`](crate::OrderDefaults) of the client, e.g. its
+//! cancellation URL, but every mandatory field is still set explicitly.
+//!
+//! ```no_run
+//! # async fn order(
+//! #     pesapal: pesapal::PesaPal,
+//! #     billing_address: pesapal::BillingAddress,
+//! # ) -> pesapal::PesaPalResult<()> {
+//! use pesapal::typestate::SubmitOrderBuilder;
+//!
+//! let response = SubmitOrderBuilder::new(&pesapal)
+//!     .currency("KES")
+//!     .amount(2500)
+//!     .description("Shopping")
+//!     .callback_url("https://example.com/callback")
+//!     .notification_id("ipn-id")
+//!     .billing_address(billing_address)
+//!     .send()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A builder missing a mandatory field has no `send` method:
+//!
+//! ```compile_fail
+//! # async fn order(pesapal: pesapal::PesaPal) {
+//! use pesapal::typestate::SubmitOrderBuilder;
+//!
+//! SubmitOrderBuilder::new(&pesapal)
+//!     .currency("KES")
+//!     .amount(2500)
+//!     .send()
+//!     .await;
+//! # }
+//! ```
+
+use std::marker::PhantomData;
+
+use crate::error::PesaPalResult;
+use crate::pesapal::billing_address::BillingAddress;
+use crate::pesapal::refund::{Refund, RefundPreflight, RefundResponse};
+use crate::pesapal::register_ipn::{NotificationType, RegisterIPN, RegisterIPNResponse};
+use crate::pesapal::submit_order::{RedirectMode, SubmitOrder, SubmitOrderResponse};
+use crate::pesapal::transaction_status::{TransactionStatus, TransactionStatusResponse};
+use crate::{PesaPal, PesaPalResponse};
+
+/// Mandatory field which hasn't been set yet
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Unset;
+
+/// Mandatory field which has been set
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Set;
+
+macro_rules! optional_setters {
+    ($($(#[$doc:meta])* $name:ident: $ty:ty),* $(,)?) => {
+        $(
+            $(#[$doc])*
+            pub fn $name(mut self, value: $ty) -> Self {
+                self.inner.$name(value);
+                self
+            }
+        )*
+    };
+}
+
+/// [`SubmitOrder`] builder whose mandatory fields are checked at compile time
+///
+/// The type parameters track, in order, the currency, amount, description,
+/// callback URL, notification id and billing address.
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct SubmitOrderBuilder<C = Unset, A = Unset, D = Unset, U = Unset, N = Unset, B = Unset> {
+    inner: crate::pesapal::submit_order::SubmitOrderBuilder,
+    state: PhantomData<(C, A, D, U, N, B)>,
+}
+
+impl SubmitOrderBuilder {
+    /// Creates a builder with no mandatory field set, prefilled with the
+    /// [`OrderDefaults`](crate::OrderDefaults) of the client
+    pub fn new(client: &PesaPal) -> Self {
+        Self {
+            inner: client.submit_order(),
+            state: PhantomData,
+        }
+    }
+}
+
+impl<C, A, D, U, N, B> SubmitOrderBuilder<C, A, D, U, N, B> {
+    fn into_state<C2, A2, D2, U2, N2, B2>(self) -> SubmitOrderBuilder<C2, A2, D2, U2, N2, B2> {
+        SubmitOrderBuilder {
+            inner: self.inner,
+            state: PhantomData,
+        }
+    }
+
+    /// Currency which is used to charge the customers
+    pub fn currency(
+        mut self,
+        currency: impl Into<String>,
+    ) -> SubmitOrderBuilder<Set, A, D, U, N, B> {
+        self.inner.currency(currency);
+        self.into_state()
+    }
+
+    /// Amount to be processed
+    pub fn amount(mut self, amount: u64) -> SubmitOrderBuilder<C, Set, D, U, N, B> {
+        self.inner.amount(amount);
+        self.into_state()
+    }
+
+    /// Description of the order
+    pub fn description(
+        mut self,
+        description: impl Into<String>,
+    ) -> SubmitOrderBuilder<C, A, Set, U, N, B> {
+        self.inner.description(description);
+        self.into_state()
+    }
+
+    /// URL which PesaPal will re-direct for the payment processing
+    pub fn callback_url(
+        mut self,
+        callback_url: impl Into<String>,
+    ) -> SubmitOrderBuilder<C, A, D, Set, N, B> {
+        self.inner.callback_url(callback_url);
+        self.into_state()
+    }
+
+    /// The IPN id which Pesapal will send notifications to
+    pub fn notification_id(
+        mut self,
+        notification_id: impl Into<String>,
+    ) -> SubmitOrderBuilder<C, A, D, U, Set, B> {
+        self.inner.notification_id(notification_id);
+        self.into_state()
+    }
+
+    /// The billing address of the customer
+    pub fn billing_address(
+        mut self,
+        billing_address: BillingAddress,
+    ) -> SubmitOrderBuilder<C, A, D, U, N, Set> {
+        self.inner.billing_address(billing_address);
+        self.into_state()
+    }
+
+    optional_setters! {
+        /// Unique merchant reference of the order, generated if not set
+        merchant_reference: impl Into<String>,
+        /// Where the callback URL will be loaded
+        redirect_mode: RedirectMode,
+        /// A valid URL which PesaPal will redirect client incase they cancel the payment
+        cancellation_url: impl Into<String>,
+        /// Store / branch to which this payment will be accredited to
+        branch: impl Into<String>,
+    }
+}
+
+impl SubmitOrderBuilder<Set, Set, Set, Set, Set, Set> {
+    /// Builds the [`SubmitOrder`]
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ValidationErrors`](crate::PesaPalError::ValidationErrors)
+    /// if a field breaks a rule of `PesaPal`
+    pub fn build(self) -> PesaPalResult<SubmitOrder> {
+        Ok(self.inner.build()?)
+    }
+
+    /// Builds and sends the order, see [`SubmitOrder::send`]
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ValidationErrors`](crate::PesaPalError::ValidationErrors)
+    /// if a field breaks a rule of `PesaPal`, otherwise the errors of
+    /// [`SubmitOrder::send`]
+    pub async fn send(self) -> PesaPalResult<SubmitOrderResponse> {
+        self.build()?.send().await
+    }
+
+    /// Builds and sends the order, see [`SubmitOrder::send_with_meta`]
+    ///
+    /// # Errors
+    ///
+    /// Same as [`SubmitOrderBuilder::send`]
+    pub async fn send_with_meta(self) -> PesaPalResult<PesaPalResponse<SubmitOrderResponse>> {
+        self.build()?.send_with_meta().await
+    }
+}
+
+/// [`Refund`] builder whose mandatory fields are checked at compile time
+///
+/// The type parameters track, in order, the confirmation code, amount,
+/// username and remarks.
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct RefundBuilder<C = Unset, A = Unset, U = Unset, R = Unset> {
+    inner: crate::pesapal::refund::RefundBuilder,
+    state: PhantomData<(C, A, U, R)>,
+}
+
+impl RefundBuilder {
+    /// Creates a builder with no field set
+    pub fn new(client: &PesaPal) -> Self {
+        Self {
+            inner: client.refund(),
+            state: PhantomData,
+        }
+    }
+}
+
+impl<C, A, U, R> RefundBuilder<C, A, U, R> {
+    fn into_state<C2, A2, U2, R2>(self) -> RefundBuilder<C2, A2, U2, R2> {
+        RefundBuilder {
+            inner: self.inner,
+            state: PhantomData,
+        }
+    }
+
+    /// Payment confirmation code returned by the payment processor
+    pub fn confirmation_code(
+        mut self,
+        confirmation_code: impl Into<String>,
+    ) -> RefundBuilder<Set, A, U, R> {
+        self.inner.confirmation_code(confirmation_code);
+        self.into_state()
+    }
+
+    /// Amount to be refunded
+    pub fn amount(mut self, amount: impl Into<f64>) -> RefundBuilder<C, Set, U, R> {
+        self.inner.amount(amount);
+        self.into_state()
+    }
+
+    /// Identity of the user who has initiated the refund
+    pub fn username(mut self, username: impl Into<String>) -> RefundBuilder<C, A, Set, R> {
+        self.inner.username(username);
+        self.into_state()
+    }
+
+    /// A brief description on the reason for the refund
+    pub fn remarks(mut self, remarks: impl Into<String>) -> RefundBuilder<C, A, U, Set> {
+        self.inner.remarks(remarks);
+        self.into_state()
+    }
+
+    optional_setters! {
+        /// Checks the refund against the original transaction before sending it
+        preflight: impl Into<RefundPreflight>,
+    }
+}
+
+impl RefundBuilder<Set, Set, Set, Set> {
+    /// Builds the [`Refund`]
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ValidationError`](crate::PesaPalError::ValidationError)
+    /// if the refund can't be built
+    pub fn build(self) -> PesaPalResult<Refund> {
+        Ok(self.inner.build()?)
+    }
+
+    /// Builds and sends the refund, see [`Refund::send`]
+    ///
+    /// # Errors
+    ///
+    /// The errors of [`Refund::send`]
+    pub async fn send(self) -> PesaPalResult<RefundResponse> {
+        self.build()?.send().await
+    }
+
+    /// Builds and sends the refund, see [`Refund::send_with_meta`]
+    ///
+    /// # Errors
+    ///
+    /// The errors of [`Refund::send_with_meta`]
+    pub async fn send_with_meta(self) -> PesaPalResult<PesaPalResponse<RefundResponse>> {
+        self.build()?.send_with_meta().await
+    }
+}
+
+/// [`RegisterIPN`] builder whose mandatory fields are checked at compile time
+///
+/// The type parameters track, in order, the URL and the notification type.
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct RegisterIPNBuilder<U = Unset, T = Unset> {
+    inner: crate::pesapal::register_ipn::RegisterIPNBuilder,
+    state: PhantomData<(U, T)>,
+}
+
+impl RegisterIPNBuilder {
+    /// Creates a builder with no field set
+    pub fn new(client: &PesaPal) -> Self {
+        Self {
+            inner: client.register_ipn_url(),
+            state: PhantomData,
+        }
+    }
+}
+
+impl<U, T> RegisterIPNBuilder<U, T> {
+    fn into_state<U2, T2>(self) -> RegisterIPNBuilder<U2, T2> {
+        RegisterIPNBuilder {
+            inner: self.inner,
+            state: PhantomData,
+        }
+    }
+
+    /// The notification URL Pesapal will send a status alert to
+    pub fn url(mut self, url: impl Into<String>) -> RegisterIPNBuilder<Set, T> {
+        self.inner.url(url);
+        self.into_state()
+    }
+
+    /// HTTP method Pesapal will call the URL with
+    pub fn ipn_notification_type(
+        mut self,
+        notification_type: NotificationType,
+    ) -> RegisterIPNBuilder<U, Set> {
+        self.inner.ipn_notification_type(notification_type);
+        self.into_state()
+    }
+}
+
+impl RegisterIPNBuilder<Set, Set> {
+    /// Builds the [`RegisterIPN`]
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ValidationError`](crate::PesaPalError::ValidationError)
+    /// if the registration can't be built
+    pub fn build(self) -> PesaPalResult<RegisterIPN> {
+        Ok(self.inner.build()?)
+    }
+
+    /// Builds and sends the registration, see [`RegisterIPN::send`]
+    ///
+    /// # Errors
+    ///
+    /// The errors of [`RegisterIPN::send`]
+    pub async fn send(self) -> PesaPalResult<RegisterIPNResponse> {
+        self.build()?.send().await
+    }
+
+    /// Builds and sends the registration, see [`RegisterIPN::send_with_meta`]
+    ///
+    /// # Errors
+    ///
+    /// The errors of [`RegisterIPN::send_with_meta`]
+    pub async fn send_with_meta(self) -> PesaPalResult<PesaPalResponse<RegisterIPNResponse>> {
+        self.build()?.send_with_meta().await
+    }
+}
+
+/// [`TransactionStatus`] builder whose order tracking id is checked at
+/// compile time
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct TransactionStatusBuilder<O = Unset> {
+    inner: crate::pesapal::transaction_status::TransactionStatusBuilder,
+    state: PhantomData<O>,
+}
+
+impl TransactionStatusBuilder {
+    /// Creates a builder with no field set
+    pub fn new(client: &PesaPal) -> Self {
+        Self {
+            inner: client.transaction_status(),
+            state: PhantomData,
+        }
+    }
+}
+
+impl<O> TransactionStatusBuilder<O> {
+    /// Unique order id generated by Pesapal
+    pub fn order_tracking_id(
+        mut self,
+        order_tracking_id: impl Into<String>,
+    ) -> TransactionStatusBuilder<Set> {
+        self.inner.order_tracking_id(order_tracking_id);
+        TransactionStatusBuilder {
+            inner: self.inner,
+            state: PhantomData,
+        }
+    }
+}
+
+impl TransactionStatusBuilder<Set> {
+    /// Builds the [`TransactionStatus`]
+    ///
+    /// # Errors
+    ///
+    /// [`PesaPalError::ValidationError`](crate::PesaPalError::ValidationError)
+    /// if the lookup can't be built
+    pub fn build(self) -> PesaPalResult<TransactionStatus> {
+        Ok(self.inner.build()?)
+    }
+
+    /// Builds and sends the lookup, see [`TransactionStatus::send`]
+    ///
+    /// # Errors
+    ///
+    /// The errors of [`TransactionStatus::send`]
+    pub async fn send(self) -> PesaPalResult<TransactionStatusResponse> {
+        self.build()?.send().await
+    }
+
+    /// Builds and sends the lookup, see [`TransactionStatus::send_with_meta`]
+    ///
+    /// # Errors
+    ///
+    /// The errors of [`TransactionStatus::send_with_meta`]
+    pub async fn send_with_meta(self) -> PesaPalResult<PesaPalResponse<TransactionStatusResponse>> {
+        self.build()?.send_with_meta().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::transport::mock::MockTransport;
+    use crate::{Environment, OrderDefaults, PesaPalError};
+
+    #[tokio::test]
+    async fn test_typestate_builders_send() {
+        let transport = Arc::new(
+            MockTransport::new()
+                .on(
+                    "api/Transactions/SubmitOrderRequest",
+                    200,
+                    r#"{"order_tracking_id":"tracking","merchant_reference":"reference","redirect_url":"https://example.com","error":null,"status":"200"}"#,
+                )
+                .on(
+                    "api/URLSetup/RegisterIPN",
+                    200,
+                    r#"{"url":"https://example.com/ipn","created_date":"2022-03-03T17:29:03.7208266Z","ipn_id":"ipn","error":null,"status":"200"}"#,
+                ),
+        );
+        let client = PesaPal::new_with_transport(
+            "typestate-key",
+            "secret",
+            Environment::Sandbox,
+            Arc::clone(&transport),
+        )
+        .with_order_defaults(OrderDefaults::new().cancellation_url("https://example.com/cancel"));
+
+        let ipn = RegisterIPNBuilder::new(&client)
+            .ipn_notification_type(NotificationType::Post)
+            .url("https://example.com/ipn")
+            .send()
+            .await
+            .unwrap();
+        let order = SubmitOrderBuilder::new(&client)
+            .currency("KES")
+            .amount(100)
+            .description("order")
+            .callback_url("https://example.com/callback")
+            .notification_id(ipn.ipn_id)
+            .billing_address(BillingAddress {
+                email_address: Some("customer@example.com".to_string()),
+                ..Default::default()
+            })
+            .branch("Nairobi")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(order.order_tracking_id, "tracking");
+        let request = &transport.requests("api/Transactions/SubmitOrderRequest")[0];
+        let body: serde_json::Value =
+            serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+        assert_eq!(body["notification_id"], "ipn");
+        assert_eq!(body["branch"], "Nairobi");
+        assert_eq!(body["cancellation_url"], "https://example.com/cancel");
+    }
+
+    #[test]
+    fn test_typestate_errors_are_pesapal_errors() {
+        let client = PesaPal::new_with_transport(
+            "typestate-key",
+            "secret",
+            Environment::Sandbox,
+            MockTransport::new(),
+        );
+
+        let order = SubmitOrderBuilder::new(&client)
+            .currency("KES")
+            .amount(0)
+            .description("order")
+            .callback_url("https://example.com/callback")
+            .notification_id("ipn")
+            .billing_address(BillingAddress::default())
+            .build();
+        assert!(matches!(
+            order,
+            Err(PesaPalError::ValidationErrors(errors)) if errors.len() == 2
+        ));
+
+        let status = TransactionStatusBuilder::new(&client)
+            .order_tracking_id("tracking")
+            .build()
+            .unwrap();
+        assert_eq!(status.order_tracking_id, "tracking");
+
+        let refund = RefundBuilder::new(&client).amount(100.0);
+        assert!(format!("{refund:?}").starts_with("RefundBuilder"));
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt;
+
+use derive_builder::UninitializedFieldError;
+
+/// Problem with the value of one field of a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    /// Path of the field in the request, e.g. `billing_address.email_address`
+    pub path: String,
+    /// Short, stable name of the broken rule, e.g. `too_long`
+    pub code: &'static str,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} : {}", self.path, self.message)
+    }
+}
+
+/// Every problem found in a request before it is sent
+///
+/// # Example
+/// ```
+/// use pesapal::ValidationErrors;
+///
+/// let mut errors = ValidationErrors::new();
+/// errors.add("amount", "zero", "amount must be greater than 0");
+/// assert_eq!(errors.field("amount").next().unwrap().code, "zero");
+/// assert_eq!(errors.to_string(), "amount : amount must be greater than 0");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationErrors {
+    errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    /// Creates an empty list
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a problem with the field at `path`
+    pub fn add(
+        &mut self,
+        path: impl Into<String>,
+        code: &'static str,
+        message: impl Into<String>,
+    ) -> &mut Self {
+        self.errors.push(FieldError {
+            path: path.into(),
+            code,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Records that the mandatory field at `path` is missing
+    pub fn required(&mut self, path: impl Into<String>) -> &mut Self {
+        let path = path.into();
+        let message = format!("{path} is required");
+        self.add(path, "required", message)
+    }
+
+    /// Problems found, in the order they were found
+    #[must_use]
+    pub fn errors(&self) -> &[FieldError] {
+        &self.errors
+    }
+
+    /// Problems with the field at `path` or any of its sub fields
+    pub fn field<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a FieldError> + 'a {
+        self.errors.iter().filter(move |error| {
+            error
+                .path
+                .strip_prefix(path)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+    }
+
+    /// Whether no problem was found
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Number of problems found
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
+    /// `Ok` if no problem was found
+    ///
+    /// # Errors
+    ///
+    /// The list itself if it isn't empty
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl IntoIterator for ValidationErrors {
+    type Item = FieldError;
+    type IntoIter = std::vec::IntoIter<FieldError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errors.into_iter()
+    }
+}
+
+impl From<UninitializedFieldError> for ValidationErrors {
+    fn from(value: UninitializedFieldError) -> Self {
+        let mut errors = Self::new();
+        errors.required(value.field_name());
+        errors
+    }
+}